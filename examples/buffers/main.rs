@@ -1,3 +1,7 @@
+#[path = "../diffuse_maps/mesh.rs"]
+mod mesh;
+#[path = "../diffuse_maps/primitives.rs"]
+mod primitives;
 mod scene;
 
 use iced_wgpu::{wgpu, Backend, Renderer, Settings, Viewport};
@@ -1,3 +1,5 @@
+use crate::mesh::{Indices, Mesh};
+use crate::primitives;
 use bytemuck;
 use iced_wgpu::wgpu;
 use iced_winit::Color;
@@ -7,39 +9,6 @@ use std::fs::File;
 use std::io::{Read, Write};
 use wgpu::{BindGroup, BindGroupLayout};
 
-const VERTICES: &[Vertex] = &[
-    Vertex {
-        position: [0.1, -0.8, 0.0],
-        color: [0.1, 0.2, 0.3],
-    },
-    Vertex {
-        position: [0.7, 0.6, 0.0],
-        color: [0.4, 0.5, 0.6],
-    },
-    Vertex {
-        position: [0.5, 0.6, 0.0],
-        color: [0.7, 0.8, 0.9],
-    },
-    Vertex {
-        position: [-0.0, -0.5, 0.0],
-        color: [0.1, 0.0, 1.0],
-    },
-    Vertex {
-        position: [-0.5, 0.6, 0.0],
-        color: [0.4, 0.3, 0.2],
-    },
-    Vertex {
-        position: [-0.7, 0.6, 0.0],
-        color: [0.6, 0.5, 0.4],
-    },
-    Vertex {
-        position: [-0.1, -0.8, 0.0],
-        color: [0.9, 0.8, 0.7],
-    },
-];
-
-const INDICES: &[u16] = &[0, 1, 2, 0, 2, 3, 0, 3, 6, 3, 4, 6, 4, 5, 6];
-
 unsafe impl bytemuck::Pod for Vertex {}
 unsafe impl bytemuck::Zeroable for Vertex {}
 
@@ -51,6 +20,18 @@ struct Vertex {
 }
 
 impl Vertex {
+    /// The mesh seen from above, flat on the screen and colored by its texture coordinates.
+    fn from_mesh(mesh: &Mesh) -> Vec<Vertex> {
+        mesh.positions
+            .iter()
+            .zip(&mesh.tex_coords)
+            .map(|(&[x, _, z], &[u, v])| Vertex {
+                position: [x, -z, 0.0],
+                color: [u, v, 1.0 - u],
+            })
+            .collect()
+    }
+
     fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
         use std::mem;
         wgpu::VertexBufferDescriptor {
@@ -99,26 +80,30 @@ impl Scene {
             "examples/buffers/shader/my_vert.spv",
             shaderc::ShaderKind::Vertex,
         );
+        let mesh = primitives::plane(1.4, 1.4, 4);
+        let vertices = Vertex::from_mesh(&mesh);
+        let indices = Indices::new(&mesh.indices, vertices.len());
+        let vertex_buffer = device
+            .create_buffer_with_data(bytemuck::cast_slice(&vertices), wgpu::BufferUsage::VERTEX);
+        let index_buffer =
+            device.create_buffer_with_data(indices.as_bytes(), wgpu::BufferUsage::INDEX);
         let pipeline = build_pipeline(
             device,
             BuildPipelineDescriptor {
                 frag_path: "examples/buffers/shader/my_frag.spv",
                 vert_path: "examples/buffers/shader/my_vert.spv",
                 bind_group_layout: &bind_group_layout,
+                index_format: indices.format(),
             },
         );
-        let vertex_buffer = device
-            .create_buffer_with_data(bytemuck::cast_slice(VERTICES), wgpu::BufferUsage::VERTEX);
-        let index_buffer =
-            device.create_buffer_with_data(bytemuck::cast_slice(INDICES), wgpu::BufferUsage::INDEX);
         Scene {
             background_color: Color::WHITE,
             pipeline,
             bind_group,
             vertex_buffer,
             index_buffer,
-            num_vertices: VERTICES.len() as u32,
-            num_indices: INDICES.len() as u32,
+            num_vertices: vertices.len() as u32,
+            num_indices: indices.len() as u32,
         }
     }
 
@@ -155,6 +140,7 @@ struct BuildPipelineDescriptor<'a> {
     frag_path: &'a str,
     vert_path: &'a str,
     bind_group_layout: &'a BindGroupLayout,
+    index_format: wgpu::IndexFormat,
 }
 
 fn get_file_as_byte_vec(filename: &String) -> Vec<u8> {
@@ -211,7 +197,7 @@ fn build_pipeline(
         sample_mask: !0,
         alpha_to_coverage_enabled: false,
         vertex_state: wgpu::VertexStateDescriptor {
-            index_format: wgpu::IndexFormat::Uint16,
+            index_format: build_pipeline_descriptor.index_format,
             vertex_buffers: &[Vertex::desc()],
         },
     });
@@ -0,0 +1,155 @@
+use crate::animation_clip::{sample_keyframes, Interpolation, Playback, Playhead};
+
+/// What a track animates, with its keyframe values. Nodes are whatever the
+/// `AnimationTarget` numbers them by, materials are indices into its materials.
+#[derive(Clone, Debug)]
+pub enum TrackValues {
+    Translations {
+        node: usize,
+        values: Vec<cgmath::Vector3<f32>>,
+    },
+    Rotations {
+        node: usize,
+        values: Vec<cgmath::Quaternion<f32>>,
+    },
+    Scales {
+        node: usize,
+        values: Vec<cgmath::Vector3<f32>>,
+    },
+    /// Weights of the node's morph targets, one list of keyframe values per target
+    MorphWeights {
+        node: usize,
+        targets: Vec<Vec<f32>>,
+    },
+    /// See `MaterialParams::tint`
+    Tints {
+        material: usize,
+        values: Vec<cgmath::Vector4<f32>>,
+    },
+}
+
+/// Keyframes of one property of a node or a material, sampled like a clip's `Channel`.
+#[derive(Clone, Debug)]
+pub struct Track {
+    /// Keyframe times in seconds, increasing
+    pub times: Vec<f32>,
+    pub interpolation: Interpolation,
+    pub values: TrackValues,
+}
+
+impl Track {
+    pub fn new(times: Vec<f32>, interpolation: Interpolation, values: TrackValues) -> Self {
+        Self {
+            times,
+            interpolation,
+            values,
+        }
+    }
+
+    /// The node the track moves, none for material tracks.
+    pub fn node(&self) -> Option<usize> {
+        match self.values {
+            TrackValues::Translations { node, .. }
+            | TrackValues::Rotations { node, .. }
+            | TrackValues::Scales { node, .. }
+            | TrackValues::MorphWeights { node, .. } => Some(node),
+            TrackValues::Tints { .. } => None,
+        }
+    }
+
+    fn apply(&self, time: f32, target: &mut dyn AnimationTarget) {
+        if self.times.is_empty() {
+            return;
+        }
+        let (times, interpolation) = (&self.times, self.interpolation);
+        match &self.values {
+            TrackValues::Translations { node, values } => {
+                target.set_translation(*node, sample_keyframes(times, values, interpolation, time));
+            }
+            TrackValues::Rotations { node, values } => {
+                target.set_rotation(*node, sample_keyframes(times, values, interpolation, time));
+            }
+            TrackValues::Scales { node, values } => {
+                target.set_scale(*node, sample_keyframes(times, values, interpolation, time));
+            }
+            TrackValues::MorphWeights { node, targets } => {
+                for (morph_target, values) in targets.iter().enumerate() {
+                    let weight = sample_keyframes(times, values, interpolation, time);
+                    target.set_morph_weight(*node, morph_target, weight);
+                }
+            }
+            TrackValues::Tints { material, values } => {
+                target.set_tint(*material, sample_keyframes(times, values, interpolation, time));
+            }
+        }
+    }
+}
+
+/// What tracks animate, the scene they play in.
+pub trait AnimationTarget {
+    fn set_translation(&mut self, node: usize, translation: cgmath::Vector3<f32>);
+    fn set_rotation(&mut self, node: usize, rotation: cgmath::Quaternion<f32>);
+    fn set_scale(&mut self, node: usize, scale: cgmath::Vector3<f32>);
+    fn set_morph_weight(&mut self, node: usize, morph_target: usize, weight: f32);
+    fn set_tint(&mut self, material: usize, tint: cgmath::Vector4<f32>);
+}
+
+/// Tracks played together.
+#[derive(Clone, Debug)]
+pub struct Animation {
+    pub name: String,
+    pub tracks: Vec<Track>,
+    /// Time of the last keyframe of any track
+    pub duration: f32,
+}
+
+impl Animation {
+    pub fn new(name: &str, tracks: Vec<Track>) -> Self {
+        let duration = tracks
+            .iter()
+            .filter_map(|track| track.times.last().copied())
+            .fold(0.0, f32::max);
+        Self {
+            name: name.to_string(),
+            tracks,
+            duration,
+        }
+    }
+}
+
+/// Plays an animation, ticked from the update loop.
+pub struct AnimationPlayer {
+    pub animation: Animation,
+    pub playhead: Playhead,
+    /// Multiplies the time passed to `update`
+    pub speed: f32,
+    pub playing: bool,
+}
+
+impl AnimationPlayer {
+    pub fn new(animation: Animation, playback: Playback) -> Self {
+        Self {
+            animation,
+            playhead: Playhead::new(playback),
+            speed: 1.0,
+            playing: true,
+        }
+    }
+
+    /// Jumps back to the start and plays.
+    pub fn restart(&mut self) {
+        self.playhead.restart();
+        self.playing = true;
+    }
+
+    /// Advances the animation by `dt` seconds and applies it to `target`.
+    pub fn update(&mut self, dt: f32, target: &mut dyn AnimationTarget) {
+        if self.playing {
+            self.playhead.advance(dt * self.speed, self.animation.duration);
+            self.playing = !self.playhead.finished(self.animation.duration);
+        }
+        for track in &self.animation.tracks {
+            track.apply(self.playhead.time(), target);
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use crate::skeleton::{nlerp, Pose};
+use cgmath::InnerSpace;
+
+/// How values between keyframes are computed, glTF's sampler interpolations and the eases
+/// of animations made in code.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Interpolation {
+    /// The previous keyframe's value until the next keyframe
+    Step,
+    Linear,
+    /// Hermite spline, every keyframe has an in and an out tangent besides its value
+    CubicSpline,
+    /// Starts slow, quadratic
+    EaseIn,
+    /// Ends slow, quadratic
+    EaseOut,
+    /// Starts and ends slow, smoothstep
+    EaseInOut,
+}
+
+/// Something keyframes can hold.
+pub trait Keyframe: Copy {
+    fn lerp(self, other: Self, t: f32) -> Self;
+
+    /// Cubic Hermite spline from `a` to `b`, `duration` apart, with the tangents per
+    /// second `a_out` and `b_in`.
+    fn hermite(a: Self, a_out: Self, b: Self, b_in: Self, t: f32, duration: f32) -> Self;
+}
+
+fn hermite_weights(t: f32, duration: f32) -> [f32; 4] {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    [
+        2.0 * t3 - 3.0 * t2 + 1.0,
+        (t3 - 2.0 * t2 + t) * duration,
+        -2.0 * t3 + 3.0 * t2,
+        (t3 - t2) * duration,
+    ]
+}
+
+/// Keyframes of types that add and scale like vectors.
+macro_rules! impl_vector_keyframe {
+    ($($ty:ty),*) => {$(
+        impl Keyframe for $ty {
+            fn lerp(self, other: Self, t: f32) -> Self {
+                self + (other - self) * t
+            }
+
+            fn hermite(a: Self, a_out: Self, b: Self, b_in: Self, t: f32, duration: f32) -> Self {
+                let [h00, h10, h01, h11] = hermite_weights(t, duration);
+                a * h00 + a_out * h10 + b * h01 + b_in * h11
+            }
+        }
+    )*};
+}
+
+impl_vector_keyframe!(f32, cgmath::Vector3<f32>, cgmath::Vector4<f32>);
+
+impl Keyframe for cgmath::Quaternion<f32> {
+    fn lerp(self, other: Self, t: f32) -> Self {
+        nlerp(self, other, t)
+    }
+
+    /// Splines the components like a vector and normalizes the result, what glTF asks for
+    fn hermite(a: Self, a_out: Self, b: Self, b_in: Self, t: f32, duration: f32) -> Self {
+        let [h00, h10, h01, h11] = hermite_weights(t, duration);
+        (a * h00 + a_out * h10 + b * h01 + b_in * h11).normalize()
+    }
+}
+
+/// The value at `time` of keyframes at `times`. `values` has one value per keyframe, or
+/// three for cubic splines: in tangent, value, out tangent, the layout glTF uses. Before
+/// the first and after the last keyframe the value holds still.
+pub fn sample_keyframes<T: Keyframe>(
+    times: &[f32],
+    values: &[T],
+    interpolation: Interpolation,
+    time: f32,
+) -> T {
+    let value = |key: usize| match interpolation {
+        Interpolation::CubicSpline => values[key * 3 + 1],
+        _ => values[key],
+    };
+    let last = times.len() - 1;
+    let next = match times.binary_search_by(|key_time| key_time.partial_cmp(&time).unwrap()) {
+        Ok(key) => return value(key),
+        Err(0) => return value(0),
+        Err(next) if next > last => return value(last),
+        Err(next) => next,
+    };
+    let previous = next - 1;
+    let duration = times[next] - times[previous];
+    let t = (time - times[previous]) / duration;
+
+    match interpolation {
+        Interpolation::Step => value(previous),
+        Interpolation::Linear => value(previous).lerp(value(next), t),
+        Interpolation::EaseIn => value(previous).lerp(value(next), t * t),
+        Interpolation::EaseOut => value(previous).lerp(value(next), t * (2.0 - t)),
+        Interpolation::EaseInOut => value(previous).lerp(value(next), t * t * (3.0 - 2.0 * t)),
+        Interpolation::CubicSpline => T::hermite(
+            value(previous),
+            values[previous * 3 + 2],
+            value(next),
+            values[next * 3],
+            t,
+            duration,
+        ),
+    }
+}
+
+/// What happens when playback reaches the end of a clip.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Playback {
+    /// Stops on the last frame
+    Once,
+    /// Starts over
+    Loop,
+    /// Plays backwards to the start, then forwards again
+    PingPong,
+}
+
+/// Where playback is in a clip, moved along the way its `Playback` says.
+#[derive(Copy, Clone, Debug)]
+pub struct Playhead {
+    pub playback: Playback,
+    time: f32,
+    /// Whether a ping-pong is on its way back
+    reversed: bool,
+}
+
+impl Playhead {
+    pub fn new(playback: Playback) -> Self {
+        Self {
+            playback,
+            time: 0.0,
+            reversed: false,
+        }
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Whether a playhead that plays once got to the end of a clip `duration` long.
+    pub fn finished(&self, duration: f32) -> bool {
+        self.playback == Playback::Once && self.time >= duration
+    }
+
+    pub fn restart(&mut self) {
+        self.time = 0.0;
+        self.reversed = false;
+    }
+
+    /// Moves `dt` seconds further in a clip `duration` long.
+    pub fn advance(&mut self, dt: f32, duration: f32) {
+        if duration <= 0.0 {
+            self.time = 0.0;
+            return;
+        }
+        match self.playback {
+            Playback::Once => self.time = (self.time + dt).min(duration),
+            Playback::Loop => self.time = (self.time + dt).rem_euclid(duration),
+            Playback::PingPong => {
+                // Unfolded, the way back is the second half of a cycle twice as long
+                let unfolded = if self.reversed {
+                    2.0 * duration - self.time
+                } else {
+                    self.time
+                };
+                let unfolded = (unfolded + dt).rem_euclid(2.0 * duration);
+                self.reversed = unfolded > duration;
+                self.time = if self.reversed {
+                    2.0 * duration - unfolded
+                } else {
+                    unfolded
+                };
+            }
+        }
+    }
+}
+
+/// What a channel animates, with its keyframe values.
+#[derive(Clone, Debug)]
+pub enum ChannelValues {
+    Translations {
+        joint: usize,
+        values: Vec<cgmath::Vector3<f32>>,
+    },
+    Rotations {
+        joint: usize,
+        values: Vec<cgmath::Quaternion<f32>>,
+    },
+    Scales {
+        joint: usize,
+        values: Vec<cgmath::Vector3<f32>>,
+    },
+    /// The weights of all morph targets, keyframe values target by target
+    MorphWeights(Vec<Vec<f32>>),
+}
+
+/// Keyframes of one property of a skeleton, like the rotation of a joint.
+#[derive(Clone, Debug)]
+pub struct Channel {
+    pub interpolation: Interpolation,
+    /// Keyframe times in seconds, increasing
+    pub times: Vec<f32>,
+    pub values: ChannelValues,
+}
+
+/// An animation of a skeleton, like a walk cycle or a jump.
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub name: String,
+    pub channels: Vec<Channel>,
+    /// Time of the last keyframe of any channel
+    pub duration: f32,
+}
+
+impl AnimationClip {
+    pub fn new(name: String, channels: Vec<Channel>) -> Self {
+        let duration = channels
+            .iter()
+            .filter_map(|channel| channel.times.last().copied())
+            .fold(0.0, f32::max);
+        Self {
+            name,
+            channels,
+            duration,
+        }
+    }
+
+    /// Sets what the clip animates to its value at `time`, the rest of `pose` stays.
+    pub fn sample(&self, time: f32, pose: &mut Pose) {
+        for channel in self.channels.iter().filter(|channel| !channel.times.is_empty()) {
+            let (times, interpolation) = (&channel.times, channel.interpolation);
+            match &channel.values {
+                ChannelValues::Translations { joint, values } => {
+                    pose.joints[*joint].translation =
+                        sample_keyframes(times, values, interpolation, time);
+                }
+                ChannelValues::Rotations { joint, values } => {
+                    pose.joints[*joint].rotation =
+                        sample_keyframes(times, values, interpolation, time);
+                }
+                ChannelValues::Scales { joint, values } => {
+                    pose.joints[*joint].scale =
+                        sample_keyframes(times, values, interpolation, time);
+                }
+                ChannelValues::MorphWeights(targets) => {
+                    for (weight, values) in pose.morph_weights.iter_mut().zip(targets) {
+                        *weight = sample_keyframes(times, values, interpolation, time);
+                    }
+                }
+            }
+        }
+    }
+}
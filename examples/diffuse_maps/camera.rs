@@ -0,0 +1,159 @@
+use cgmath::{Deg, Rad};
+use iced_wgpu::wgpu;
+
+#[cfg_attr(rustfmt, rustfmt_skip)]
+pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
+    1.0, 0.0, 0.0, 0.0,
+    0.0, 1.0, 0.0, 0.0,
+    0.0, 0.0, 0.5, 0.0,
+    0.0, 0.0, 0.5, 1.0,
+);
+
+#[derive(Copy, Clone, Debug)]
+pub enum Projection {
+    Perspective {
+        fovy: Deg<f32>,
+        znear: f32,
+        zfar: f32,
+    },
+    /// `height` is the visible extent along the up axis, the width follows from the aspect.
+    Orthographic {
+        height: f32,
+        znear: f32,
+        zfar: f32,
+    },
+    /// Perspective without a far plane that maps the near plane to depth 1 and infinity
+    /// to 0. Floats are much denser near 0, so reversing the range spreads depth precision
+    /// evenly over distance. Needs a `Greater` depth test and depth cleared to 0.
+    InfiniteReverseZ {
+        fovy: Deg<f32>,
+        znear: f32,
+    },
+    /// Asymmetric frustum, the bounds are where its sides cross the near plane.
+    /// Used for tiled rendering, stereo and portals. Ignores the aspect.
+    OffCenter {
+        left: f32,
+        right: f32,
+        bottom: f32,
+        top: f32,
+        znear: f32,
+        zfar: f32,
+    },
+}
+
+impl Projection {
+    pub fn matrix(&self, aspect: f32) -> cgmath::Matrix4<f32> {
+        match *self {
+            Projection::Perspective { fovy, znear, zfar } => {
+                OPENGL_TO_WGPU_MATRIX * cgmath::perspective(fovy, aspect, znear, zfar)
+            }
+            Projection::Orthographic {
+                height,
+                znear,
+                zfar,
+            } => {
+                let half_height = height * 0.5;
+                let half_width = half_height * aspect;
+                OPENGL_TO_WGPU_MATRIX
+                    * cgmath::ortho(-half_width, half_width, -half_height, half_height, znear, zfar)
+            }
+            Projection::InfiniteReverseZ { fovy, znear } => {
+                // Already in wgpu's 0..1 depth range, no need for OPENGL_TO_WGPU_MATRIX
+                let f = 1.0 / (Rad::from(fovy).0 * 0.5).tan();
+                #[cfg_attr(rustfmt, rustfmt_skip)]
+                let proj = cgmath::Matrix4::new(
+                    f / aspect, 0.0, 0.0,   0.0,
+                    0.0,        f,   0.0,   0.0,
+                    0.0,        0.0, 0.0,  -1.0,
+                    0.0,        0.0, znear, 0.0,
+                );
+                proj
+            }
+            Projection::OffCenter {
+                left,
+                right,
+                bottom,
+                top,
+                znear,
+                zfar,
+            } => OPENGL_TO_WGPU_MATRIX * cgmath::frustum(left, right, bottom, top, znear, zfar),
+        }
+    }
+
+    pub fn is_reverse_z(&self) -> bool {
+        match self {
+            Projection::InfiniteReverseZ { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Depth test that keeps the closest fragment with this projection.
+    pub fn depth_compare(&self) -> wgpu::CompareFunction {
+        if self.is_reverse_z() {
+            wgpu::CompareFunction::Greater
+        } else {
+            wgpu::CompareFunction::Less
+        }
+    }
+
+    /// Depth of the far plane, what the depth buffer has to be cleared to.
+    pub fn clear_depth(&self) -> f32 {
+        if self.is_reverse_z() {
+            0.0
+        } else {
+            1.0
+        }
+    }
+
+    /// How many pixels one world unit covers at `distance` in front of the camera, in a
+    /// viewport `viewport_height` pixels high. Doesn't depend on the distance for
+    /// orthographic projections.
+    pub fn pixels_per_unit(&self, viewport_height: f32, distance: f32) -> f32 {
+        match *self {
+            Projection::Orthographic { height, .. } => viewport_height / height,
+            _ => {
+                let half_fovy = self.fovy().unwrap() * 0.5;
+                viewport_height / (2.0 * distance.max(f32::EPSILON) * half_fovy.0.tan())
+            }
+        }
+    }
+
+    /// Vertical field of view, `None` for orthographic projections.
+    pub fn fovy(&self) -> Option<Rad<f32>> {
+        match *self {
+            Projection::Perspective { fovy, .. } | Projection::InfiniteReverseZ { fovy, .. } => {
+                Some(fovy.into())
+            }
+            Projection::Orthographic { .. } => None,
+            Projection::OffCenter {
+                bottom, top, znear, ..
+            } => Some(Rad((top / znear).atan() - (bottom / znear).atan())),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Camera {
+    pub eye: cgmath::Point3<f32>,
+    pub target: cgmath::Point3<f32>,
+    pub up: cgmath::Vector3<f32>,
+    pub aspect: f32,
+    pub projection: Projection,
+}
+
+impl Camera {
+    pub fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
+        let view = cgmath::Matrix4::look_at(self.eye, self.target, self.up);
+        let proj = self.projection.matrix(self.aspect);
+        return proj * view;
+    }
+
+    /// Camera placed between `self` and `other`, used to render between two simulation steps.
+    pub fn lerp(&self, other: &Camera, t: f32) -> Camera {
+        Camera {
+            eye: self.eye + (other.eye - self.eye) * t,
+            target: self.target + (other.target - self.target) * t,
+            ..other.clone()
+        }
+    }
+}
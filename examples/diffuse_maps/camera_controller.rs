@@ -1,11 +1,47 @@
-use crate::Camera;
+use crate::camera::Camera;
 use iced_winit::winit::{
     event::*,
     event_loop::{ControlFlow, EventLoop},
     window::Window,
 };
 
-pub struct CameraController {
+/// Anything that moves a `Camera` in response to input. The app owns a boxed controller
+/// and can swap it at runtime, `attach` lets the new one pick up from the current camera.
+pub trait CameraController {
+    /// Returns true if the event was used.
+    fn process_events(&mut self, event: &WindowEvent) -> bool;
+
+    fn process_device_events(&mut self, _event: &DeviceEvent) -> bool {
+        false
+    }
+
+    /// `dt` is in seconds
+    fn update_camera(&mut self, camera: &mut Camera, dt: f32);
+
+    /// Called when the controller becomes the active one.
+    fn attach(&mut self, _camera: &Camera) {}
+
+    /// Called when another controller replaces this one.
+    fn detach(&mut self) {}
+
+    /// The window should grab and hide the cursor while this is true.
+    fn is_mouse_captured(&self) -> bool {
+        false
+    }
+
+    /// Moves the camera so the box is entirely in view, if the controller supports it.
+    fn frame_bounds(
+        &mut self,
+        _min: cgmath::Point3<f32>,
+        _max: cgmath::Point3<f32>,
+        _camera: &Camera,
+    ) {
+    }
+}
+
+/// WASD/QE movement that keeps looking at `Camera::target`.
+pub struct KeyboardCameraController {
+    /// Units per second
     speed: f32,
     is_up_pressed: bool,
     is_down_pressed: bool,
@@ -15,7 +51,7 @@ pub struct CameraController {
     is_right_pressed: bool,
 }
 
-impl CameraController {
+impl KeyboardCameraController {
     pub fn new(speed: f32) -> Self {
         Self {
             speed,
@@ -27,8 +63,10 @@ impl CameraController {
             is_right_pressed: false,
         }
     }
+}
 
-    pub fn process_events(&mut self, event: &WindowEvent) -> bool {
+impl CameraController for KeyboardCameraController {
+    fn process_events(&mut self, event: &WindowEvent) -> bool {
         match event {
             WindowEvent::KeyboardInput {
                 input:
@@ -72,37 +110,38 @@ impl CameraController {
         }
     }
 
-    pub fn update_camera(&self, camera: &mut Camera) {
+    fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
         use cgmath::InnerSpace;
+        let step = self.speed * dt;
         let forward = camera.target - camera.eye;
         let forward_norm = forward.normalize();
         let forward_mag = forward.magnitude();
 
         // Prevents glitching when camera gets too close to the center of the scene
-        if self.is_forward_pressed && forward_mag > self.speed {
-            camera.eye += forward_norm * self.speed;
+        if self.is_forward_pressed && forward_mag > step {
+            camera.eye += forward_norm * step;
         }
         if self.is_backward_pressed {
-            camera.eye -= forward_norm * self.speed;
+            camera.eye -= forward_norm * step;
         }
 
         if self.is_up_pressed {
-            camera.eye += camera.up * self.speed;
-            camera.target += camera.up * self.speed;
+            camera.eye += camera.up * step;
+            camera.target += camera.up * step;
         }
         if self.is_down_pressed {
-            camera.eye -= camera.up * self.speed;
-            camera.target -= camera.up * self.speed;
+            camera.eye -= camera.up * step;
+            camera.target -= camera.up * step;
         }
 
         let right = forward_norm.cross(camera.up);
         if self.is_right_pressed {
-            camera.eye += right * self.speed;
-            camera.target += right * self.speed;
+            camera.eye += right * step;
+            camera.target += right * step;
         }
         if self.is_left_pressed {
-            camera.eye -= right * self.speed;
-            camera.target -= right * self.speed;
+            camera.eye -= right * step;
+            camera.target -= right * step;
         }
     }
 }
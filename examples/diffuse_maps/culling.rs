@@ -0,0 +1,160 @@
+use cgmath::{EuclideanSpace, InnerSpace, Matrix};
+
+/// Axis aligned bounding box.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: cgmath::Point3<f32>,
+    pub max: cgmath::Point3<f32>,
+}
+
+impl Aabb {
+    pub fn from_points(points: impl IntoIterator<Item = cgmath::Point3<f32>>) -> Self {
+        let mut min = cgmath::Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = cgmath::Point3::new(f32::MIN, f32::MIN, f32::MIN);
+        for point in points {
+            for i in 0..3 {
+                min[i] = min[i].min(point[i]);
+                max[i] = max[i].max(point[i]);
+            }
+        }
+        Self { min, max }
+    }
+
+    pub fn corners(&self) -> [cgmath::Point3<f32>; 8] {
+        let (min, max) = (self.min, self.max);
+        [
+            cgmath::Point3::new(min.x, min.y, min.z),
+            cgmath::Point3::new(max.x, min.y, min.z),
+            cgmath::Point3::new(min.x, max.y, min.z),
+            cgmath::Point3::new(max.x, max.y, min.z),
+            cgmath::Point3::new(min.x, min.y, max.z),
+            cgmath::Point3::new(max.x, min.y, max.z),
+            cgmath::Point3::new(min.x, max.y, max.z),
+            cgmath::Point3::new(max.x, max.y, max.z),
+        ]
+    }
+
+    /// Box around this box after transforming it, looser than the transformed box itself.
+    pub fn transform(&self, matrix: &cgmath::Matrix4<f32>) -> Aabb {
+        use cgmath::Transform;
+        Aabb::from_points(self.corners().iter().map(|corner| matrix.transform_point(*corner)))
+    }
+
+    /// Smallest sphere centered on the model origin that contains the box. It stays valid
+    /// however the model is rotated around its origin, which is what the instance spin on
+    /// the gpu does.
+    pub fn origin_sphere(&self) -> BoundingSphere {
+        let radius = self
+            .corners()
+            .iter()
+            .map(|corner| corner.to_vec().magnitude())
+            .fold(0.0, f32::max);
+        BoundingSphere {
+            center: cgmath::Point3::origin(),
+            radius,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: cgmath::Point3<f32>,
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    pub fn translate(&self, offset: cgmath::Vector3<f32>) -> Self {
+        Self {
+            center: self.center + offset,
+            radius: self.radius,
+        }
+    }
+
+    /// Sphere around everything inside scaled by `factor` around the origin.
+    pub fn scale(&self, factor: f32) -> Self {
+        Self {
+            center: self.center * factor,
+            radius: self.radius * factor,
+        }
+    }
+}
+
+/// Plane of the points `p` with `normal.dot(p) + distance == 0`, the normal points to
+/// the inside of the frustum.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Plane {
+    pub normal: cgmath::Vector3<f32>,
+    pub distance: f32,
+}
+
+impl Plane {
+    /// A plane from a combination of rows of the view projection matrix, normalized so
+    /// `signed_distance` is in world units. Infinite projections don't have a far plane,
+    /// there the combination has no normal and becomes a plane everything is inside of.
+    fn from_row(row: cgmath::Vector4<f32>) -> Self {
+        let normal = row.truncate();
+        let length = normal.magnitude();
+        if length < 1e-6 {
+            return Plane {
+                normal: cgmath::Vector3::new(0.0, 0.0, 0.0),
+                distance: 0.0,
+            };
+        }
+        Plane {
+            normal: normal / length,
+            distance: row.w / length,
+        }
+    }
+
+    pub fn signed_distance(&self, point: cgmath::Point3<f32>) -> f32 {
+        self.normal.dot(point.to_vec()) + self.distance
+    }
+}
+
+/// The six planes bounding what a camera sees.
+#[derive(Copy, Clone, Debug)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near, far
+    pub planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes from a view projection matrix (Gribb and Hartmann). Expects
+    /// wgpu's clip space, depth in `0..w`, so near is `z >= 0` instead of `z >= -w`. For
+    /// reverse-Z projections near and far swap places, the frustum is the same.
+    pub fn from_view_projection(matrix: &cgmath::Matrix4<f32>) -> Self {
+        let x = matrix.row(0);
+        let y = matrix.row(1);
+        let z = matrix.row(2);
+        let w = matrix.row(3);
+        Self {
+            planes: [
+                Plane::from_row(w + x),
+                Plane::from_row(w - x),
+                Plane::from_row(w + y),
+                Plane::from_row(w - y),
+                Plane::from_row(z),
+                Plane::from_row(w - z),
+            ],
+        }
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.signed_distance(sphere.center) >= -sphere.radius)
+    }
+
+    /// Conservative: boxes near the frustum's edges can pass while being outside of it.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            // The corner furthest along the normal, if it's outside the whole box is
+            let positive = cgmath::Point3::new(
+                if plane.normal.x >= 0.0 { aabb.max.x } else { aabb.min.x },
+                if plane.normal.y >= 0.0 { aabb.max.y } else { aabb.min.y },
+                if plane.normal.z >= 0.0 { aabb.max.z } else { aabb.min.z },
+            );
+            plane.signed_distance(positive) >= 0.0
+        })
+    }
+}
@@ -0,0 +1,318 @@
+use crate::culling::{Aabb, BoundingSphere};
+use crate::material::BlendMode;
+use crate::pipeline_cache::{DepthState, LayoutId, PipelineCache, PipelineId, PipelineKey};
+use crate::texture;
+use crate::upload::{RingBuffer, StagingBelt};
+use cgmath::{EuclideanSpace, SquareMatrix};
+use iced_wgpu::wgpu;
+
+/// Room for the lines of a few frames, anything past it in one frame is dropped
+const RING_SIZE: wgpu::BufferAddress = 1 << 20;
+const VERTEX_SIZE: wgpu::BufferAddress =
+    std::mem::size_of::<DebugVertex>() as wgpu::BufferAddress;
+const CIRCLE_SEGMENTS: usize = 32;
+
+/// Edges between the corners of a box, numbered by their bits with x in bit 0 and z in bit 2
+#[rustfmt::skip]
+const BOX_EDGES: [(usize, usize); 12] = [
+    (0, 1), (2, 3), (4, 5), (6, 7),
+    (0, 2), (1, 3), (4, 6), (5, 7),
+    (0, 4), (1, 5), (2, 6), (3, 7),
+];
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct DebugVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for DebugVertex {}
+unsafe impl bytemuck::Zeroable for DebugVertex {}
+
+impl DebugVertex {
+    fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        wgpu::VertexBufferDescriptor {
+            stride: VERTEX_SIZE,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: 12,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float4,
+                },
+            ],
+        }
+    }
+}
+
+/// Whether lines hide behind what's drawn in front of them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DepthMode {
+    Tested,
+    /// Over everything, for gizmos that must never get lost
+    OnTop,
+}
+
+/// Immediate mode debug lines.
+///
+/// Anything can add lines during the frame, `upload` copies them all to the gpu in one go
+/// and `draw` draws them with two line list draws, one per `DepthMode`. `clear` starts
+/// the next frame's lines, nothing stays unless added again.
+pub struct DebugDraw {
+    tested: Vec<DebugVertex>,
+    on_top: Vec<DebugVertex>,
+    ring: RingBuffer,
+    /// Where this frame's lines start in the ring, the depth tested ones first
+    offset: wgpu::BufferAddress,
+    tested_count: u32,
+    on_top_count: u32,
+    tested_key: PipelineKey,
+    tested_pipeline: PipelineId,
+    on_top_pipeline: PipelineId,
+}
+
+impl DebugDraw {
+    /// `layout` is the view uniforms' alone, the line shaders read them from bind group 0.
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        layout: LayoutId,
+        color_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+    ) -> Self {
+        let key = PipelineKey::new(
+            layout,
+            "examples/diffuse_maps/shader/debug_line_vert.spv",
+            "examples/diffuse_maps/shader/debug_line_frag.spv",
+        )
+        .with_vertex_layouts(&[DebugVertex::desc()]);
+        let tested_key = PipelineKey {
+            primitive_topology: wgpu::PrimitiveTopology::LineList,
+            color_targets: vec![BlendMode::AlphaBlend.color_target(color_format)],
+            depth: Some(DepthState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare,
+            }),
+            ..key
+        };
+        // The passes have a depth attachment, so the pipeline needs a depth state too
+        let on_top_key = PipelineKey {
+            depth: Some(DepthState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+            }),
+            ..tested_key.clone()
+        };
+        Self {
+            tested: Vec::new(),
+            on_top: Vec::new(),
+            ring: RingBuffer::new(device, RING_SIZE, 0, wgpu::BufferUsage::VERTEX, "debug_ring"),
+            offset: 0,
+            tested_count: 0,
+            on_top_count: 0,
+            tested_pipeline: pipeline_cache.get_or_create(device, &tested_key),
+            on_top_pipeline: pipeline_cache.get_or_create(device, &on_top_key),
+            tested_key,
+        }
+    }
+
+    /// Switches the depth tested pipeline to another depth test, like
+    /// `Material::set_depth_compare`.
+    pub fn set_depth_compare(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        depth_compare: wgpu::CompareFunction,
+    ) {
+        if let Some(depth) = &mut self.tested_key.depth {
+            depth.depth_compare = depth_compare;
+        }
+        self.tested_pipeline = pipeline_cache.get_or_create(device, &self.tested_key);
+    }
+
+    /// Forgets the lines added so far, the ones already uploaded are still drawn.
+    pub fn clear(&mut self) {
+        self.tested.clear();
+        self.on_top.clear();
+    }
+
+    pub fn line(
+        &mut self,
+        a: cgmath::Point3<f32>,
+        b: cgmath::Point3<f32>,
+        color: [f32; 4],
+        mode: DepthMode,
+    ) {
+        let vertices = match mode {
+            DepthMode::Tested => &mut self.tested,
+            DepthMode::OnTop => &mut self.on_top,
+        };
+        vertices.push(DebugVertex {
+            position: a.into(),
+            color,
+        });
+        vertices.push(DebugVertex {
+            position: b.into(),
+            color,
+        });
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 4], mode: DepthMode) {
+        let corners = aabb.corners();
+        for (a, b) in &BOX_EDGES {
+            self.line(corners[*a], corners[*b], color, mode);
+        }
+    }
+
+    /// Three circles around the sphere, one in each axis plane.
+    pub fn sphere(&mut self, sphere: &BoundingSphere, color: [f32; 4], mode: DepthMode) {
+        let x = cgmath::Vector3::unit_x() * sphere.radius;
+        let y = cgmath::Vector3::unit_y() * sphere.radius;
+        let z = cgmath::Vector3::unit_z() * sphere.radius;
+        self.circle(sphere.center, x, y, color, mode);
+        self.circle(sphere.center, y, z, color, mode);
+        self.circle(sphere.center, z, x, color, mode);
+    }
+
+    /// Edges of what a camera with the view projection `view_proj` sees. Corners at
+    /// infinity, like the far plane of an infinite projection, are left out.
+    pub fn frustum(&mut self, view_proj: &cgmath::Matrix4<f32>, color: [f32; 4], mode: DepthMode) {
+        let inverse = match view_proj.invert() {
+            Some(inverse) => inverse,
+            None => return,
+        };
+        // wgpu's clip space has depth in 0..1, the corners are numbered like a box's
+        let corners = (0..8)
+            .map(|i| {
+                let clip = cgmath::Vector4::new(
+                    if i & 1 == 0 { -1.0 } else { 1.0 },
+                    if i & 2 == 0 { -1.0 } else { 1.0 },
+                    if i & 4 == 0 { 0.0 } else { 1.0 },
+                    1.0,
+                );
+                let world = inverse * clip;
+                if world.w.abs() < 1e-6 {
+                    None
+                } else {
+                    Some(cgmath::Point3::from_vec(world.truncate() / world.w))
+                }
+            })
+            .collect::<Vec<_>>();
+        for (a, b) in &BOX_EDGES {
+            if let (Some(a), Some(b)) = (corners[*a], corners[*b]) {
+                self.line(a, b, color, mode);
+            }
+        }
+    }
+
+    /// Red, green and blue lines of length `size` along the x, y and z axes of `transform`.
+    pub fn axes(&mut self, transform: &cgmath::Matrix4<f32>, size: f32, mode: DepthMode) {
+        let origin = cgmath::Point3::from_vec(transform.w.truncate());
+        let axes = [
+            (transform.x.truncate(), [1.0, 0.0, 0.0, 1.0]),
+            (transform.y.truncate(), [0.0, 1.0, 0.0, 1.0]),
+            (transform.z.truncate(), [0.0, 0.0, 1.0, 1.0]),
+        ];
+        for (axis, color) in &axes {
+            self.line(origin, origin + axis * size, *color, mode);
+        }
+    }
+
+    /// A square grid on the xz plane through `center`, `size` wide with `divisions` cells
+    /// along each side.
+    pub fn grid(
+        &mut self,
+        center: cgmath::Point3<f32>,
+        size: f32,
+        divisions: u32,
+        color: [f32; 4],
+        mode: DepthMode,
+    ) {
+        let half = size * 0.5;
+        let divisions = divisions.max(1);
+        for i in 0..=divisions {
+            let t = -half + size * i as f32 / divisions as f32;
+            self.line(
+                center + cgmath::Vector3::new(t, 0.0, -half),
+                center + cgmath::Vector3::new(t, 0.0, half),
+                color,
+                mode,
+            );
+            self.line(
+                center + cgmath::Vector3::new(-half, 0.0, t),
+                center + cgmath::Vector3::new(half, 0.0, t),
+                color,
+                mode,
+            );
+        }
+    }
+
+    fn circle(
+        &mut self,
+        center: cgmath::Point3<f32>,
+        x: cgmath::Vector3<f32>,
+        y: cgmath::Vector3<f32>,
+        color: [f32; 4],
+        mode: DepthMode,
+    ) {
+        let point = |i: usize| {
+            let angle = std::f32::consts::PI * 2.0 * i as f32 / CIRCLE_SEGMENTS as f32;
+            center + x * angle.cos() + y * angle.sin()
+        };
+        for i in 0..CIRCLE_SEGMENTS {
+            self.line(point(i), point(i + 1), color, mode);
+        }
+    }
+
+    /// Copies this frame's lines to the gpu.
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+    ) {
+        let capacity = (RING_SIZE / VERTEX_SIZE) as usize & !1;
+        self.tested.truncate(capacity);
+        self.on_top.truncate(capacity - self.tested.len());
+        self.tested_count = self.tested.len() as u32;
+        self.on_top_count = self.on_top.len() as u32;
+        if self.tested.is_empty() && self.on_top.is_empty() {
+            return;
+        }
+        let vertices = self.tested.iter().chain(&self.on_top).copied().collect::<Vec<_>>();
+        self.offset = self.ring.push(device, encoder, belt, bytemuck::cast_slice(&vertices));
+    }
+
+    /// Draws the uploaded lines. `uniform_bind_group` is a view's, bound at
+    /// `uniform_offset`.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipeline_cache: &'a PipelineCache,
+        uniform_bind_group: &'a wgpu::BindGroup,
+        uniform_offset: wgpu::DynamicOffset,
+    ) {
+        if self.tested_count + self.on_top_count == 0 {
+            return;
+        }
+        render_pass.set_bind_group(0, uniform_bind_group, &[uniform_offset]);
+        render_pass.set_vertex_buffer(0, &self.ring.buffer, self.offset, 0);
+        let end = self.tested_count + self.on_top_count;
+        if self.tested_count > 0 {
+            render_pass.set_pipeline(pipeline_cache.get(self.tested_pipeline));
+            render_pass.draw(0..self.tested_count, 0..1);
+        }
+        if self.on_top_count > 0 {
+            render_pass.set_pipeline(pipeline_cache.get(self.on_top_pipeline));
+            render_pass.draw(self.tested_count..end, 0..1);
+        }
+    }
+}
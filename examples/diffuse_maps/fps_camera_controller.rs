@@ -0,0 +1,212 @@
+use crate::camera::Camera;
+use crate::camera_controller::CameraController;
+use cgmath::{InnerSpace, Rad};
+use iced_winit::winit::event::*;
+use std::f32::consts::FRAC_PI_2;
+
+/// Stay a bit away from straight up and down, `look_at` breaks when forward == up
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+const MIN_SPEED: f32 = 0.1;
+const MAX_SPEED: f32 = 100.0;
+
+/// First person controller: mouse looks around, WASD moves relative to where the camera
+/// looks, E/Q or Space/LControl move up and down, LShift sprints and the scroll wheel
+/// changes the speed.
+///
+/// Mouse look uses raw `DeviceEvent::MouseMotion` and is active only while the cursor is
+/// captured. A left click captures the cursor, Escape releases it.
+pub struct FpsCameraController {
+    yaw: Rad<f32>,
+    pitch: Rad<f32>,
+    /// Units per second
+    speed: f32,
+    sprint_multiplier: f32,
+    /// Radians per pixel of mouse movement
+    sensitivity: f32,
+    mouse_delta: (f32, f32),
+    mouse_captured: bool,
+    is_up_pressed: bool,
+    is_down_pressed: bool,
+    is_forward_pressed: bool,
+    is_backward_pressed: bool,
+    is_left_pressed: bool,
+    is_right_pressed: bool,
+    is_sprint_pressed: bool,
+}
+
+impl FpsCameraController {
+    /// Yaw and pitch start out looking the way the camera already looks.
+    pub fn new(camera: &Camera, speed: f32, sensitivity: f32) -> Self {
+        let mut controller = Self {
+            yaw: Rad(0.0),
+            pitch: Rad(0.0),
+            speed,
+            sprint_multiplier: 3.0,
+            sensitivity,
+            mouse_delta: (0.0, 0.0),
+            mouse_captured: false,
+            is_up_pressed: false,
+            is_down_pressed: false,
+            is_forward_pressed: false,
+            is_backward_pressed: false,
+            is_left_pressed: false,
+            is_right_pressed: false,
+            is_sprint_pressed: false,
+        };
+        controller.attach(camera);
+        controller
+    }
+
+    fn release_mouse(&mut self) {
+        self.mouse_captured = false;
+        self.mouse_delta = (0.0, 0.0);
+    }
+}
+
+impl CameraController for FpsCameraController {
+    fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } => {
+                let is_pressed = *state == ElementState::Pressed;
+                match keycode {
+                    VirtualKeyCode::Escape if self.mouse_captured => {
+                        if is_pressed {
+                            self.release_mouse();
+                        }
+                        true
+                    }
+                    VirtualKeyCode::E | VirtualKeyCode::Space => {
+                        self.is_up_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::Q | VirtualKeyCode::LControl => {
+                        self.is_down_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::W | VirtualKeyCode::Up => {
+                        self.is_forward_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::A | VirtualKeyCode::Left => {
+                        self.is_left_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::S | VirtualKeyCode::Down => {
+                        self.is_backward_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::D | VirtualKeyCode::Right => {
+                        self.is_right_pressed = is_pressed;
+                        true
+                    }
+                    VirtualKeyCode::LShift => {
+                        self.is_sprint_pressed = is_pressed;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.mouse_captured = true;
+                true
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                };
+                self.speed = (self.speed * 1.1f32.powf(lines)).max(MIN_SPEED).min(MAX_SPEED);
+                true
+            }
+            WindowEvent::Focused(false) => {
+                self.release_mouse();
+                false
+            }
+            _ => false,
+        }
+    }
+
+    fn process_device_events(&mut self, event: &DeviceEvent) -> bool {
+        match event {
+            DeviceEvent::MouseMotion { delta } if self.mouse_captured => {
+                self.mouse_delta.0 += delta.0 as f32;
+                self.mouse_delta.1 += delta.1 as f32;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Mouse movement isn't scaled by `dt`, the deltas are already the distance moved
+    /// since the last update.
+    fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        self.yaw += Rad(self.mouse_delta.0 * self.sensitivity);
+        self.pitch -= Rad(self.mouse_delta.1 * self.sensitivity);
+        self.pitch = Rad(self.pitch.0.max(-MAX_PITCH).min(MAX_PITCH));
+        self.mouse_delta = (0.0, 0.0);
+
+        let (yaw_sin, yaw_cos) = self.yaw.0.sin_cos();
+        let (pitch_sin, pitch_cos) = self.pitch.0.sin_cos();
+        let direction =
+            cgmath::Vector3::new(yaw_cos * pitch_cos, pitch_sin, yaw_sin * pitch_cos).normalize();
+        // Walking ignores pitch, looking down doesn't slow you down
+        let forward = cgmath::Vector3::new(yaw_cos, 0.0, yaw_sin);
+        let right = forward.cross(camera.up).normalize();
+
+        let mut movement = cgmath::Vector3::new(0.0, 0.0, 0.0);
+        if self.is_forward_pressed {
+            movement += forward;
+        }
+        if self.is_backward_pressed {
+            movement -= forward;
+        }
+        if self.is_right_pressed {
+            movement += right;
+        }
+        if self.is_left_pressed {
+            movement -= right;
+        }
+        if self.is_up_pressed {
+            movement += camera.up;
+        }
+        if self.is_down_pressed {
+            movement -= camera.up;
+        }
+        if movement.magnitude2() > 0.0 {
+            let speed = if self.is_sprint_pressed {
+                self.speed * self.sprint_multiplier
+            } else {
+                self.speed
+            };
+            camera.eye += movement.normalize() * speed * dt;
+        }
+
+        camera.target = camera.eye + direction;
+    }
+
+    fn attach(&mut self, camera: &Camera) {
+        let direction = (camera.target - camera.eye).normalize();
+        self.yaw = Rad(direction.z.atan2(direction.x));
+        self.pitch = Rad(direction.y.asin().max(-MAX_PITCH).min(MAX_PITCH));
+    }
+
+    fn detach(&mut self) {
+        self.release_mouse();
+    }
+
+    fn is_mouse_captured(&self) -> bool {
+        self.mouse_captured
+    }
+}
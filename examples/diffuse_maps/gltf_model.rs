@@ -0,0 +1,295 @@
+use crate::animation_clip::{AnimationClip, Channel, ChannelValues, Interpolation};
+use crate::mesh::MorphTarget;
+use crate::skeleton::{Joint, Skeleton, Transform};
+use crate::skinning::SkinnedVertex;
+use cgmath::SquareMatrix;
+use gltf::animation::util::ReadOutputs;
+use std::collections::{HashMap, HashSet};
+
+/// A skinned character from a glTF file: the meshes bound to its first skin merged into
+/// one, the skin's joints and the file's animations. Materials are left out, the model is
+/// drawn with one of ours.
+///
+/// A file without skins gives its first mesh instead, with a skeleton of one joint that
+/// is the mesh's node.
+pub struct GltfModel {
+    pub vertices: Vec<SkinnedVertex>,
+    pub indices: Vec<u32>,
+    /// Targets of the merged meshes, which share their weights
+    pub morph_targets: Vec<MorphTarget>,
+    /// Weight of every morph target where no clip animates them
+    pub default_weights: Vec<f32>,
+    pub skeleton: Skeleton,
+    pub clips: Vec<AnimationClip>,
+}
+
+impl GltfModel {
+    pub fn load(path: &str) -> Result<Self, failure::Error> {
+        let (document, buffers, _images) = gltf::import(path)?;
+
+        let mut parents = HashMap::new();
+        for node in document.nodes() {
+            for child in node.children() {
+                parents.insert(child.index(), node.index());
+            }
+        }
+        let nodes = document.nodes().collect::<Vec<_>>();
+        let global_matrix = |mut index: usize| {
+            let mut matrix = cgmath::Matrix4::identity();
+            loop {
+                matrix = cgmath::Matrix4::from(nodes[index].transform().matrix()) * matrix;
+                match parents.get(&index) {
+                    Some(&parent) => index = parent,
+                    None => return matrix,
+                }
+            }
+        };
+
+        // Joints are indexed by their position in the skin, the indices vertices use
+        let (joint_nodes, inverse_binds, mesh_nodes) = match document.skins().next() {
+            Some(skin) => {
+                let inverse_binds = skin
+                    .reader(|buffer| Some(&buffers[buffer.index()]))
+                    .read_inverse_bind_matrices()
+                    .map(|matrices| matrices.map(cgmath::Matrix4::from).collect::<Vec<_>>());
+                let mesh_nodes = document
+                    .nodes()
+                    .filter(|node| node.mesh().is_some())
+                    .filter(|node| node.skin().map(|used| used.index()) == Some(skin.index()))
+                    .map(|node| node.index())
+                    .collect::<Vec<_>>();
+                (skin.joints().map(|node| node.index()).collect(), inverse_binds, mesh_nodes)
+            }
+            None => {
+                let node = document
+                    .nodes()
+                    .find(|node| node.mesh().is_some())
+                    .ok_or_else(|| failure::format_err!("{} has no mesh", path))?;
+                (vec![node.index()], None, vec![node.index()])
+            }
+        };
+        let joint_of_node = joint_nodes
+            .iter()
+            .enumerate()
+            .map(|(joint, &node)| (node, joint))
+            .collect::<HashMap<_, _>>();
+
+        let mut root = None;
+        let mut joints = Vec::with_capacity(joint_nodes.len());
+        for (joint, node) in joint_nodes.iter().map(|&index| &nodes[index]).enumerate() {
+            // Nodes between joints that aren't joints themselves are skipped, exporters
+            // rarely put any there
+            let mut parent = parents.get(&node.index()).copied();
+            while let Some(index) = parent {
+                if joint_of_node.contains_key(&index) {
+                    break;
+                }
+                parent = parents.get(&index).copied();
+            }
+            let parent = parent.map(|index| joint_of_node[&index]);
+            if parent.is_none() && root.is_none() {
+                root = Some(match parents.get(&node.index()) {
+                    Some(&index) => global_matrix(index),
+                    None => cgmath::Matrix4::identity(),
+                });
+            }
+
+            let (translation, rotation, scale) = node.transform().decomposed();
+            joints.push(Joint {
+                name: node.name().unwrap_or("").to_string(),
+                parent,
+                rest: Transform {
+                    translation: translation.into(),
+                    rotation: quaternion(rotation),
+                    scale: scale.into(),
+                },
+                inverse_bind: inverse_binds
+                    .as_ref()
+                    .map_or(cgmath::Matrix4::identity(), |matrices| matrices[joint]),
+            });
+        }
+        let skeleton = Skeleton::new(joints, root.unwrap_or_else(cgmath::Matrix4::identity));
+
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        let mut morph_targets = Vec::<MorphTarget>::new();
+        let mut default_weights = Vec::new();
+        for node in mesh_nodes.iter().map(|&index| &nodes[index]) {
+            let mesh = node.mesh().unwrap();
+            if default_weights.is_empty() {
+                if let Some(weights) = node.weights().or_else(|| mesh.weights()) {
+                    default_weights = weights.to_vec();
+                }
+            }
+
+            for primitive in mesh.primitives() {
+                let reader = primitive.reader(|buffer| Some(&buffers[buffer.index()]));
+                let positions = match reader.read_positions() {
+                    Some(positions) => positions.collect::<Vec<_>>(),
+                    None => continue,
+                };
+                let count = positions.len();
+                // Defaults for what the file leaves out, the shaders only use positions,
+                // texture coordinates and the skin so far
+                let normals = reader
+                    .read_normals()
+                    .map_or(vec![[0.0, 1.0, 0.0]; count], |normals| normals.collect());
+                let tangents = reader
+                    .read_tangents()
+                    .map_or(vec![[1.0, 0.0, 0.0, 1.0]; count], |tangents| tangents.collect());
+                let tex_coords = reader
+                    .read_tex_coords(0)
+                    .map_or(vec![[0.0, 0.0]; count], |uvs| uvs.into_f32().collect());
+                let joints = reader
+                    .read_joints(0)
+                    .map_or(vec![[0; 4]; count], |joints| joints.into_u16().collect());
+                let weights = reader
+                    .read_weights(0)
+                    .map_or(vec![[1.0, 0.0, 0.0, 0.0]; count], |weights| {
+                        weights.into_f32().collect()
+                    });
+
+                // Every target spans all vertices, zero where a primitive has fewer targets
+                let targets = reader.read_morph_targets().collect::<Vec<_>>();
+                while morph_targets.len() < targets.len() {
+                    morph_targets.push(MorphTarget {
+                        name: format!("target {}", morph_targets.len()),
+                        position_deltas: vec![[0.0; 3]; vertices.len()],
+                        normal_deltas: vec![[0.0; 3]; vertices.len()],
+                    });
+                }
+                let mut targets = targets.into_iter();
+                for target in &mut morph_targets {
+                    let (positions, normals) = match targets.next() {
+                        Some((positions, normals, _tangents)) => (positions, normals),
+                        None => (None, None),
+                    };
+                    match positions {
+                        Some(deltas) => target.position_deltas.extend(deltas),
+                        None => target.position_deltas.extend(vec![[0.0; 3]; count]),
+                    }
+                    match normals {
+                        Some(deltas) => target.normal_deltas.extend(deltas),
+                        None => target.normal_deltas.extend(vec![[0.0; 3]; count]),
+                    }
+                }
+
+                let first = vertices.len() as u32;
+                for i in 0..count {
+                    vertices.push(SkinnedVertex {
+                        position: positions[i],
+                        tex_coords: tex_coords[i],
+                        normal: normals[i],
+                        tangent: tangents[i],
+                        joints: joints[i],
+                        weights: normalize_weights(weights[i]),
+                    });
+                }
+                match reader.read_indices() {
+                    Some(read) => indices.extend(read.into_u32().map(|index| first + index)),
+                    None => indices.extend(first..first + count as u32),
+                }
+            }
+        }
+        if indices.is_empty() {
+            return Err(failure::format_err!("{} has no mesh using its skin", path));
+        }
+        default_weights.resize(morph_targets.len(), 0.0);
+        let mesh_nodes = mesh_nodes.into_iter().collect::<HashSet<_>>();
+
+        let clips = document
+            .animations()
+            .enumerate()
+            .map(|(index, animation)| {
+                let channels = animation
+                    .channels()
+                    .filter_map(|channel| {
+                        read_channel(&channel, &buffers, &joint_of_node, &mesh_nodes)
+                    })
+                    .collect();
+                let name = match animation.name() {
+                    Some(name) => name.to_string(),
+                    None => format!("animation {}", index),
+                };
+                AnimationClip::new(name, channels)
+            })
+            .collect();
+
+        Ok(Self {
+            vertices,
+            indices,
+            morph_targets,
+            default_weights,
+            skeleton,
+            clips,
+        })
+    }
+}
+
+/// Keyframes of a channel that animates one of the skin's joints or the morph target
+/// weights of one of the meshes.
+fn read_channel(
+    channel: &gltf::animation::Channel,
+    buffers: &[gltf::buffer::Data],
+    joint_of_node: &HashMap<usize, usize>,
+    mesh_nodes: &HashSet<usize>,
+) -> Option<Channel> {
+    let node = channel.target().node().index();
+    let joint = joint_of_node.get(&node).copied();
+    let reader = channel.reader(|buffer| Some(&buffers[buffer.index()]));
+    let times = reader.read_inputs()?.collect::<Vec<_>>();
+    let values = match reader.read_outputs()? {
+        ReadOutputs::Translations(values) => ChannelValues::Translations {
+            joint: joint?,
+            values: values.map(Into::into).collect(),
+        },
+        ReadOutputs::Rotations(values) => ChannelValues::Rotations {
+            joint: joint?,
+            values: values.into_f32().map(quaternion).collect(),
+        },
+        ReadOutputs::Scales(values) => ChannelValues::Scales {
+            joint: joint?,
+            values: values.map(Into::into).collect(),
+        },
+        ReadOutputs::MorphTargetWeights(values) if mesh_nodes.contains(&node) => {
+            // All weights of a keyframe are next to each other, with cubic splines the in
+            // tangents, values and out tangents each. Taking every nth gives one target's.
+            let values = values.into_f32().collect::<Vec<_>>();
+            let target_count = values.len() / times.len().max(1);
+            let target_count = match channel.sampler().interpolation() {
+                gltf::animation::Interpolation::CubicSpline => target_count / 3,
+                _ => target_count,
+            };
+            let targets = (0..target_count)
+                .map(|target| values.iter().skip(target).step_by(target_count).copied().collect())
+                .collect();
+            ChannelValues::MorphWeights(targets)
+        }
+        ReadOutputs::MorphTargetWeights(_) => return None,
+    };
+    let interpolation = match channel.sampler().interpolation() {
+        gltf::animation::Interpolation::Step => Interpolation::Step,
+        gltf::animation::Interpolation::Linear => Interpolation::Linear,
+        gltf::animation::Interpolation::CubicSpline => Interpolation::CubicSpline,
+    };
+    Some(Channel {
+        interpolation,
+        times,
+        values,
+    })
+}
+
+/// glTF stores quaternions as x, y, z, w
+fn quaternion([x, y, z, w]: [f32; 4]) -> cgmath::Quaternion<f32> {
+    cgmath::Quaternion::new(w, x, y, z)
+}
+
+/// Exporters don't always make the weights add up to one, which the shader relies on
+fn normalize_weights(weights: [f32; 4]) -> [f32; 4] {
+    let sum: f32 = weights.iter().sum();
+    if sum > 0.0 {
+        [weights[0] / sum, weights[1] / sum, weights[2] / sum, weights[3] / sum]
+    } else {
+        [1.0, 0.0, 0.0, 0.0]
+    }
+}
@@ -0,0 +1,373 @@
+use crate::culling::Frustum;
+use crate::pipeline_cache::PipelineCache;
+use crate::upload::StagingBelt;
+use iced_wgpu::wgpu;
+use std::ops::Range;
+
+/// Size of one instance in the instance data, laid out like `InstanceRaw`: a model
+/// matrix, a vec4 and a float padded to 16 bytes. The compute shader copies instances
+/// with this layout.
+const INSTANCE_SIZE: wgpu::BufferAddress = 96;
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Arguments of one `draw_indexed_indirect` call, as the gpu reads them.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct DrawIndexedIndirect {
+    index_count: u32,
+    instance_count: u32,
+    first_index: u32,
+    base_vertex: i32,
+    first_instance: u32,
+}
+
+unsafe impl bytemuck::Pod for DrawIndexedIndirect {}
+unsafe impl bytemuck::Zeroable for DrawIndexedIndirect {}
+
+const DRAW_INDIRECT_SIZE: wgpu::BufferAddress =
+    std::mem::size_of::<DrawIndexedIndirect>() as wgpu::BufferAddress;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct CullUniforms {
+    planes: [[f32; 4]; 6],
+    radius: f32,
+    instance_count: u32,
+    _padding: [u32; 2],
+}
+
+unsafe impl bytemuck::Pod for CullUniforms {}
+unsafe impl bytemuck::Zeroable for CullUniforms {}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CullingMode {
+    /// Instances are culled and compacted on the cpu, one draw per material run per view
+    Cpu,
+    /// Instances are culled by a compute shader and drawn indirectly, see `GpuCulling`
+    Gpu,
+}
+
+impl CullingMode {
+    pub fn toggle(&self) -> Self {
+        match self {
+            CullingMode::Cpu => CullingMode::Gpu,
+            CullingMode::Gpu => CullingMode::Cpu,
+        }
+    }
+}
+
+/// Instances of one material, drawn with a single indirect draw.
+#[derive(Copy, Clone, Debug)]
+pub struct IndirectBatch {
+    pub material: usize,
+    /// Start of the batch's region in the visible instance buffer
+    pub first_instance: u32,
+    /// Size of the region, how many instances the batch has before culling
+    pub instance_count: u32,
+}
+
+/// Frustum culling in a compute shader.
+///
+/// All instances live in a storage buffer on the gpu. Every frame the compute shader
+/// tests them against a view's frustum, appends the visible ones to the region of their
+/// batch in the view's `CullTarget` and counts them in the batch's indirect draw
+/// arguments. The cpu then issues one `draw_indexed_indirect` per batch, however many
+/// instances there are.
+///
+/// The gpu can't sort, so transparent batches come out in no particular order. They
+/// look right with OIT, sorted blending needs the cpu path. Level of detail selection
+/// needs the cpu path too, every instance is drawn with the same indices.
+pub struct GpuCulling {
+    pipeline: wgpu::ComputePipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    instances: wgpu::Buffer,
+    /// Batch index of every instance
+    batch_ids: wgpu::Buffer,
+    instance_count: u32,
+    /// How many instances the buffers have room for
+    instance_capacity: u32,
+    batches: Vec<IndirectBatch>,
+    /// Indices every instance is drawn with
+    indices: Range<u32>,
+    /// Bumped whenever the buffers are recreated, targets rebuild their bind group when
+    /// it changes
+    generation: u64,
+}
+
+impl GpuCulling {
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        comp_path: &str,
+    ) -> Self {
+        let storage = |binding, readonly| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty: wgpu::BindingType::StorageBuffer {
+                dynamic: false,
+                readonly,
+            },
+        };
+        let bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::COMPUTE,
+                        ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                    },
+                    storage(1, true),
+                    storage(2, true),
+                    storage(3, false),
+                    storage(4, false),
+                ],
+                label: Some("gpu_cull_bind_group_layout"),
+            });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts: &[&bind_group_layout],
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            layout: &layout,
+            compute_stage: wgpu::ProgrammableStageDescriptor {
+                module: pipeline_cache.shader_module(device, comp_path),
+                entry_point: "main",
+            },
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            instances: create_buffer(device, INSTANCE_SIZE, input_usage(), "gpu_cull_instances"),
+            batch_ids: create_buffer(device, 4, input_usage(), "gpu_cull_batch_ids"),
+            instance_count: 0,
+            instance_capacity: 1,
+            batches: Vec::new(),
+            indices: 0..0,
+            generation: 0,
+        }
+    }
+
+    pub fn batches(&self) -> &[IndirectBatch] {
+        &self.batches
+    }
+
+    /// Uploads the instances to cull. They have to be grouped by batch, `batch_ids` has
+    /// the batch of every instance and `batches` the runs they form.
+    #[allow(clippy::too_many_arguments)]
+    pub fn set_instances(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+        instances: &[u8],
+        batch_ids: &[u32],
+        batches: Vec<IndirectBatch>,
+        indices: Range<u32>,
+    ) {
+        let size = instances.len() as wgpu::BufferAddress;
+        assert_eq!(size % INSTANCE_SIZE, 0, "instance data must be a multiple of 96 bytes");
+        assert_eq!(size / INSTANCE_SIZE, batch_ids.len() as wgpu::BufferAddress);
+
+        let count = batch_ids.len() as u32;
+        if count > self.instance_capacity {
+            let capacity = count.next_power_of_two();
+            self.instances = create_buffer(
+                device,
+                capacity as wgpu::BufferAddress * INSTANCE_SIZE,
+                input_usage(),
+                "gpu_cull_instances",
+            );
+            self.batch_ids = create_buffer(
+                device,
+                capacity as wgpu::BufferAddress * 4,
+                input_usage(),
+                "gpu_cull_batch_ids",
+            );
+            self.instance_capacity = capacity;
+            self.generation += 1;
+        }
+        if count > 0 {
+            belt.write_buffer(device, encoder, &self.instances, 0, instances);
+            belt.write_buffer(device, encoder, &self.batch_ids, 0, bytemuck::cast_slice(batch_ids));
+        }
+
+        self.instance_count = batch_ids.len() as u32;
+        self.batches = batches;
+        self.indices = indices;
+    }
+
+    /// Records the culling of all instances against `frustum` into `target`. Draw with
+    /// `draw` in a pass recorded after this one.
+    pub fn cull(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+        target: &mut CullTarget,
+        frustum: &Frustum,
+        radius: f32,
+    ) {
+        if self.batches.is_empty() {
+            return;
+        }
+        target.prepare(device, self);
+
+        let mut planes = [[0.0; 4]; 6];
+        for (plane, out) in frustum.planes.iter().zip(planes.iter_mut()) {
+            *out = plane.normal.extend(plane.distance).into();
+        }
+        let uniforms = CullUniforms {
+            planes,
+            radius,
+            instance_count: self.instance_count,
+            _padding: [0; 2],
+        };
+        belt.write_buffer(
+            device,
+            encoder,
+            &target.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[uniforms]),
+        );
+
+        // Counts start at zero every frame, the shader adds the visible instances
+        let draws = self
+            .batches
+            .iter()
+            .map(|batch| DrawIndexedIndirect {
+                index_count: self.indices.end - self.indices.start,
+                instance_count: 0,
+                first_index: self.indices.start,
+                base_vertex: 0,
+                first_instance: batch.first_instance,
+            })
+            .collect::<Vec<_>>();
+        belt.write_buffer(device, encoder, &target.draws, 0, bytemuck::cast_slice(&draws));
+
+        let mut compute_pass = encoder.begin_compute_pass();
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, target.bind_group.as_ref().unwrap(), &[]);
+        compute_pass.dispatch((self.instance_count + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE, 1, 1);
+    }
+
+    /// Draws batch `batch` of the instances `target` culled. The visible instances have to
+    /// be bound as instance buffer, see `CullTarget::visible`.
+    pub fn draw<'a>(
+        &self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        target: &'a CullTarget,
+        batch: usize,
+    ) {
+        let offset = batch as wgpu::BufferAddress * DRAW_INDIRECT_SIZE;
+        render_pass.draw_indexed_indirect(&target.draws, offset);
+    }
+}
+
+/// Per view output of `GpuCulling`: the compacted visible instances and the indirect
+/// draw arguments.
+pub struct CullTarget {
+    uniform_buffer: wgpu::Buffer,
+    /// Visible instances, bind it as the instance vertex buffer
+    pub visible: wgpu::Buffer,
+    visible_size: wgpu::BufferAddress,
+    draws: wgpu::Buffer,
+    draws_size: wgpu::BufferAddress,
+    bind_group: Option<wgpu::BindGroup>,
+    generation: u64,
+}
+
+impl CullTarget {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            uniform_buffer: create_buffer(
+                device,
+                std::mem::size_of::<CullUniforms>() as wgpu::BufferAddress,
+                wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+                "gpu_cull_uniforms",
+            ),
+            visible: create_buffer(device, INSTANCE_SIZE, visible_usage(), "gpu_cull_visible"),
+            visible_size: INSTANCE_SIZE,
+            draws: create_buffer(device, DRAW_INDIRECT_SIZE, draws_usage(), "gpu_cull_draws"),
+            draws_size: DRAW_INDIRECT_SIZE,
+            bind_group: None,
+            generation: 0,
+        }
+    }
+
+    /// Grows the buffers to fit the culling's instances and rebuilds the bind group when
+    /// any of the buffers changed.
+    fn prepare(&mut self, device: &wgpu::Device, culling: &GpuCulling) {
+        let mut changed = self.bind_group.is_none() || self.generation != culling.generation;
+
+        let visible_size = culling.instance_capacity as wgpu::BufferAddress * INSTANCE_SIZE;
+        if visible_size > self.visible_size {
+            self.visible = create_buffer(device, visible_size, visible_usage(), "gpu_cull_visible");
+            self.visible_size = visible_size;
+            changed = true;
+        }
+        let draws_size = culling.batches.len() as wgpu::BufferAddress * DRAW_INDIRECT_SIZE;
+        if draws_size > self.draws_size {
+            self.draws = create_buffer(device, draws_size, draws_usage(), "gpu_cull_draws");
+            self.draws_size = draws_size;
+            changed = true;
+        }
+        if !changed {
+            return;
+        }
+
+        // Whole buffers are bound, the shader only looks at the first `instance_count`
+        // instances so the bind group survives instance count changes
+        let capacity = culling.instance_capacity as wgpu::BufferAddress;
+        self.bind_group = Some(device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &culling.bind_group_layout,
+            bindings: &[
+                buffer_binding(
+                    0,
+                    &self.uniform_buffer,
+                    std::mem::size_of::<CullUniforms>() as wgpu::BufferAddress,
+                ),
+                buffer_binding(1, &culling.instances, capacity * INSTANCE_SIZE),
+                buffer_binding(2, &culling.batch_ids, capacity * 4),
+                buffer_binding(3, &self.visible, self.visible_size),
+                buffer_binding(4, &self.draws, self.draws_size),
+            ],
+            label: Some("gpu_cull_bind_group"),
+        }));
+        self.generation = culling.generation;
+    }
+}
+
+fn buffer_binding(binding: u32, buffer: &wgpu::Buffer, size: wgpu::BufferAddress) -> wgpu::Binding {
+    wgpu::Binding {
+        binding,
+        resource: wgpu::BindingResource::Buffer {
+            buffer,
+            range: 0..size,
+        },
+    }
+}
+
+fn input_usage() -> wgpu::BufferUsage {
+    wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST
+}
+
+fn visible_usage() -> wgpu::BufferUsage {
+    wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::STORAGE
+}
+
+fn draws_usage() -> wgpu::BufferUsage {
+    wgpu::BufferUsage::INDIRECT | wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST
+}
+
+fn create_buffer(
+    device: &wgpu::Device,
+    size: wgpu::BufferAddress,
+    usage: wgpu::BufferUsage,
+    label: &str,
+) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size,
+        usage,
+    })
+}
@@ -0,0 +1,183 @@
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+
+/// One level of detail of a `LodMesh`, a range of its indices.
+#[derive(Clone, Debug)]
+pub struct LodLevel {
+    pub indices: Range<u32>,
+    /// How far the level's vertices are from where they are at full detail, in model
+    /// units. Selection projects it to the screen to see whether the difference shows.
+    pub error: f32,
+}
+
+/// A mesh with several levels of detail, finest first. Every level indexes the same
+/// vertices, coarser levels use fewer of them, so one vertex and one index buffer hold
+/// all levels.
+pub struct LodMesh {
+    pub indices: Vec<u32>,
+    pub levels: Vec<LodLevel>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct LodSettings {
+    /// Largest error on screen, in pixels, a level may have to be picked
+    pub threshold: f32,
+    /// Cross-fade to the next coarser level while its error is below
+    /// `threshold * (1 + fade_band)`, 0 switches levels without fading.
+    pub fade_band: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            threshold: 2.0,
+            fade_band: 0.5,
+        }
+    }
+}
+
+/// The level an instance is drawn with.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct LodSelection {
+    pub level: usize,
+    /// Coarser level fading in and how far along the fade is, in `0..1`
+    pub fade_to: Option<(usize, f32)>,
+}
+
+impl LodMesh {
+    /// Authored levels, finest first, each with its error.
+    pub fn from_levels(levels: &[(&[u32], f32)]) -> Self {
+        let mut mesh = LodMesh {
+            indices: Vec::new(),
+            levels: Vec::new(),
+        };
+        for (indices, error) in levels {
+            mesh.push_level(indices, *error);
+        }
+        mesh
+    }
+
+    /// `indices` at full detail plus up to `extra_levels` levels made by `simplify`, the
+    /// cluster size starts at `cell_size` and doubles every level. Stops early once a
+    /// level would lose every triangle.
+    pub fn generate(
+        positions: &[[f32; 3]],
+        indices: &[u32],
+        extra_levels: usize,
+        cell_size: f32,
+    ) -> Self {
+        let mut mesh = LodMesh::from_levels(&[(indices, 0.0)]);
+        let mut cell_size = cell_size;
+        for _ in 0..extra_levels {
+            let (simplified, error) = simplify(positions, indices, cell_size);
+            cell_size *= 2.0;
+
+            let previous = mesh.levels.last().unwrap();
+            let previous_count = previous.indices.end - previous.indices.start;
+            if simplified.is_empty() {
+                break;
+            }
+            // Clusters too small to merge anything, try again with bigger ones
+            if simplified.len() as u32 >= previous_count {
+                continue;
+            }
+            // Errors have to grow with the level for selection to work
+            let error = error.max(previous.error);
+            mesh.push_level(&simplified, error);
+        }
+        mesh
+    }
+
+    fn push_level(&mut self, indices: &[u32], error: f32) {
+        let start = self.indices.len() as u32;
+        self.indices.extend_from_slice(indices);
+        self.levels.push(LodLevel {
+            indices: start..self.indices.len() as u32,
+            error,
+        });
+    }
+
+    /// Picks the coarsest level whose error covers at most `settings.threshold` pixels.
+    /// `pixels_per_unit` is the size of one model unit on screen where the instance is,
+    /// see `Projection::pixels_per_unit`.
+    pub fn select(&self, pixels_per_unit: f32, settings: &LodSettings) -> LodSelection {
+        let projected = |level: &LodLevel| level.error * pixels_per_unit;
+        let level = self
+            .levels
+            .iter()
+            .rposition(|level| projected(level) <= settings.threshold)
+            .unwrap_or(0);
+
+        let fade_to = self.levels.get(level + 1).and_then(|next| {
+            let band = settings.threshold * settings.fade_band;
+            let over = projected(next) - settings.threshold;
+            if band > 0.0 && over < band {
+                Some((level + 1, 1.0 - over / band))
+            } else {
+                None
+            }
+        });
+
+        LodSelection { level, fade_to }
+    }
+}
+
+/// Simplifies a triangle list by vertex clustering: vertices in the same cell of a grid
+/// with `cell_size` cells merge into the one closest to the cell's center, triangles
+/// that collapse are dropped. Only indices change, the vertices stay as they are.
+///
+/// Returns the new indices and the furthest any vertex moved. Fast and robust, but it
+/// doesn't preserve features, sharp edges smaller than a cell get rounded off.
+pub fn simplify(positions: &[[f32; 3]], indices: &[u32], cell_size: f32) -> (Vec<u32>, f32) {
+    let cell = |position: [f32; 3]| {
+        (
+            (position[0] / cell_size).floor() as i32,
+            (position[1] / cell_size).floor() as i32,
+            (position[2] / cell_size).floor() as i32,
+        )
+    };
+    let distance2 = |a: [f32; 3], b: [f32; 3]| {
+        (a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)
+    };
+
+    let mut representatives: HashMap<(i32, i32, i32), (u32, f32)> = HashMap::new();
+    for &index in indices {
+        let position = positions[index as usize];
+        let key = cell(position);
+        let center = [
+            (key.0 as f32 + 0.5) * cell_size,
+            (key.1 as f32 + 0.5) * cell_size,
+            (key.2 as f32 + 0.5) * cell_size,
+        ];
+        let distance = distance2(position, center);
+        let representative = representatives.entry(key).or_insert((index, distance));
+        if distance < representative.1 {
+            *representative = (index, distance);
+        }
+    }
+
+    let mut error: f32 = 0.0;
+    let mut remap = |index: u32| {
+        let position = positions[index as usize];
+        let representative = representatives[&cell(position)].0;
+        error = error.max(distance2(position, positions[representative as usize]));
+        representative
+    };
+
+    let mut seen = HashSet::new();
+    let mut simplified = Vec::new();
+    for triangle in indices.chunks(3) {
+        let (a, b, c) = (remap(triangle[0]), remap(triangle[1]), remap(triangle[2]));
+        if a == b || b == c || a == c {
+            continue;
+        }
+        // The same triangle can come out of several clusters, keep one
+        let mut key = [a, b, c];
+        key.sort_unstable();
+        if seen.insert(key) {
+            simplified.extend_from_slice(&[a, b, c]);
+        }
+    }
+
+    (simplified, error.sqrt())
+}
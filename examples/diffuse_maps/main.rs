@@ -1,10 +1,41 @@
+mod animation;
+mod animation_clip;
+mod camera;
 mod camera_controller;
+mod culling;
+mod debug_draw;
+mod fps_camera_controller;
+mod gltf_model;
+mod gpu_culling;
+mod lod;
+mod mesh;
+mod morph;
+mod orbit_camera_controller;
+mod particles;
+mod primitives;
+mod skeleton;
+mod skinning;
+mod material;
+mod oit;
+mod pipeline_cache;
+mod text;
 mod texture;
-
-use camera_controller::CameraController;
+mod timing;
+mod upload;
+mod viewport;
+
+use animation::{Animation, AnimationPlayer, AnimationTarget, Track, TrackValues};
+use animation_clip::{Interpolation, Playback};
+use camera::{Camera, Projection};
+use camera_controller::{CameraController, KeyboardCameraController};
+use fps_camera_controller::FpsCameraController;
+use gltf_model::GltfModel;
+use gpu_culling::{CullTarget, CullingMode, GpuCulling, IndirectBatch};
+use lod::{LodMesh, LodSettings};
 use cgmath;
 use cgmath::prelude::*;
-use cgmath::Rotation;
+use culling::{Aabb, BoundingSphere, Frustum};
+use debug_draw::{DebugDraw, DepthMode};
 use iced_wgpu::wgpu;
 use iced_winit::winit;
 use iced_winit::winit::{
@@ -12,23 +43,38 @@ use iced_winit::winit::{
     event_loop::{ControlFlow, EventLoop},
     window::Window,
 };
+use material::{BlendMode, Material};
+use mesh::{Indices, Mesh, MorphTarget};
+use morph::MorphTargets;
+use oit::{TransparencyMode, WeightedBlendedOit};
+use orbit_camera_controller::OrbitCameraController;
+use particles::{Emitter, EmitterShape, ParticleBlend, ParticleSystem};
+use pipeline_cache::{PipelineCache, PipelineKey};
+use skinning::{SkinnedModel, SkinnedVertex};
 use std::fs;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::Write;
+use std::ops::Range;
+use text::{GlyphMode, HorizontalAlign, TextRenderer, TextStyle, VerticalAlign};
+use timing::{AppLoop, FrameStats, FrameTime};
+use upload::{RingBuffer, StagingBelt};
+use viewport::{Viewport, ViewportRect};
 
 const NUM_INSTANCES_PER_ROW: u32 = 10;
-const NUM_INSTANCES: u32 = NUM_INSTANCES_PER_ROW * NUM_INSTANCES_PER_ROW;
 const INSTANCE_DISPLACEMENT: cgmath::Vector3<f32> = cgmath::Vector3::new(NUM_INSTANCES_PER_ROW as f32 * 0.5, 0.0, NUM_INSTANCES_PER_ROW as f32 * 0.5);
 
-const INDICES: &[u16] = &[0, 1, 4, 1, 2, 4, 2, 3, 4];
+const INDICES: &[u32] = &[0, 1, 4, 1, 2, 4, 2, 3, 4];
+
+/// Size of the texture the security camera renders into
+const MONITOR_SIZE: u32 = 512;
+/// Index of the material showing the security camera, after the three diffuse materials
+const MONITOR_MATERIAL: usize = 4;
 
-#[cfg_attr(rustfmt, rustfmt_skip)]
-pub const OPENGL_TO_WGPU_MATRIX: cgmath::Matrix4<f32> = cgmath::Matrix4::new(
-    1.0, 0.0, 0.0, 0.0,
-    0.0, 1.0, 0.0, 0.0,
-    0.0, 0.0, 0.5, 0.0,
-    0.0, 0.0, 0.5, 1.0,
-);
+const STAGING_CHUNK_SIZE: wgpu::BufferAddress = 64 * 1024;
+/// Room for a few frames worth of uniforms per view, every allocation takes a whole 256
+/// byte slot
+const UNIFORM_RING_SIZE: wgpu::BufferAddress = 16 * wgpu::BIND_BUFFER_ALIGNMENT;
+const INSTANCE_RING_SIZE: wgpu::BufferAddress = 1024 * 1024;
 
 trait VBDesc {
     fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a>;
@@ -39,6 +85,8 @@ trait VBDesc {
 struct Vertex {
     position: [f32; 3],
     tex_coords: [f32; 2],
+    normal: [f32; 3],
+    tangent: [f32; 4],
 }
 
 unsafe impl bytemuck::Pod for Vertex {}
@@ -61,33 +109,373 @@ impl VBDesc for Vertex {
                     shader_location: 1,
                     format: wgpu::VertexFormat::Float2,
                 },
+                // After the instance attributes, the shaders don't light anything yet
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 5]>() as wgpu::BufferAddress,
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: mem::size_of::<[f32; 8]>() as wgpu::BufferAddress,
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float4,
+                },
             ],
         }
     }
 }
 
+impl Vertex {
+    fn from_mesh(mesh: &Mesh) -> Vec<Vertex> {
+        (0..mesh.vertex_count())
+            .map(|i| Vertex {
+                position: mesh.positions[i],
+                tex_coords: mesh.tex_coords[i],
+                normal: mesh.normals[i],
+                tangent: mesh.tangents[i],
+            })
+            .collect()
+    }
+}
+
+/// Meshes the M key cycles through, the hand made pentagon and the generated primitives.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Shape {
+    Pentagon,
+    Plane,
+    Cube,
+    UvSphere,
+    Icosphere,
+    Cylinder,
+    Cone,
+    Torus,
+    Capsule,
+    /// More vertices than 16 bit indices can reach
+    DenseSphere,
+}
+
+impl Shape {
+    fn next(self) -> Self {
+        match self {
+            Shape::Pentagon => Shape::Plane,
+            Shape::Plane => Shape::Cube,
+            Shape::Cube => Shape::UvSphere,
+            Shape::UvSphere => Shape::Icosphere,
+            Shape::Icosphere => Shape::Cylinder,
+            Shape::Cylinder => Shape::Cone,
+            Shape::Cone => Shape::Torus,
+            Shape::Torus => Shape::Capsule,
+            Shape::Capsule => Shape::DenseSphere,
+            Shape::DenseSphere => Shape::Pentagon,
+        }
+    }
+
+    /// All about a unit across, like the pentagon, so the lod cell sizes suit them all
+    fn mesh(self) -> Mesh {
+        let mut mesh = match self {
+            Shape::Pentagon => pentagon(),
+            Shape::Plane => primitives::plane(1.0, 1.0, 4),
+            Shape::Cube => primitives::cube(0.8),
+            Shape::UvSphere => primitives::uv_sphere(0.5, 32, 16),
+            Shape::Icosphere => primitives::icosphere(0.5, 3),
+            Shape::Cylinder => primitives::cylinder(0.4, 1.0, 32),
+            Shape::Cone => primitives::cone(0.5, 1.0, 32),
+            Shape::Torus => primitives::torus(0.35, 0.15, 32, 16),
+            Shape::Capsule => primitives::capsule(0.3, 0.4, 32, 8),
+            Shape::DenseSphere => primitives::uv_sphere(0.5, 256, 256),
+        };
+        let puff = puff_target(&mesh, 0.15);
+        mesh.morph_targets.push(puff);
+        mesh
+    }
+}
+
+/// Pushes every vertex away from the model origin. Vertices split at seams share their
+/// position and so their delta, the mesh doesn't crack open.
+fn puff_target(mesh: &Mesh, distance: f32) -> MorphTarget {
+    let position_deltas = mesh
+        .positions
+        .iter()
+        .map(|&position| {
+            let position = cgmath::Vector3::from(position);
+            if position.is_zero() {
+                [0.0; 3]
+            } else {
+                (position.normalize() * distance).into()
+            }
+        })
+        .collect();
+    MorphTarget {
+        name: "puff".to_string(),
+        position_deltas,
+        normal_deltas: Vec::new(),
+    }
+}
+
+fn pentagon() -> Mesh {
+    let mut mesh = Mesh::new(
+        vec![
+            [-0.0868241, 0.49240386, 0.0],   // A
+            [-0.49513406, 0.06958647, 0.0],  // B
+            [-0.21918549, -0.44939706, 0.0], // C
+            [0.35966998, -0.3473291, 0.0],   // D
+            [0.44147372, 0.2347359, 0.0],    // E
+        ],
+        vec![
+            [0.4131759, 0.00759614],
+            [0.0048659444, 0.43041354],
+            [0.28081453, 0.949397057],
+            [0.85967, 0.84732911],
+            [0.9414737, 0.2652641],
+        ],
+        INDICES.to_vec(),
+    );
+    mesh.weld(0.0);
+    mesh.compute_normals(cgmath::Deg(180.0));
+    primitives::finish(mesh)
+}
+
+/// Vertex and index buffer of a mesh, the format of its indices, its levels of detail, its
+/// bounds and its morph targets with a row of weights for every instance of the grid.
+fn create_mesh_buffers(
+    device: &wgpu::Device,
+    morph_layout: &wgpu::BindGroupLayout,
+    mesh: &Mesh,
+) -> (wgpu::Buffer, wgpu::Buffer, wgpu::IndexFormat, LodMesh, BoundingSphere, MorphTargets) {
+    let vertices = Vertex::from_mesh(mesh);
+    let vertex_buffer = device
+        .create_buffer_with_data(bytemuck::cast_slice(&vertices), wgpu::BufferUsage::VERTEX);
+    let lod_mesh = LodMesh::generate(&mesh.positions, &mesh.indices, 2, 0.25);
+    let indices = Indices::new(&lod_mesh.indices, mesh.vertex_count());
+    let index_buffer =
+        device.create_buffer_with_data(indices.as_bytes(), wgpu::BufferUsage::INDEX);
+    let bounds = Aabb::from_points(mesh.positions.iter().map(|&position| position.into()))
+        .origin_sphere();
+    let instance_count = (NUM_INSTANCES_PER_ROW * NUM_INSTANCES_PER_ROW) as usize;
+    let morph_targets = MorphTargets::new(
+        device,
+        morph_layout,
+        &mesh.morph_targets,
+        &[],
+        mesh.vertex_count(),
+        instance_count,
+    );
+    (vertex_buffer, index_buffer, indices.format(), lod_mesh, bounds, morph_targets)
+}
+
 struct Instance {
     position: cgmath::Vector3<f32>,
     rotation: cgmath::Quaternion<f32>,
+    scale: cgmath::Vector3<f32>,
+    material: usize,
+    /// Model space axis the instance spins around on the gpu
+    spin_axis: cgmath::Vector3<f32>,
+    /// Angular speed of the spin, per second
+    spin_speed: cgmath::Rad<f32>,
+    /// Row of the instance's weights in the mesh's `MorphTargets`
+    morph_instance: u32,
+    /// Weights of the mesh's morph targets, empty leaves the mesh at rest
+    morph_weights: Vec<f32>,
 }
 
 impl Instance {
     fn to_raw(&self) -> InstanceRaw {
+        let model = cgmath::Matrix4::from_translation(self.position)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z);
         InstanceRaw {
-            model: cgmath::Matrix4::from_translation(self.position) * cgmath::Matrix4::from(self.rotation),
+            model,
+            spin: self.spin_axis.extend(self.spin_speed.0),
+            lod_fade: 1.0,
+            morph_instance: self.morph_instance,
+            _padding: [0.0; 2],
+        }
+    }
+}
+
+/// The scene as animations see it, nodes are instances.
+struct SceneTarget<'a> {
+    instances: &'a mut [Instance],
+    materials: &'a mut [Material],
+    /// Whether any instance moved, gpu culling then needs them again
+    instances_changed: bool,
+    morph_weights_changed: bool,
+}
+
+impl AnimationTarget for SceneTarget<'_> {
+    fn set_translation(&mut self, node: usize, translation: cgmath::Vector3<f32>) {
+        if let Some(instance) = self.instances.get_mut(node) {
+            instance.position = translation;
+            self.instances_changed = true;
+        }
+    }
+
+    fn set_rotation(&mut self, node: usize, rotation: cgmath::Quaternion<f32>) {
+        if let Some(instance) = self.instances.get_mut(node) {
+            instance.rotation = rotation;
+            self.instances_changed = true;
         }
     }
+
+    fn set_scale(&mut self, node: usize, scale: cgmath::Vector3<f32>) {
+        if let Some(instance) = self.instances.get_mut(node) {
+            instance.scale = scale;
+            self.instances_changed = true;
+        }
+    }
+
+    fn set_morph_weight(&mut self, node: usize, morph_target: usize, weight: f32) {
+        if let Some(instance) = self.instances.get_mut(node) {
+            if instance.morph_weights.len() <= morph_target {
+                instance.morph_weights.resize(morph_target + 1, 0.0);
+            }
+            instance.morph_weights[morph_target] = weight;
+            self.morph_weights_changed = true;
+        }
+    }
+
+    fn set_tint(&mut self, material: usize, tint: cgmath::Vector4<f32>) {
+        if let Some(material) = self.materials.get_mut(material) {
+            material.params.tint = tint.into();
+        }
+    }
+}
+
+/// An instance in the middle of the grid bobbing up and down and growing at the top, its
+/// neighbours turning around once, again on R, and puffing up, and the opaque material
+/// pulsing red.
+fn demo_animations(instances: &[Instance]) -> Vec<AnimationPlayer> {
+    let row = NUM_INSTANCES_PER_ROW as usize;
+    let node = row * row / 2 + row / 2;
+    let position = instances[node].position;
+    let bob = Animation::new(
+        "bob",
+        vec![
+            Track::new(
+                vec![0.0, 1.0],
+                Interpolation::EaseInOut,
+                TrackValues::Translations {
+                    node,
+                    values: vec![position, position + cgmath::Vector3::unit_y()],
+                },
+            ),
+            Track::new(
+                vec![0.5, 1.0],
+                Interpolation::EaseOut,
+                TrackValues::Scales {
+                    node,
+                    values: vec![
+                        cgmath::Vector3::new(1.0, 1.0, 1.0),
+                        cgmath::Vector3::new(1.5, 1.5, 1.5),
+                    ],
+                },
+            ),
+        ],
+    );
+    // Thirds of a turn, keyframes half a turn apart could go either way round
+    let neighbour = node + 1;
+    let rotation = instances[neighbour].rotation;
+    let turn = Animation::new(
+        "turn",
+        vec![Track::new(
+            vec![0.0, 0.5, 1.0, 1.5],
+            Interpolation::Linear,
+            TrackValues::Rotations {
+                node: neighbour,
+                values: [0.0, 120.0, 240.0, 360.0]
+                    .iter()
+                    .map(|&angle| rotation * cgmath::Quaternion::from_angle_y(cgmath::Deg(angle)))
+                    .collect(),
+            },
+        )],
+    );
+    let puff = Animation::new(
+        "puff",
+        vec![Track::new(
+            vec![0.0, 0.75, 1.5],
+            Interpolation::EaseInOut,
+            TrackValues::MorphWeights {
+                node: node - 1,
+                targets: vec![vec![0.0, 1.0, 0.0]],
+            },
+        )],
+    );
+    let white = cgmath::Vector4::new(1.0, 1.0, 1.0, 1.0);
+    let pulse = Animation::new(
+        "pulse",
+        vec![Track::new(
+            vec![0.0, 1.5, 3.0],
+            Interpolation::EaseIn,
+            TrackValues::Tints {
+                material: 2,
+                values: vec![white, cgmath::Vector4::new(1.0, 0.3, 0.3, 1.0), white],
+            },
+        )],
+    );
+    vec![
+        AnimationPlayer::new(bob, Playback::PingPong),
+        AnimationPlayer::new(turn, Playback::Once),
+        AnimationPlayer::new(puff, Playback::Loop),
+        AnimationPlayer::new(pulse, Playback::Loop),
+    ]
 }
 
 #[repr(C)]
 #[derive(Copy, Clone)]
 struct InstanceRaw {
     model: cgmath::Matrix4<f32>,
+    /// xyz is the spin axis, w the angular speed in radians per second
+    spin: cgmath::Vector4<f32>,
+    /// Dithered cross-fade between lod levels, 1 draws every pixel
+    lod_fade: f32,
+    /// See `Instance::morph_instance`
+    morph_instance: u32,
+    _padding: [f32; 2],
 }
 
 unsafe impl bytemuck::Pod for InstanceRaw {}
 unsafe impl bytemuck::Zeroable for InstanceRaw {}
 
+/// A run of consecutive instances in the instance buffer sharing material and lod level.
+struct DrawBatch {
+    material: usize,
+    lod: usize,
+    instances: Range<u32>,
+}
+
+/// A visible instance and the lod level to draw it with. Cross-fading instances are
+/// drawn twice, once per level.
+struct LodInstance<'a> {
+    instance: &'a Instance,
+    lod: usize,
+    fade: f32,
+}
+
+impl LodInstance<'_> {
+    fn to_raw(&self) -> InstanceRaw {
+        InstanceRaw {
+            lod_fade: self.fade,
+            ..self.instance.to_raw()
+        }
+    }
+}
+
+/// What one view draws, only the instances inside its frustum.
+struct ViewBatches {
+    opaque: Vec<DrawBatch>,
+    transparent: Vec<DrawBatch>,
+}
+
+impl ViewBatches {
+    fn instance_count(&self) -> u32 {
+        self.opaque
+            .iter()
+            .chain(&self.transparent)
+            .map(|batch| batch.instances.end - batch.instances.start)
+            .sum()
+    }
+}
+
 const FLOAT_SIZE: wgpu::BufferAddress = std::mem::size_of::<f32>() as wgpu::BufferAddress;
 impl VBDesc for InstanceRaw {
     fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
@@ -115,6 +503,21 @@ impl VBDesc for InstanceRaw {
                     format: wgpu::VertexFormat::Float4,
                     shader_location: 5,
                 },
+                wgpu::VertexAttributeDescriptor {
+                    offset: FLOAT_SIZE * 4 * 4,
+                    format: wgpu::VertexFormat::Float4,
+                    shader_location: 6,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: FLOAT_SIZE * 4 * 5,
+                    format: wgpu::VertexFormat::Float,
+                    shader_location: 7,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: FLOAT_SIZE * (4 * 5 + 1),
+                    format: wgpu::VertexFormat::Uint,
+                    shader_location: 12,
+                },
             ]
         }
     }
@@ -125,6 +528,14 @@ impl VBDesc for InstanceRaw {
 #[derive(Copy, Clone)] // This is so we can store this in a buffer
 struct Uniforms {
     view_proj: cgmath::Matrix4<f32>,
+    /// Seconds since start, drives the instance animation
+    time: f32,
+    /// 1 when the projection maps near to depth 1 and far to 0, see `Projection::is_reverse_z`
+    reverse_z: f32,
+    _padding: [f32; 2],
+    /// World space directions of the screen's x and y, for camera facing quads
+    camera_right: cgmath::Vector4<f32>,
+    camera_up: cgmath::Vector4<f32>,
 }
 
 unsafe impl bytemuck::Pod for Uniforms {}
@@ -134,83 +545,233 @@ impl Uniforms {
     fn new() -> Self {
         Self {
             view_proj: cgmath::Matrix4::identity(),
+            time: 0.0,
+            reverse_z: 0.0,
+            _padding: [0.0; 2],
+            camera_right: cgmath::Vector4::unit_x(),
+            camera_up: cgmath::Vector4::unit_y(),
         }
     }
 
     fn update_view_proj(&mut self, camera: &Camera) {
         self.view_proj = camera.build_view_projection_matrix();
+        self.reverse_z = if camera.projection.is_reverse_z() { 1.0 } else { 0.0 };
+        let forward = (camera.target - camera.eye).normalize();
+        let right = forward.cross(camera.up).normalize();
+        self.camera_right = right.extend(0.0);
+        self.camera_up = right.cross(forward).extend(0.0);
     }
 }
 
-pub struct Camera {
-    eye: cgmath::Point3<f32>,
-    target: cgmath::Point3<f32>,
-    up: cgmath::Vector3<f32>,
-    aspect: f32,
-    fovy: f32,
-    znear: f32,
-    zfar: f32,
+/// Where a view gets its camera from.
+enum ViewCamera {
+    /// The camera moved by the active camera controller
+    Main,
+    Fixed(Camera),
 }
 
-impl Camera {
-    fn build_view_projection_matrix(&self) -> cgmath::Matrix4<f32> {
-        let view = cgmath::Matrix4::look_at(self.eye, self.target, self.up);
-        let proj = cgmath::perspective(cgmath::Deg(self.fovy), self.aspect, self.znear, self.zfar);
-        return OPENGL_TO_WGPU_MATRIX * proj * view;
+/// One camera rendering into one part of the window. Every view has its own uniforms
+/// and bind group, so all views are drawn in the same passes by switching viewport and
+/// bind group in between.
+struct View {
+    viewport: Viewport,
+    camera: ViewCamera,
+    uniforms: Uniforms,
+    uniform_ring: RingBuffer,
+    uniform_offset: wgpu::DynamicOffset,
+    uniform_bind_group: wgpu::BindGroup,
+    /// Where `GpuCulling` puts the instances this view sees
+    cull_target: CullTarget,
+}
+
+impl View {
+    fn new(
+        device: &wgpu::Device,
+        uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        viewport: Viewport,
+        camera: ViewCamera,
+    ) -> Self {
+        let uniform_ring = RingBuffer::uniform(device, UNIFORM_RING_SIZE, "uniform_ring");
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: uniform_bind_group_layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &uniform_ring.buffer,
+                        // The dynamic offset passed to set_bind_group picks the slot of the ring
+                        range: 0..std::mem::size_of::<Uniforms>() as wgpu::BufferAddress,
+                    },
+                }
+            ],
+            label: Some("uniform_bind_group"),
+        });
+
+        Self {
+            viewport,
+            camera,
+            uniforms: Uniforms::new(),
+            uniform_ring,
+            uniform_offset: 0,
+            uniform_bind_group,
+            cull_target: CullTarget::new(device),
+        }
+    }
+
+    /// The camera this view renders with this frame, with the aspect of its viewport
+    fn camera(&self, main_camera: &Camera) -> Camera {
+        let mut camera = match &self.camera {
+            ViewCamera::Main => main_camera.clone(),
+            ViewCamera::Fixed(camera) => camera.clone(),
+        };
+        self.viewport.apply(&mut camera);
+        camera
+    }
+}
+
+/// A view rendered into a texture instead of the window. The texture is bound like any
+/// other diffuse texture, so a material can show it (mirrors, security monitors, minimaps).
+struct OffscreenView {
+    view: View,
+    color: texture::Texture,
+    depth: texture::Texture,
+    /// Material showing `color`. Its instances are skipped when rendering the view itself,
+    /// a texture can't be sampled in the pass that renders to it.
+    material: usize,
+}
+
+/// How the window is split between views. The first view always shows the main camera.
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum ViewLayout {
+    Single,
+    /// The main camera in a custom viewport with a border around it
+    Inset,
+    /// Main camera on the left, an overview of the scene on the right
+    SplitScreen,
+    /// Editor style: main camera plus top, front and side views
+    Quad,
+}
+
+impl ViewLayout {
+    fn next(self) -> Self {
+        match self {
+            ViewLayout::Single => ViewLayout::Inset,
+            ViewLayout::Inset => ViewLayout::SplitScreen,
+            ViewLayout::SplitScreen => ViewLayout::Quad,
+            ViewLayout::Quad => ViewLayout::Single,
+        }
+    }
+
+    fn rects(self) -> Vec<ViewportRect> {
+        let rect = |x, y, width, height| ViewportRect {
+            x,
+            y,
+            width,
+            height,
+        };
+        match self {
+            ViewLayout::Single => vec![ViewportRect::FULL],
+            ViewLayout::Inset => vec![rect(0.1, 0.1, 0.8, 0.6)],
+            ViewLayout::SplitScreen => vec![rect(0.0, 0.0, 0.5, 1.0), rect(0.5, 0.0, 0.5, 1.0)],
+            ViewLayout::Quad => vec![
+                rect(0.0, 0.0, 0.5, 0.5),
+                rect(0.5, 0.0, 0.5, 0.5),
+                rect(0.0, 0.5, 0.5, 0.5),
+                rect(0.5, 0.5, 0.5, 0.5),
+            ],
+        }
+    }
+
+    /// Cameras of the views after the main one. They share the main camera's projection,
+    /// all views are drawn with the same pipelines and depth buffer so they have to agree
+    /// on the depth test.
+    fn fixed_cameras(self, projection: Projection) -> Vec<Camera> {
+        let camera = |eye: (f32, f32, f32), up| Camera {
+            eye: eye.into(),
+            target: (0.0, 0.0, 0.0).into(),
+            up,
+            aspect: 1.0,
+            projection,
+        };
+        match self {
+            ViewLayout::Single | ViewLayout::Inset => Vec::new(),
+            ViewLayout::SplitScreen => vec![camera((0.0, 8.0, 10.0), cgmath::Vector3::unit_y())],
+            ViewLayout::Quad => vec![
+                // Looking straight down, up can't be the y axis
+                camera((0.0, 12.0, 0.0), -cgmath::Vector3::unit_z()),
+                camera((0.0, 0.0, 12.0), cgmath::Vector3::unit_y()),
+                camera((12.0, 0.0, 0.0), cgmath::Vector3::unit_y()),
+            ],
+        }
     }
 }
 
 pub struct State {
-    vertices: [Vertex; 5],
     instances: Vec<Instance>,
     surface: wgpu::Surface,
     device: wgpu::Device,
     queue: wgpu::Queue,
     sc_desc: wgpu::SwapChainDescriptor,
     swap_chain: wgpu::SwapChain,
-    render_pipeline: wgpu::RenderPipeline,
+    pipeline_cache: PipelineCache,
+    depth_texture: texture::Texture,
 
     vertex_buffer: wgpu::Buffer,
-    instance_buffer: wgpu::Buffer,
+    instance_ring: RingBuffer,
+    instance_offset: wgpu::BufferAddress,
     index_buffer: wgpu::Buffer,
-    num_indices: u32,
-
-    diffuse_bind_group: wgpu::BindGroup,
+    /// Levels of detail of the mesh, their indices fill `index_buffer`
+    lod_mesh: LodMesh,
+    lod_settings: LodSettings,
+    /// Bounds of the mesh, around the model origin so the spin on the gpu can't move the
+    /// mesh out of them
+    mesh_sphere: BoundingSphere,
+    /// Vertices transformed per triangle of the mesh, shown with the frame stats
+    mesh_acmr: f32,
+    morph_targets: MorphTargets,
+    /// The instances' morph weights changed, or the mesh, since they were last uploaded
+    morph_weights_dirty: bool,
+    shape: Shape,
+    /// Animated character, only there when a glTF file is passed on the command line
+    skinned_model: Option<SkinnedModel>,
+    animation_players: Vec<AnimationPlayer>,
+    particles: ParticleSystem,
+    debug_draw: DebugDraw,
+    /// Bounds, frusta and a grid drawn over the scene
+    show_debug: bool,
+    /// Labels and the HUD
+    text: TextRenderer,
+
+    materials: Vec<Material>,
+    /// Batches of every view followed by the ones of `monitor`, when culling on the cpu
+    view_batches: Vec<ViewBatches>,
+    culling_mode: CullingMode,
+    gpu_culling: GpuCulling,
+    /// The instances changed since they were last uploaded for gpu culling
+    gpu_instances_dirty: bool,
+    transparency_mode: TransparencyMode,
+    oit: WeightedBlendedOit,
 
     size: winit::dpi::PhysicalSize<u32>,
+    view_layout: ViewLayout,
+    /// Views drawn every frame, the first one shows `camera`
+    views: Vec<View>,
+    /// Security camera feeding the monitor material, rendered before the window views
+    monitor: OffscreenView,
 
     camera: Camera,
-    uniforms: Uniforms,
-    uniform_buffer: wgpu::Buffer,
-    uniform_bind_group: wgpu::BindGroup,
-    camera_controller: CameraController,
+    /// Camera of the previous fixed update, rendering interpolates from it to `camera`
+    previous_camera: Camera,
+    staging_belt: StagingBelt,
+    uniform_bind_group_layout: wgpu::BindGroupLayout,
+    /// Meshes of new shapes bring their own morph targets
+    morph_bind_group_layout: wgpu::BindGroupLayout,
+    camera_controllers: Vec<Box<dyn CameraController>>,
+    active_camera_controller: usize,
 }
 
 impl State {
     async fn new(window: &Window) -> Self {
-        let vertices: [Vertex; 5] = [
-            Vertex {
-                position: [-0.0868241, 0.49240386, 0.0],
-                tex_coords: [0.4131759, 0.00759614],
-            }, // A
-            Vertex {
-                position: [-0.49513406, 0.06958647, 0.0],
-                tex_coords: [0.0048659444, 0.43041354],
-            }, // B
-            Vertex {
-                position: [-0.21918549, -0.44939706, 0.0],
-                tex_coords: [0.28081453, 0.949397057],
-            }, // C
-            Vertex {
-                position: [0.35966998, -0.3473291, 0.0],
-                tex_coords: [0.85967, 0.84732911],
-            }, // D
-            Vertex {
-                position: [0.44147372, 0.2347359, 0.0],
-                tex_coords: [0.9414737, 0.2652641],
-            }, // E
-        ];
-
         let surface = wgpu::Surface::create(window);
         let adapter = wgpu::Adapter::request(
             &wgpu::RequestAdapterOptions {
@@ -241,10 +802,13 @@ impl State {
             present_mode: wgpu::PresentMode::Mailbox,
         };
         let swap_chain = device.create_swap_chain(&surface, &sc_desc);
-        let vertex_buffer = device
-            .create_buffer_with_data(bytemuck::cast_slice(&vertices), wgpu::BufferUsage::VERTEX);
-        let index_buffer =
-            device.create_buffer_with_data(bytemuck::cast_slice(INDICES), wgpu::BufferUsage::INDEX);
+        let depth_texture = texture::Texture::create_depth_texture(&device, &sc_desc, "depth_texture");
+        let shape = Shape::Pentagon;
+        let mesh = shape.mesh();
+        let morph_bind_group_layout = MorphTargets::bind_group_layout(&device);
+        let (vertex_buffer, index_buffer, index_format, lod_mesh, mesh_sphere, morph_targets) =
+            create_mesh_buffers(&device, &morph_bind_group_layout, &mesh);
+        let mesh_acmr = mesh.average_cache_miss_ratio();
 
         let diffuse_bytes = include_bytes!("happy-tree.png");
         let (diffuse_texture, cmd_buffer) =
@@ -252,42 +816,24 @@ impl State {
 
         queue.submit(&[cmd_buffer]);
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                bindings: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStage::FRAGMENT,
-                        ty: wgpu::BindingType::SampledTexture {
-                            multisampled: false,
-                            dimension: wgpu::TextureViewDimension::D2,
-                            component_type: wgpu::TextureComponentType::Uint,
-                        },
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStage::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler { comparison: false },
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
+        let texture_bind_group_layout = Material::bind_group_layout(&device);
 
+        let viewport = Viewport::new(ViewportRect::FULL, size, window.scale_factor());
         let camera = Camera {
             eye: (0.0, 0.0, 2.0).into(),
             target: (0.0, 0.0, 0.0).into(),
             up: cgmath::Vector3::unit_y(),
-            aspect: sc_desc.width as f32 / sc_desc.height as f32,
-            fovy: 70.0,
-            znear: 0.1,
-            zfar: 100.0,
+            aspect: viewport.aspect(),
+            projection: Projection::Perspective {
+                fovy: cgmath::Deg(70.0),
+                znear: 0.1,
+                zfar: 100.0,
+            },
         };
 
-        let mut uniforms = Uniforms::new();
-        uniforms.update_view_proj(&camera);
-
         let instances = (0..NUM_INSTANCES_PER_ROW).flat_map(|z| {
             (0..NUM_INSTANCES_PER_ROW).map(move |x| {
+                let morph_instance = z * NUM_INSTANCES_PER_ROW + x;
                 let position = cgmath::Vector3 { x: x as f32, y: 0.0, z: z as f32 } - INSTANCE_DISPLACEMENT;
 
                 let rotation = if position.is_zero() {
@@ -298,181 +844,924 @@ impl State {
                     cgmath::Quaternion::from_axis_angle(position.clone().normalize(), cgmath::Deg(45.0))
                 };
 
+                // The back row shows what the security camera sees
+                let material = if z == 0 { MONITOR_MATERIAL } else { ((x + z) % 4) as usize };
+
                 Instance {
-                    position, rotation,
+                    position,
+                    rotation,
+                    scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+                    material,
+                    spin_axis: cgmath::Vector3::unit_y(),
+                    spin_speed: cgmath::Deg(60.0).into(),
+                    morph_instance,
+                    morph_weights: Vec::new(),
                 }
             })
         }).collect::<Vec<_>>();
+        let animation_players = demo_animations(&instances);
 
-        let instance_data = instances.iter().map(Instance::to_raw).collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_with_data(
-            bytemuck::cast_slice(&instance_data),
-            wgpu::BufferUsage::VERTEX,
-        );
-
-        let uniform_buffer = device.create_buffer_with_data(
-            bytemuck::cast_slice(&[uniforms]),
-            wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
-        );
+        let instance_ring =
+            RingBuffer::new(&device, INSTANCE_RING_SIZE, 0, wgpu::BufferUsage::VERTEX, "instance_ring");
 
         let uniform_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 bindings: &[
                     wgpu::BindGroupLayoutEntry {
                         binding: 0,
-                        visibility: wgpu::ShaderStage::VERTEX,
-                        ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                        // The OIT weights need to know which way depth runs
+                        visibility: wgpu::ShaderStage::VERTEX | wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::UniformBuffer { dynamic: true },
                     },
                 ],
                 label: Some("uniform_bind_group_layout"),
             });
 
-        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &uniform_bind_group_layout,
-            bindings: &[
-                wgpu::Binding {
-                    binding: 0,
-                    resource: wgpu::BindingResource::Buffer {
-                        buffer: &uniform_buffer,
-                        // FYI: you can share a single buffer between bindings.
-                        range: 0..std::mem::size_of_val(&uniforms) as wgpu::BufferAddress,
-                    },
-                }
-            ],
-            label: Some("uniform_bind_group"),
-        });
-
-        let render_pipeline_layout =
-            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-                bind_group_layouts: &[&texture_bind_group_layout, &uniform_bind_group_layout],
-            });
+        let views =
+            vec![View::new(&device, &uniform_bind_group_layout, viewport, ViewCamera::Main)];
 
-        let fs = get_file_as_byte_vec(&"examples/diffuse_maps/shader/my_frag.spv".to_string());
-        let vs = get_file_as_byte_vec(&"examples/diffuse_maps/shader/my_vert.spv".to_string());
-
-        let vs_module =
-            device.create_shader_module(&wgpu::read_spirv(std::io::Cursor::new(&vs[..])).unwrap());
-        let fs_module =
-            device.create_shader_module(&wgpu::read_spirv(std::io::Cursor::new(&fs[..])).unwrap());
-
-        let render_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-            layout: &render_pipeline_layout,
-            vertex_stage: wgpu::ProgrammableStageDescriptor {
-                module: &vs_module,
-                entry_point: "main",
-            },
-            fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-                module: &fs_module,
-                entry_point: "main",
-            }),
-            rasterization_state: Some(wgpu::RasterizationStateDescriptor {
-                front_face: wgpu::FrontFace::Ccw,
-                cull_mode: wgpu::CullMode::None,
-                depth_bias: 0,
-                depth_bias_slope_scale: 0.0,
-                depth_bias_clamp: 0.0,
-            }),
-            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-            color_states: &[wgpu::ColorStateDescriptor {
-                format: wgpu::TextureFormat::Bgra8UnormSrgb,
-                color_blend: wgpu::BlendDescriptor::REPLACE,
-                alpha_blend: wgpu::BlendDescriptor::REPLACE,
-                write_mask: wgpu::ColorWrite::ALL,
-            }],
-            depth_stencil_state: None,
-            sample_count: 1,
-            sample_mask: !0,
-            alpha_to_coverage_enabled: false,
-            vertex_state: wgpu::VertexStateDescriptor {
-                index_format: wgpu::IndexFormat::Uint16,
-                vertex_buffers: &[
-                    Vertex::desc(),
-                    InstanceRaw::desc(),
-                ],
-            },
-        });
+        let mut pipeline_cache = PipelineCache::new();
+        let pipeline_layout = pipeline_cache.create_layout(
+            &device,
+            &[&texture_bind_group_layout, &uniform_bind_group_layout, &morph_bind_group_layout],
+        );
 
-        let diffuse_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &texture_bind_group_layout,
-            bindings: &[
-                wgpu::Binding {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::Binding {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
+        compile_my_shader(
+            "examples/diffuse_maps/shader/my.vert",
+            "examples/diffuse_maps/shader/my_vert.spv",
+            shaderc::ShaderKind::Vertex,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/my.frag",
+            "examples/diffuse_maps/shader/my_frag.spv",
+            shaderc::ShaderKind::Fragment,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/my_cutout.frag",
+            "examples/diffuse_maps/shader/my_cutout_frag.spv",
+            shaderc::ShaderKind::Fragment,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/oit_accum.frag",
+            "examples/diffuse_maps/shader/oit_accum_frag.spv",
+            shaderc::ShaderKind::Fragment,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/oit_composite.vert",
+            "examples/diffuse_maps/shader/oit_composite_vert.spv",
+            shaderc::ShaderKind::Vertex,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/oit_composite.frag",
+            "examples/diffuse_maps/shader/oit_composite_frag.spv",
+            shaderc::ShaderKind::Fragment,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/gpu_cull.comp",
+            "examples/diffuse_maps/shader/gpu_cull_comp.spv",
+            shaderc::ShaderKind::Compute,
+        );
+        let oit = WeightedBlendedOit::new(&device, &sc_desc, &mut pipeline_cache);
+        let gpu_culling = GpuCulling::new(
+            &device,
+            &mut pipeline_cache,
+            "examples/diffuse_maps/shader/gpu_cull_comp.spv",
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/particles_simulate.comp",
+            "examples/diffuse_maps/shader/particles_simulate_comp.spv",
+            shaderc::ShaderKind::Compute,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/particles_sort.comp",
+            "examples/diffuse_maps/shader/particles_sort_comp.spv",
+            shaderc::ShaderKind::Compute,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/particles_gather.comp",
+            "examples/diffuse_maps/shader/particles_gather_comp.spv",
+            shaderc::ShaderKind::Compute,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/particle.vert",
+            "examples/diffuse_maps/shader/particle_vert.spv",
+            shaderc::ShaderKind::Vertex,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/particle.frag",
+            "examples/diffuse_maps/shader/particle_frag.spv",
+            shaderc::ShaderKind::Fragment,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/debug_line.vert",
+            "examples/diffuse_maps/shader/debug_line_vert.spv",
+            shaderc::ShaderKind::Vertex,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/debug_line.frag",
+            "examples/diffuse_maps/shader/debug_line_frag.spv",
+            shaderc::ShaderKind::Fragment,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/text_world.vert",
+            "examples/diffuse_maps/shader/text_world_vert.spv",
+            shaderc::ShaderKind::Vertex,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/text_screen.vert",
+            "examples/diffuse_maps/shader/text_screen_vert.spv",
+            shaderc::ShaderKind::Vertex,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/text_bitmap.frag",
+            "examples/diffuse_maps/shader/text_bitmap_frag.spv",
+            shaderc::ShaderKind::Fragment,
+        );
+        compile_my_shader(
+            "examples/diffuse_maps/shader/text_sdf.frag",
+            "examples/diffuse_maps/shader/text_sdf_frag.spv",
+            shaderc::ShaderKind::Fragment,
+        );
+        // Particles and debug lines only need the view uniforms
+        let uniforms_layout = pipeline_cache.create_layout(&device, &[&uniform_bind_group_layout]);
+        let particles = ParticleSystem::new(
+            &device,
+            &mut pipeline_cache,
+            uniforms_layout,
+            sc_desc.format,
+            camera.projection.depth_compare(),
+            8192,
+            fountain(),
+            ParticleBlend::Additive,
+        );
+        let debug_draw = DebugDraw::new(
+            &device,
+            &mut pipeline_cache,
+            uniforms_layout,
+            sc_desc.format,
+            camera.projection.depth_compare(),
+        );
+        let text = TextRenderer::new(
+            &device,
+            &mut pipeline_cache,
+            &uniform_bind_group_layout,
+            sc_desc.format,
+            camera.projection.depth_compare(),
+            load_font(),
+            GlyphMode::Sdf,
+        );
+        let material_key = PipelineKey::new(
+            pipeline_layout,
+            "examples/diffuse_maps/shader/my_vert.spv",
+            "examples/diffuse_maps/shader/my_frag.spv",
+        )
+        .with_vertex_layouts(&[Vertex::desc(), InstanceRaw::desc()])
+        .with_index_format(index_format);
+        let mut materials = [
+            BlendMode::AlphaBlend,
+            BlendMode::AlphaTest,
+            BlendMode::Opaque,
+            BlendMode::Premultiplied,
+        ]
+        .iter()
+        .map(|blend_mode| {
+            Material::new(
+                &device,
+                &mut pipeline_cache,
+                *blend_mode,
+                &texture_bind_group_layout,
+                &diffuse_texture,
+                material_key.clone(),
+                camera.projection.depth_compare(),
+            )
+        })
+        .collect::<Vec<_>>();
+        // Scaling color and alpha alike keeps the opaque texels premultiplied, a faded tree
+        materials[3].params.tint = [0.5; 4];
+
+        let monitor_color = texture::Texture::create_render_target(
+            &device,
+            MONITOR_SIZE,
+            MONITOR_SIZE,
+            sc_desc.format,
+            "monitor_color",
+        );
+        materials.push(Material::new(
+            &device,
+            &mut pipeline_cache,
+            BlendMode::Opaque,
+            &texture_bind_group_layout,
+            &monitor_color,
+            material_key,
+            camera.projection.depth_compare(),
+        ));
+        compile_my_shader(
+            "examples/diffuse_maps/shader/skinned.vert",
+            "examples/diffuse_maps/shader/skinned_vert.spv",
+            shaderc::ShaderKind::Vertex,
+        );
+        let joint_bind_group_layout = SkinnedModel::joint_bind_group_layout(&device);
+        let skinned_layout = pipeline_cache.create_layout(
+            &device,
+            &[
+                &texture_bind_group_layout,
+                &uniform_bind_group_layout,
+                &morph_bind_group_layout,
+                &joint_bind_group_layout,
             ],
-            label: Some("diffuse_bind_group"),
+        );
+        // An animated glTF character or morphing mesh, passed as the first argument:
+        // cargo run --example diffuse_maps -- character.gltf
+        let skinned_model = std::env::args().nth(1).map(|path| {
+            let model = GltfModel::load(&path)
+                .unwrap_or_else(|error| panic!("couldn't load {}: {}", path, error));
+            let key = PipelineKey::new(
+                skinned_layout,
+                "examples/diffuse_maps/shader/skinned_vert.spv",
+                "examples/diffuse_maps/shader/my_frag.spv",
+            )
+            .with_vertex_layouts(&[SkinnedVertex::desc(), InstanceRaw::desc()]);
+            let material = Material::new(
+                &device,
+                &mut pipeline_cache,
+                BlendMode::Opaque,
+                &texture_bind_group_layout,
+                &diffuse_texture,
+                key,
+                camera.projection.depth_compare(),
+            );
+            // In front of the grid of instances, standing still
+            let instance = Instance {
+                position: (0.0, 0.0, 7.0).into(),
+                rotation: cgmath::Quaternion::from_angle_y(cgmath::Deg(0.0)),
+                scale: cgmath::Vector3::new(1.0, 1.0, 1.0),
+                material: 0,
+                spin_axis: cgmath::Vector3::unit_y(),
+                spin_speed: cgmath::Rad(0.0),
+                morph_instance: 0,
+                morph_weights: Vec::new(),
+            };
+            let instance = instance.to_raw();
+            SkinnedModel::new(
+                &device,
+                &mut pipeline_cache,
+                model,
+                &joint_bind_group_layout,
+                &morph_bind_group_layout,
+                material,
+                bytemuck::cast_slice(&[instance]),
+                &instance.model,
+            )
         });
 
+        let monitor_camera = Camera {
+            eye: (6.0, 5.0, 8.0).into(),
+            ..camera.clone()
+        };
+        let monitor = OffscreenView {
+            view: View::new(
+                &device,
+                &uniform_bind_group_layout,
+                Viewport::new(
+                    ViewportRect::FULL,
+                    winit::dpi::PhysicalSize::new(MONITOR_SIZE, MONITOR_SIZE),
+                    1.0,
+                ),
+                ViewCamera::Fixed(monitor_camera),
+            ),
+            color: monitor_color,
+            depth: texture::Texture::create_depth_target(
+                &device,
+                MONITOR_SIZE,
+                MONITOR_SIZE,
+                "monitor_depth",
+            ),
+            material: MONITOR_MATERIAL,
+        };
+
         Self {
-            vertices,
             instances,
             surface,
             device,
             queue,
             sc_desc,
             swap_chain,
-            render_pipeline,
+            pipeline_cache,
+            depth_texture,
             vertex_buffer,
-            instance_buffer,
+            instance_ring,
+            instance_offset: 0,
             index_buffer,
-            num_indices: INDICES.len() as u32,
-            diffuse_bind_group,
+            lod_mesh,
+            lod_settings: LodSettings::default(),
+            mesh_sphere,
+            mesh_acmr,
+            morph_targets,
+            morph_weights_dirty: false,
+            shape,
+            skinned_model,
+            animation_players,
+            particles,
+            debug_draw,
+            show_debug: false,
+            text,
+            materials,
+            view_batches: Vec::new(),
+            culling_mode: CullingMode::Cpu,
+            gpu_culling,
+            gpu_instances_dirty: true,
+            transparency_mode: TransparencyMode::Sorted,
+            oit,
             size,
+            view_layout: ViewLayout::Single,
+            views,
+            monitor,
+            previous_camera: camera.clone(),
+            staging_belt: StagingBelt::new(STAGING_CHUNK_SIZE),
+            uniform_bind_group_layout,
+            morph_bind_group_layout,
+            camera_controllers: vec![
+                Box::new(FpsCameraController::new(&camera, 4.0, 0.003)),
+                Box::new(OrbitCameraController::new(&camera)),
+                Box::new(KeyboardCameraController::new(4.0)),
+            ],
+            active_camera_controller: 0,
             camera,
-            uniforms,
-            uniform_buffer,
-            uniform_bind_group,
-            camera_controller: CameraController::new(0.2),
         }
     }
 
-    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
+    fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>, scale_factor: f64) {
         self.size = new_size;
+        for view in &mut self.views {
+            view.viewport.resize(new_size, scale_factor);
+        }
+        self.views[0].viewport.apply(&mut self.camera);
+        self.views[0].viewport.apply(&mut self.previous_camera);
         self.sc_desc.width = new_size.width;
         self.sc_desc.height = new_size.height;
         self.swap_chain = self.device.create_swap_chain(&self.surface, &self.sc_desc);
+        self.depth_texture =
+            texture::Texture::create_depth_texture(&self.device, &self.sc_desc, "depth_texture");
+        self.oit.resize(&self.device, &self.sc_desc);
+    }
+
+    /// Keeps the main view and creates the other views of the layout
+    fn set_view_layout(&mut self, layout: ViewLayout) {
+        let rects = layout.rects();
+        let scale_factor = self.views[0].viewport.scale_factor();
+        self.views.truncate(1);
+        self.views[0].viewport.set_rect(rects[0], self.size);
+        self.views[0].viewport.apply(&mut self.camera);
+        self.views[0].viewport.apply(&mut self.previous_camera);
+
+        for (rect, camera) in rects[1..].iter().zip(layout.fixed_cameras(self.camera.projection)) {
+            self.views.push(View::new(
+                &self.device,
+                &self.uniform_bind_group_layout,
+                Viewport::new(*rect, self.size, scale_factor),
+                ViewCamera::Fixed(camera),
+            ));
+        }
+        self.view_layout = layout;
+    }
+
+    fn set_shape(&mut self, shape: Shape) {
+        let mesh = shape.mesh();
+        let (vertex_buffer, index_buffer, index_format, lod_mesh, mesh_sphere, morph_targets) =
+            create_mesh_buffers(&self.device, &self.morph_bind_group_layout, &mesh);
+        // The pipelines read the index buffer in the format they were created with
+        for material in &mut self.materials {
+            material.set_index_format(&self.device, &mut self.pipeline_cache, index_format);
+        }
+        self.vertex_buffer = vertex_buffer;
+        self.index_buffer = index_buffer;
+        self.lod_mesh = lod_mesh;
+        self.mesh_sphere = mesh_sphere;
+        self.mesh_acmr = mesh.average_cache_miss_ratio();
+        // The new targets start at rest for every instance
+        self.morph_targets = morph_targets;
+        self.morph_weights_dirty = true;
+        self.shape = shape;
+        // The gpu culling draws use the indices of the first level
+        self.gpu_instances_dirty = true;
+    }
+
+    fn camera_controller(&mut self) -> &mut dyn CameraController {
+        self.camera_controllers[self.active_camera_controller].as_mut()
+    }
+
+    fn next_camera_controller(&mut self) {
+        self.camera_controller().detach();
+        self.active_camera_controller =
+            (self.active_camera_controller + 1) % self.camera_controllers.len();
+        self.camera_controllers[self.active_camera_controller].attach(&self.camera);
+    }
+
+    fn set_projection(&mut self, projection: Projection) {
+        let depth_compare_changed =
+            projection.depth_compare() != self.camera.projection.depth_compare();
+        self.camera.projection = projection;
+        self.previous_camera.projection = projection;
+        for view in self.views.iter_mut().chain(Some(&mut self.monitor.view)) {
+            if let ViewCamera::Fixed(camera) = &mut view.camera {
+                camera.projection = projection;
+            }
+        }
+        if depth_compare_changed {
+            let skinned_material = self.skinned_model.as_mut().map(|model| &mut model.material);
+            for material in self.materials.iter_mut().chain(skinned_material) {
+                material.set_depth_compare(
+                    &self.device,
+                    &mut self.pipeline_cache,
+                    projection.depth_compare(),
+                );
+            }
+            self.particles.set_depth_compare(
+                &self.device,
+                &mut self.pipeline_cache,
+                projection.depth_compare(),
+            );
+            self.debug_draw.set_depth_compare(
+                &self.device,
+                &mut self.pipeline_cache,
+                projection.depth_compare(),
+            );
+            self.text.set_depth_compare(
+                &self.device,
+                &mut self.pipeline_cache,
+                projection.depth_compare(),
+            );
+        }
+    }
+
+    /// Box around all instances, with some room for the models themselves
+    fn scene_bounds(&self) -> (cgmath::Point3<f32>, cgmath::Point3<f32>) {
+        let mut min = cgmath::Point3::new(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = cgmath::Point3::new(f32::MIN, f32::MIN, f32::MIN);
+        for instance in &self.instances {
+            for i in 0..3 {
+                min[i] = min[i].min(instance.position[i] - 0.5);
+                max[i] = max[i].max(instance.position[i] + 0.5);
+            }
+        }
+        (min, max)
+    }
+
+    fn device_input(&mut self, event: &DeviceEvent) -> bool {
+        self.camera_controller().process_device_events(event)
+    }
+
+    fn wants_cursor_grab(&self) -> bool {
+        self.camera_controllers[self.active_camera_controller].is_mouse_captured()
     }
 
     fn input(&mut self, event: &WindowEvent) -> bool {
-        self.camera_controller.process_events(event)
+        if self.camera_controller().process_events(event) {
+            return true;
+        }
+        match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        state: ElementState::Pressed,
+                        virtual_keycode: Some(keycode),
+                        ..
+                    },
+                ..
+            } => match keycode {
+                VirtualKeyCode::T => {
+                    self.transparency_mode = self.transparency_mode.toggle();
+                    true
+                }
+                VirtualKeyCode::C => {
+                    self.next_camera_controller();
+                    true
+                }
+                VirtualKeyCode::P => {
+                    self.set_projection(next_projection(&self.camera.projection));
+                    true
+                }
+                VirtualKeyCode::L => {
+                    let fade_band = if self.lod_settings.fade_band > 0.0 { 0.0 } else { 0.5 };
+                    self.lod_settings.fade_band = fade_band;
+                    true
+                }
+                VirtualKeyCode::G => {
+                    self.culling_mode = self.culling_mode.toggle();
+                    true
+                }
+                VirtualKeyCode::V => {
+                    self.set_view_layout(self.view_layout.next());
+                    true
+                }
+                VirtualKeyCode::M => {
+                    self.set_shape(self.shape.next());
+                    true
+                }
+                VirtualKeyCode::K => {
+                    if let Some(model) = &mut self.skinned_model {
+                        model.next_clip();
+                    }
+                    true
+                }
+                VirtualKeyCode::R => {
+                    for player in &mut self.animation_players {
+                        player.restart();
+                    }
+                    true
+                }
+                VirtualKeyCode::B => {
+                    let blend = self.particles.blend().toggle();
+                    self.particles.set_blend(&self.device, &mut self.pipeline_cache, blend);
+                    true
+                }
+                VirtualKeyCode::N => {
+                    let emitter = Emitter {
+                        shape: next_emitter_shape(&self.particles.emitter().shape),
+                        ..self.particles.emitter().clone()
+                    };
+                    self.particles.set_emitter(&self.device, emitter);
+                    true
+                }
+                VirtualKeyCode::X => {
+                    self.show_debug = !self.show_debug;
+                    true
+                }
+                VirtualKeyCode::J => {
+                    let mode = self.text.mode().toggle();
+                    self.text.set_mode(&self.device, &mut self.pipeline_cache, mode);
+                    true
+                }
+                VirtualKeyCode::F => {
+                    let (min, max) = self.scene_bounds();
+                    let controller = &mut self.camera_controllers[self.active_camera_controller];
+                    controller.frame_bounds(min, max, &self.camera);
+                    true
+                }
+                _ => false,
+            },
+            _ => false,
+        }
     }
 
-    fn update(&mut self) {
-        rotate_model(&mut self.vertices);
-        self.vertex_buffer = self.device.create_buffer_with_data(
-            bytemuck::cast_slice(&self.vertices),
-            wgpu::BufferUsage::VERTEX,
-        );
+    /// Simulation, runs at a fixed rate independent of the frame rate
+    fn fixed_update(&mut self, dt: f32) {
+        self.previous_camera = self.camera.clone();
+        let controller = &mut self.camera_controllers[self.active_camera_controller];
+        controller.update_camera(&mut self.camera, dt);
+    }
 
-        self.camera_controller.update_camera(&mut self.camera);
-        self.uniforms.update_view_proj(&self.camera);
+    fn update(&mut self, frame: &FrameTime, stats: &FrameStats) {
+        for _ in 0..frame.fixed_steps {
+            self.fixed_update(frame.fixed_delta.as_secs_f32());
+        }
 
+        let mut target = SceneTarget {
+            instances: &mut self.instances,
+            materials: &mut self.materials,
+            instances_changed: false,
+            morph_weights_changed: false,
+        };
+        for player in &mut self.animation_players {
+            player.update(frame.delta.as_secs_f32(), &mut target);
+        }
+        if target.instances_changed {
+            self.gpu_instances_dirty = true;
+        }
+        if target.morph_weights_changed {
+            self.morph_weights_dirty = true;
+        }
+
+        let main_camera = self.previous_camera.lerp(&self.camera, frame.alpha);
+        let time = frame.elapsed.as_secs_f32();
+        let cameras = self
+            .views
+            .iter_mut()
+            .chain(Some(&mut self.monitor.view))
+            .map(|view| {
+                let camera = view.camera(&main_camera);
+                view.uniforms.update_view_proj(&camera);
+                view.uniforms.time = time;
+                (camera, view.viewport.physical_rect().height as f32)
+            })
+            .collect::<Vec<_>>();
 
         // Copy operation's are performed on the gpu, so we'll need a CommandEncoder for that
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("update encoder"),
         });
 
-        let staging_buffer = self.device.create_buffer_with_data(
-            bytemuck::cast_slice(&[self.uniforms]),
-            wgpu::BufferUsage::COPY_SRC,
-        );
+        if self.morph_weights_dirty {
+            for instance in &self.instances {
+                self.morph_targets.set_weights(
+                    &self.device,
+                    &mut encoder,
+                    &mut self.staging_belt,
+                    instance.morph_instance as usize,
+                    &instance.morph_weights,
+                );
+            }
+            self.morph_weights_dirty = false;
+        }
 
-        encoder.copy_buffer_to_buffer(
-            &staging_buffer,
-            0,
-            &self.uniform_buffer,
-            0,
-            std::mem::size_of::<Uniforms>() as wgpu::BufferAddress,
+        match self.culling_mode {
+            CullingMode::Cpu => {
+                let instance_data = self.update_instances(&cameras);
+                self.instance_offset = self.instance_ring.push(
+                    &self.device,
+                    &mut encoder,
+                    &mut self.staging_belt,
+                    bytemuck::cast_slice(&instance_data),
+                );
+            }
+            CullingMode::Gpu => {
+                self.view_batches.clear();
+                if self.gpu_instances_dirty {
+                    self.upload_gpu_instances(&mut encoder);
+                }
+                let views = self.views.iter_mut().chain(Some(&mut self.monitor.view));
+                for (view, (camera, _)) in views.zip(&cameras) {
+                    let view_proj = camera.build_view_projection_matrix();
+                    self.gpu_culling.cull(
+                        &self.device,
+                        &mut encoder,
+                        &mut self.staging_belt,
+                        &mut view.cull_target,
+                        &Frustum::from_view_projection(&view_proj),
+                        self.mesh_sphere.radius,
+                    );
+                }
+            }
+        }
+        if let Some(model) = &mut self.skinned_model {
+            model.update(
+                &self.device,
+                &mut encoder,
+                &mut self.staging_belt,
+                frame.delta.as_secs_f32(),
+            );
+        }
+        let skinned_material = self.skinned_model.as_mut().map(|model| &mut model.material);
+        for material in self.materials.iter_mut().chain(skinned_material) {
+            material.upload_params(&self.device, &mut encoder, &mut self.staging_belt);
+        }
+        // Sorted for the main view, the others may see some particles out of order
+        self.particles.update(
+            &self.device,
+            &mut encoder,
+            &mut self.staging_belt,
+            frame.delta.as_secs_f32(),
+            cameras[0].0.eye,
         );
+        self.update_debug_draw(&mut encoder, &cameras);
+        self.update_text(&mut encoder, stats);
+        for view in self.views.iter_mut().chain(Some(&mut self.monitor.view)) {
+            view.uniform_offset = view.uniform_ring.push(
+                &self.device,
+                &mut encoder,
+                &mut self.staging_belt,
+                bytemuck::cast_slice(&[view.uniforms]),
+            ) as wgpu::DynamicOffset;
+        }
+        self.staging_belt.finish();
 
         // We need to remember to submit our CommandEncoder's output
         // otherwise we won't see any change.
         self.queue.submit(&[encoder.finish()]);
+        self.staging_belt.recall();
+    }
+
+    /// Lines of the debug overlay: the scene bounds, the bounding sphere of every instance,
+    /// green when the main view sees it, the frusta of the other views and a ground grid.
+    fn update_debug_draw(
+        &mut self,
+        encoder: &mut wgpu::CommandEncoder,
+        cameras: &[(Camera, f32)],
+    ) {
+        self.debug_draw.clear();
+        if self.show_debug {
+            let (min, max) = self.scene_bounds();
+            let debug_draw = &mut self.debug_draw;
+            debug_draw.grid(
+                cgmath::Point3::new(0.0, min.y, 0.0),
+                20.0,
+                20,
+                [0.5, 0.5, 0.5, 0.5],
+                DepthMode::Tested,
+            );
+            debug_draw.aabb(&Aabb { min, max }, [1.0, 1.0, 1.0, 1.0], DepthMode::Tested);
+
+            let view_proj = cameras[0].0.build_view_projection_matrix();
+            let frustum = Frustum::from_view_projection(&view_proj);
+            for instance in &self.instances {
+                let scale = instance.scale.x.max(instance.scale.y).max(instance.scale.z);
+                let sphere = self.mesh_sphere.scale(scale).translate(instance.position);
+                let color = if frustum.intersects_sphere(&sphere) {
+                    [0.2, 1.0, 0.2, 1.0]
+                } else {
+                    [1.0, 0.2, 0.2, 1.0]
+                };
+                debug_draw.sphere(&sphere, color, DepthMode::Tested);
+            }
+            if let Some(model) = &self.skinned_model {
+                let color = if frustum.intersects_aabb(&model.bounds) {
+                    [0.2, 1.0, 0.2, 1.0]
+                } else {
+                    [1.0, 0.2, 0.2, 1.0]
+                };
+                debug_draw.aabb(&model.bounds, color, DepthMode::Tested);
+            }
+            for (camera, _) in &cameras[1..] {
+                let view_proj = camera.build_view_projection_matrix();
+                debug_draw.frustum(&view_proj, [1.0, 1.0, 0.2, 1.0], DepthMode::Tested);
+            }
+            debug_draw.axes(&cgmath::Matrix4::identity(), 1.0, DepthMode::OnTop);
+        }
+        self.debug_draw.upload(&self.device, encoder, &mut self.staging_belt);
+    }
+
+    /// HUD with the frame time and the render settings, and labels over the instances the
+    /// animations move and over the particle emitter.
+    fn update_text(&mut self, encoder: &mut wgpu::CommandEncoder, stats: &FrameStats) {
+        let visible = match self.visible_instances() {
+            Some(count) => count.to_string(),
+            None => "culled on the gpu".to_string(),
+        };
+        let text = &mut self.text;
+        text.clear();
+        text.set_screen_size(self.size.width, self.size.height);
+        let scale_factor = self.views[0].viewport.scale_factor() as f32;
+        let hud = format!(
+            "{:.0} fps, {:.2} ms\nvisible instances: {}\nculling: {:?}\ntransparency: {:?}\n\
+             particles: {:?}\nglyphs: {:?}",
+            stats.fps(),
+            stats.average_frame_time().as_secs_f32() * 1000.0,
+            visible,
+            self.culling_mode,
+            self.transparency_mode,
+            self.particles.blend(),
+            text.mode(),
+        );
+        let hud_style = TextStyle {
+            size: 16.0 * scale_factor,
+            ..TextStyle::default()
+        };
+        text.queue_screen([10.0 * scale_factor, 10.0 * scale_factor], &hud, &hud_style);
+        let help_style = TextStyle {
+            size: 14.0 * scale_factor,
+            color: [0.8, 0.8, 0.8, 0.8],
+            horizontal_align: HorizontalAlign::Right,
+            vertical_align: VerticalAlign::Bottom,
+            max_width: Some(360.0 * scale_factor),
+        };
+        let corner = [
+            self.size.width as f32 - 10.0 * scale_factor,
+            self.size.height as f32 - 10.0 * scale_factor,
+        ];
+        text.queue_screen(corner, HELP, &help_style);
+
+        let label_style = TextStyle {
+            size: 0.3,
+            color: [1.0, 1.0, 0.6, 1.0],
+            horizontal_align: HorizontalAlign::Center,
+            vertical_align: VerticalAlign::Bottom,
+            max_width: None,
+        };
+        let above = cgmath::Vector3::new(0.0, 0.8, 0.0);
+        for player in &self.animation_players {
+            let mut nodes =
+                player.animation.tracks.iter().filter_map(Track::node).collect::<Vec<_>>();
+            nodes.sort();
+            nodes.dedup();
+            for node in nodes {
+                let position = cgmath::Point3::from_vec(self.instances[node].position + above);
+                text.queue_world(position, &player.animation.name, &label_style);
+            }
+        }
+        let fountain = self.particles.emitter().position + above;
+        text.queue_world(fountain, "fountain", &label_style);
+        text.upload(&self.device, encoder, &mut self.staging_belt);
+    }
+
+    /// Culls the instances against the frustum of every camera and compacts the visible
+    /// ones into the returned instance data, one run per camera in the order of `cameras`.
+    /// Every camera comes with the height of its viewport, which lod selection needs.
+    ///
+    /// Opaque instances are grouped by material and lod level, transparent ones are sorted
+    /// back to front so blending composes them in the right order. OIT doesn't care about
+    /// the order, so there transparent instances are grouped too.
+    fn update_instances(&mut self, cameras: &[(Camera, f32)]) -> Vec<InstanceRaw> {
+        let materials = &self.materials;
+        let mesh_sphere = self.mesh_sphere;
+        let mut instance_data = Vec::new();
+
+        self.view_batches.clear();
+        for (camera, viewport_height) in cameras {
+            let frustum = Frustum::from_view_projection(&camera.build_view_projection_matrix());
+            let eye = camera.eye.to_vec();
+            let mut opaque = Vec::new();
+            let mut transparent = Vec::new();
+            for instance in self.instances.iter().filter(|instance| {
+                let scale = instance.scale.x.max(instance.scale.y).max(instance.scale.z);
+                frustum.intersects_sphere(&mesh_sphere.scale(scale).translate(instance.position))
+            }) {
+                let distance = (instance.position - eye).magnitude();
+                let pixels_per_unit = camera.projection.pixels_per_unit(*viewport_height, distance);
+                let selection = self.lod_mesh.select(pixels_per_unit, &self.lod_settings);
+                let list = if materials[instance.material].blend_mode.is_transparent() {
+                    &mut transparent
+                } else {
+                    &mut opaque
+                };
+                match selection.fade_to {
+                    Some((next, t)) => {
+                        list.push(LodInstance {
+                            instance,
+                            lod: selection.level,
+                            fade: 1.0 - t,
+                        });
+                        list.push(LodInstance {
+                            instance,
+                            lod: next,
+                            fade: -(1.0 - t),
+                        });
+                    }
+                    None => list.push(LodInstance {
+                        instance,
+                        lod: selection.level,
+                        fade: 1.0,
+                    }),
+                }
+            }
+
+            opaque.sort_by_key(|item| (item.instance.material, item.lod));
+            match self.transparency_mode {
+                TransparencyMode::Sorted => {
+                    transparent.sort_by(|a, b| {
+                        let distance_a = (a.instance.position - eye).magnitude2();
+                        let distance_b = (b.instance.position - eye).magnitude2();
+                        distance_b.partial_cmp(&distance_a).unwrap()
+                    });
+                }
+                TransparencyMode::WeightedBlended => {
+                    transparent.sort_by_key(|item| (item.instance.material, item.lod));
+                }
+            }
+
+            let opaque_batches = build_batches(&opaque, instance_data.len() as u32);
+            instance_data.extend(opaque.iter().map(LodInstance::to_raw));
+            let transparent_batches = build_batches(&transparent, instance_data.len() as u32);
+            instance_data.extend(transparent.iter().map(LodInstance::to_raw));
+            self.view_batches.push(ViewBatches {
+                opaque: opaque_batches,
+                transparent: transparent_batches,
+            });
+        }
+
+        instance_data
+    }
+
+    /// Uploads all instances grouped by material for the compute shader to cull
+    fn upload_gpu_instances(&mut self, encoder: &mut wgpu::CommandEncoder) {
+        let mut instances = self.instances.iter().collect::<Vec<_>>();
+        instances.sort_by_key(|instance| instance.material);
+
+        let mut batches: Vec<IndirectBatch> = Vec::new();
+        let mut batch_ids = Vec::with_capacity(instances.len());
+        for (i, instance) in instances.iter().enumerate() {
+            match batches.last_mut() {
+                Some(batch) if batch.material == instance.material => batch.instance_count += 1,
+                _ => batches.push(IndirectBatch {
+                    material: instance.material,
+                    first_instance: i as u32,
+                    instance_count: 1,
+                }),
+            }
+            batch_ids.push(batches.len() as u32 - 1);
+        }
+
+        let instance_data = instances.iter().map(|instance| instance.to_raw()).collect::<Vec<_>>();
+        self.gpu_culling.set_instances(
+            &self.device,
+            encoder,
+            &mut self.staging_belt,
+            bytemuck::cast_slice(&instance_data),
+            &batch_ids,
+            batches,
+            self.lod_mesh.levels[0].indices.clone(),
+        );
+        self.gpu_instances_dirty = false;
+    }
+
+    /// Instances the main view draws this frame, after culling. Unknown when culling on
+    /// the gpu, the count never comes back to the cpu.
+    fn visible_instances(&self) -> Option<u32> {
+        match self.culling_mode {
+            CullingMode::Cpu => {
+                Some(self.view_batches.first().map_or(0, ViewBatches::instance_count))
+            }
+            CullingMode::Gpu => None,
+        }
     }
 
     fn render(&mut self) {
@@ -481,6 +1770,8 @@ impl State {
             label: Some("Render Encoder"),
         });
 
+        self.render_monitor(&mut encoder);
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
@@ -495,29 +1786,337 @@ impl State {
                         a: 1.0,
                     },
                 }],
-                depth_stencil_attachment: None,
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                    attachment: &self.depth_texture.view,
+                    depth_load_op: wgpu::LoadOp::Clear,
+                    depth_store_op: wgpu::StoreOp::Store,
+                    clear_depth: self.camera.projection.clear_depth(),
+                    stencil_load_op: wgpu::LoadOp::Clear,
+                    stencil_store_op: wgpu::StoreOp::Store,
+                    clear_stencil: 0,
+                }),
             });
+            for index in 0..self.views.len() {
+                self.draw_view(&mut render_pass, index, false, false, None);
+            }
+        }
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.set_bind_group(0, &self.diffuse_bind_group, &[]);
-            render_pass.set_bind_group(1, &self.uniform_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, &self.vertex_buffer, 0, 0);
-            render_pass.set_vertex_buffer(1, &self.instance_buffer, 0, 0);
-            render_pass.set_index_buffer(&self.index_buffer, 0, 0);
-            render_pass.draw_indexed(0..self.num_indices, 0, 0..NUM_INSTANCES);
+        match self.transparency_mode {
+            TransparencyMode::Sorted => self.render_sorted_transparent(&mut encoder, &frame.view),
+            TransparencyMode::WeightedBlended => {
+                {
+                    let mut render_pass =
+                        self.oit.begin_accum_pass(&mut encoder, &self.depth_texture.view);
+                    for index in 0..self.views.len() {
+                        self.draw_view(&mut render_pass, index, true, true, None);
+                    }
+                }
+                self.oit.composite(&mut encoder, &frame.view, &self.pipeline_cache);
+                self.render_particles(&mut encoder, &frame.view);
+            }
+        }
+        if self.show_debug {
+            self.render_debug(&mut encoder, &frame.view);
         }
+        self.render_text(&mut encoder, &frame.view);
 
         self.queue.submit(&[encoder.finish()]);
     }
+
+    /// Renders the security camera into the monitor texture. Everything goes into one pass,
+    /// transparent instances are blended directly even with OIT enabled.
+    fn render_monitor(&self, encoder: &mut wgpu::CommandEncoder) {
+        let monitor = &self.monitor;
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: &monitor.color.view,
+                resolve_target: None,
+                load_op: wgpu::LoadOp::Clear,
+                store_op: wgpu::StoreOp::Store,
+                clear_color: wgpu::Color {
+                    r: 0.05,
+                    g: 0.05,
+                    b: 0.05,
+                    a: 1.0,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &monitor.depth.view,
+                depth_load_op: wgpu::LoadOp::Clear,
+                depth_store_op: wgpu::StoreOp::Store,
+                clear_depth: self.camera.projection.clear_depth(),
+                stencil_load_op: wgpu::LoadOp::Clear,
+                stencil_store_op: wgpu::StoreOp::Store,
+                clear_stencil: 0,
+            }),
+        });
+        let index = self.views.len();
+        self.draw_view(&mut render_pass, index, false, false, Some(monitor.material));
+        self.draw_view(&mut render_pass, index, true, false, Some(monitor.material));
+    }
+
+    /// Pass that keeps the color and depth drawn so far
+    fn begin_load_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        target: &'a wgpu::TextureView,
+    ) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: target,
+                resolve_target: None,
+                load_op: wgpu::LoadOp::Load,
+                store_op: wgpu::StoreOp::Store,
+                clear_color: wgpu::Color::BLACK,
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: &self.depth_texture.view,
+                depth_load_op: wgpu::LoadOp::Load,
+                depth_store_op: wgpu::StoreOp::Store,
+                clear_depth: 1.0,
+                stencil_load_op: wgpu::LoadOp::Load,
+                stencil_store_op: wgpu::StoreOp::Store,
+                clear_stencil: 0,
+            }),
+        })
+    }
+
+    /// Transparent pass keeps the opaque color and depth, it only tests against depth
+    fn render_sorted_transparent(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+    ) {
+        let mut render_pass = self.begin_load_pass(encoder, target);
+        for index in 0..self.views.len() {
+            self.draw_view(&mut render_pass, index, true, false, None);
+        }
+    }
+
+    /// Particles of every window view over what's already there, for when the transparent
+    /// instances went through OIT
+    fn render_particles(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut render_pass = self.begin_load_pass(encoder, target);
+        for index in 0..self.views.len() {
+            self.draw_particles(&mut render_pass, index);
+        }
+    }
+
+    /// Debug lines of every window view, over everything else
+    fn render_debug(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let mut render_pass = self.begin_load_pass(encoder, target);
+        for index in 0..self.views.len() {
+            let view = &self.views[index];
+            view.viewport.set_on(&mut render_pass);
+            self.debug_draw.draw(
+                &mut render_pass,
+                &self.pipeline_cache,
+                &view.uniform_bind_group,
+                view.uniform_offset,
+            );
+        }
+    }
+
+    /// Labels of every window view, then the HUD over the whole window
+    fn render_text(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
+        let text = &self.text;
+        let mut render_pass = self.begin_load_pass(encoder, target);
+        for view in &self.views {
+            view.viewport.set_on(&mut render_pass);
+            text.draw_world(
+                &mut render_pass,
+                &self.pipeline_cache,
+                &view.uniform_bind_group,
+                view.uniform_offset,
+            );
+        }
+        let (width, height) = (self.size.width, self.size.height);
+        render_pass.set_viewport(0.0, 0.0, width as f32, height as f32, 0.0, 1.0);
+        render_pass.set_scissor_rect(0, 0, width, height);
+        text.draw_screen(&mut render_pass, &self.pipeline_cache);
+    }
+
+    /// Window views followed by the monitor's view, in the order `update` culls them
+    fn view(&self, index: usize) -> &View {
+        self.views.get(index).unwrap_or(&self.monitor.view)
+    }
+
+    /// Draws the opaque or the transparent instances view `index` sees, except the ones
+    /// using `skip_material`, and with the opaque ones the skinned model
+    fn draw_view<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        index: usize,
+        transparent: bool,
+        oit: bool,
+        skip_material: Option<usize>,
+    ) {
+        let view = self.view(index);
+        view.viewport.set_on(render_pass);
+        render_pass.set_bind_group(1, &view.uniform_bind_group, &[view.uniform_offset]);
+        render_pass.set_bind_group(2, &self.morph_targets.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, &self.vertex_buffer, 0, 0);
+        render_pass.set_index_buffer(&self.index_buffer, 0, 0);
+
+        let is_drawn = |material: usize| {
+            Some(material) != skip_material
+                && self.materials[material].blend_mode.is_transparent() == transparent
+        };
+        match self.culling_mode {
+            CullingMode::Cpu => {
+                let instances = &self.instance_ring.buffer;
+                render_pass.set_vertex_buffer(1, instances, self.instance_offset, 0);
+                let batches = &self.view_batches[index];
+                let batches = if transparent { &batches.transparent } else { &batches.opaque };
+                for batch in batches.iter().filter(|batch| is_drawn(batch.material)) {
+                    self.set_material(render_pass, batch.material, oit);
+                    let indices = self.lod_mesh.levels[batch.lod].indices.clone();
+                    render_pass.draw_indexed(indices, 0, batch.instances.clone());
+                }
+            }
+            CullingMode::Gpu => {
+                render_pass.set_vertex_buffer(1, &view.cull_target.visible, 0, 0);
+                for (i, batch) in self.gpu_culling.batches().iter().enumerate() {
+                    if is_drawn(batch.material) {
+                        self.set_material(render_pass, batch.material, oit);
+                        self.gpu_culling.draw(render_pass, &view.cull_target, i);
+                    }
+                }
+            }
+        }
+
+        // Opaque, culled by its box as it's a single model
+        if !transparent {
+            if let Some(model) = &self.skinned_model {
+                let frustum = Frustum::from_view_projection(&view.uniforms.view_proj);
+                if frustum.intersects_aabb(&model.bounds) {
+                    model.draw(render_pass, &self.pipeline_cache);
+                }
+            }
+        }
+        // Particles aren't part of the OIT passes, they get their own after the composite
+        if transparent && !oit {
+            self.draw_particles(render_pass, index);
+        }
+    }
+
+    fn draw_particles<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, index: usize) {
+        let view = self.view(index);
+        view.viewport.set_on(render_pass);
+        self.particles.draw(
+            render_pass,
+            &self.pipeline_cache,
+            &view.uniform_bind_group,
+            view.uniform_offset,
+        );
+    }
+
+    fn set_material<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>, index: usize, oit: bool) {
+        let material = &self.materials[index];
+        let pipeline = if oit {
+            material.oit_pipeline.unwrap()
+        } else {
+            material.pipeline
+        };
+        render_pass.set_pipeline(self.pipeline_cache.get(pipeline));
+        render_pass.set_bind_group(0, &material.bind_group, &[]);
+    }
+}
+
+const HELP: &str = "C camera, P projection, V views, T transparency, G culling, L lod fade, \
+    M shape, K clip, R animations, B particle blending, N emitter, X debug lines, J glyphs, \
+    F frame the scene";
+
+/// Font for labels and the HUD, the file in `$FONT` or else the bundled DejaVu Sans Mono.
+fn load_font() -> ab_glyph::FontVec {
+    match std::env::var("FONT") {
+        Ok(path) => TextRenderer::load_font(&path)
+            .unwrap_or_else(|error| panic!("couldn't load {}: {}", path, error)),
+        Err(_) => {
+            let font_bytes = include_bytes!("DejaVuSansMono.ttf");
+            ab_glyph::FontVec::try_from_vec(font_bytes.to_vec()).expect("bundled font")
+        }
+    }
 }
 
-fn get_file_as_byte_vec(filename: &String) -> Vec<u8> {
-    let mut f = File::open(&filename).expect("no file found");
-    let metadata = fs::metadata(&filename).expect("unable to read metadata");
-    let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer).expect("buffer overflow");
+/// Sparks shooting up from above the middle of the grid and falling back down
+fn fountain() -> Emitter {
+    Emitter {
+        position: (0.0, 2.0, 0.0).into(),
+        shape: EmitterShape::Cone {
+            direction: cgmath::Vector3::unit_y(),
+            angle: cgmath::Deg(20.0).into(),
+        },
+        rate: 1500.0,
+        speed: 5.0,
+        lifetime: 3.0,
+        size: 0.05,
+        start_color: [1.0, 0.8, 0.3, 1.0],
+        end_color: [0.8, 0.1, 0.0, 0.0],
+        gravity: (0.0, -4.0, 0.0).into(),
+        drag: 0.2,
+        curl_strength: 2.0,
+        curl_scale: 0.5,
+    }
+}
 
-    buffer
+/// Cycles through the emitter shapes, for trying them out at runtime
+fn next_emitter_shape(shape: &EmitterShape) -> EmitterShape {
+    match shape {
+        EmitterShape::Point => EmitterShape::Sphere { radius: 0.5 },
+        EmitterShape::Sphere { .. } => EmitterShape::Cone {
+            direction: cgmath::Vector3::unit_y(),
+            angle: cgmath::Deg(20.0).into(),
+        },
+        EmitterShape::Cone { .. } => EmitterShape::MeshSurface(primitives::torus(0.6, 0.2, 32, 16)),
+        EmitterShape::MeshSurface(_) => EmitterShape::Point,
+    }
+}
+
+/// Cycles through the projection types, for trying them out at runtime
+fn next_projection(projection: &Projection) -> Projection {
+    match projection {
+        Projection::Perspective { .. } => Projection::Orthographic {
+            height: 10.0,
+            znear: 0.1,
+            zfar: 100.0,
+        },
+        Projection::Orthographic { .. } => Projection::InfiniteReverseZ {
+            fovy: cgmath::Deg(70.0),
+            znear: 0.1,
+        },
+        Projection::InfiniteReverseZ { .. } => Projection::OffCenter {
+            left: -0.05,
+            right: 0.1,
+            bottom: -0.05,
+            top: 0.05,
+            znear: 0.1,
+            zfar: 100.0,
+        },
+        Projection::OffCenter { .. } => Projection::Perspective {
+            fovy: cgmath::Deg(70.0),
+            znear: 0.1,
+            zfar: 100.0,
+        },
+    }
+}
+
+fn build_batches(items: &[LodInstance], first_instance: u32) -> Vec<DrawBatch> {
+    let mut batches: Vec<DrawBatch> = Vec::new();
+    for (i, item) in items.iter().enumerate() {
+        let index = first_instance + i as u32;
+        match batches.last_mut() {
+            Some(batch) if batch.material == item.instance.material && batch.lod == item.lod => {
+                batch.instances.end = index + 1
+            }
+            _ => batches.push(DrawBatch {
+                material: item.instance.material,
+                lod: item.lod,
+                instances: index..index + 1,
+            }),
+        }
+    }
+    batches
 }
 
 pub fn main() {
@@ -528,6 +2127,9 @@ pub fn main() {
 
     use futures::executor::block_on;
     let mut state = block_on(State::new(&window));
+    let mut app_loop = AppLoop::new(60);
+    app_loop.set_frame_rate_limit(Some(144));
+    let mut cursor_grabbed = false;
 
     event_loop.run(move |event, _, control_flow| {
         match event {
@@ -547,23 +2149,59 @@ pub fn main() {
                             _ => {}
                         },
                         WindowEvent::Resized(physical_size) => {
-                            state.resize(*physical_size);
+                            state.resize(*physical_size, window.scale_factor());
                         }
-                        WindowEvent::ScaleFactorChanged { new_inner_size, .. } => {
+                        WindowEvent::ScaleFactorChanged {
+                            scale_factor,
+                            new_inner_size,
+                        } => {
                             // new_inner_size is &mut so w have to dereference it twice
-                            state.resize(**new_inner_size);
+                            state.resize(**new_inner_size, *scale_factor);
                         }
                         _ => {}
                     }
                 }
+
+                if state.wants_cursor_grab() != cursor_grabbed {
+                    cursor_grabbed = state.wants_cursor_grab();
+                    // Not every platform can grab the cursor, mouse look still works without it
+                    let _ = window.set_cursor_grab(cursor_grabbed);
+                    window.set_cursor_visible(!cursor_grabbed);
+                }
+            }
+            Event::DeviceEvent { ref event, .. } => {
+                state.device_input(event);
             }
             Event::RedrawRequested(_) => {
-                state.update();
+                let frame = app_loop.tick();
+                state.update(&frame, app_loop.stats());
                 state.render();
+
+                if frame.index % 60 == 0 {
+                    let stats = app_loop.stats();
+                    let visible = match state.visible_instances() {
+                        Some(count) => format!("{} visible instances", count),
+                        None => "culled on the gpu".to_string(),
+                    };
+                    window.set_title(&format!(
+                        "{:.0} fps, {:.2} ms (min {:.2} ms, max {:.2} ms), {}, {:.2} ACMR",
+                        stats.fps(),
+                        stats.average_frame_time().as_secs_f32() * 1000.0,
+                        stats.min_frame_time().as_secs_f32() * 1000.0,
+                        stats.max_frame_time().as_secs_f32() * 1000.0,
+                        visible,
+                        state.mesh_acmr,
+                    ));
+                }
             }
             Event::MainEventsCleared => {
                 // RedrawRequested will only trigger once, unless we manually request it
-                window.request_redraw();
+                if app_loop.is_frame_due() {
+                    *control_flow = ControlFlow::Poll;
+                    window.request_redraw();
+                } else if let Some(next_frame_at) = app_loop.next_frame_at() {
+                    *control_flow = ControlFlow::WaitUntil(next_frame_at);
+                }
             }
             _ => {}
         }
@@ -574,6 +2212,16 @@ fn compile_my_shader(path: &str, out: &str, shader_type: shaderc::ShaderKind) {
     let mut compiler = shaderc::Compiler::new().unwrap();
     let mut options = shaderc::CompileOptions::new().unwrap();
     options.add_macro_definition("EP", Some("main"));
+    // Includes are looked up next to the file including them
+    options.set_include_callback(|name, _include_type, source, _depth| {
+        let include_path = std::path::Path::new(source).with_file_name(name);
+        let content = fs::read_to_string(&include_path)
+            .map_err(|err| format!("{}: {}", include_path.display(), err))?;
+        Ok(shaderc::ResolvedInclude {
+            resolved_name: include_path.to_string_lossy().into_owned(),
+            content,
+        })
+    });
     let source = fs::read_to_string(path).expect("file doesn't exist");
     let frag = compiler
         .compile_into_spirv(
@@ -588,18 +2236,3 @@ fn compile_my_shader(path: &str, out: &str, shader_type: shaderc::ShaderKind) {
     let mut file = File::create(out).unwrap();
     file.write_all(frag.as_binary_u8()).unwrap();
 }
-
-fn rotate_model(vertices: &mut [Vertex; 5]) {
-    let rotation = cgmath::Quaternion::from_angle_y(cgmath::Deg(1.0));
-    for v in vertices {
-        // let center = cgmath::Vector3::new()
-        let vv = rotation.rotate_vector(cgmath::Vector3::new(
-            v.position[0],
-            v.position[1],
-            v.position[2],
-        ));
-        v.position[0] = vv.x;
-        v.position[1] = vv.y;
-        v.position[2] = vv.z;
-    }
-}
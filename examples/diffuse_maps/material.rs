@@ -0,0 +1,250 @@
+use crate::oit::WeightedBlendedOit;
+use crate::pipeline_cache::{Blend, ColorTarget, DepthState, PipelineCache, PipelineId, PipelineKey};
+use crate::texture;
+use crate::upload::StagingBelt;
+use iced_wgpu::wgpu;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum BlendMode {
+    /// Alpha is ignored.
+    Opaque,
+    /// Fragments with alpha below 0.5 are discarded, the rest are drawn as opaque.
+    AlphaTest,
+    /// Classic `src * a + dst * (1 - a)` blending for textures with straight alpha.
+    AlphaBlend,
+    /// `src * a + dst`, order doesn't matter, good for glows and particles.
+    Additive,
+    /// `src + dst * (1 - a)` for textures whose color is already multiplied by alpha.
+    Premultiplied,
+}
+
+impl BlendMode {
+    /// Transparent materials don't write depth and are drawn after everything opaque,
+    /// sorted back to front.
+    pub fn is_transparent(&self) -> bool {
+        match self {
+            BlendMode::Opaque | BlendMode::AlphaTest => false,
+            BlendMode::AlphaBlend | BlendMode::Additive | BlendMode::Premultiplied => true,
+        }
+    }
+
+    pub fn color_target(&self, format: wgpu::TextureFormat) -> ColorTarget {
+        let (color_blend, alpha_blend) = match self {
+            BlendMode::Opaque | BlendMode::AlphaTest => (Blend::REPLACE, Blend::REPLACE),
+            BlendMode::AlphaBlend => (
+                blend(wgpu::BlendFactor::SrcAlpha, wgpu::BlendFactor::OneMinusSrcAlpha),
+                blend(wgpu::BlendFactor::One, wgpu::BlendFactor::OneMinusSrcAlpha),
+            ),
+            BlendMode::Additive => (
+                blend(wgpu::BlendFactor::SrcAlpha, wgpu::BlendFactor::One),
+                blend(wgpu::BlendFactor::Zero, wgpu::BlendFactor::One),
+            ),
+            BlendMode::Premultiplied => (
+                blend(wgpu::BlendFactor::One, wgpu::BlendFactor::OneMinusSrcAlpha),
+                blend(wgpu::BlendFactor::One, wgpu::BlendFactor::OneMinusSrcAlpha),
+            ),
+        };
+        ColorTarget {
+            format,
+            color_blend,
+            alpha_blend,
+            write_mask: wgpu::ColorWrite::ALL,
+        }
+    }
+
+    pub fn depth_state(&self, depth_compare: wgpu::CompareFunction) -> DepthState {
+        DepthState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: !self.is_transparent(),
+            depth_compare,
+        }
+    }
+
+    pub fn frag_path(&self) -> &'static str {
+        match self {
+            BlendMode::AlphaTest => "examples/diffuse_maps/shader/my_cutout_frag.spv",
+            _ => "examples/diffuse_maps/shader/my_frag.spv",
+        }
+    }
+}
+
+fn blend(src_factor: wgpu::BlendFactor, dst_factor: wgpu::BlendFactor) -> Blend {
+    Blend {
+        src_factor,
+        dst_factor,
+        operation: wgpu::BlendOperation::Add,
+    }
+}
+
+/// What the fragment shaders read of a material besides its texture, see `MaterialParams`
+/// in the shaders. Changes show up after `Material::upload_params`.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct MaterialParams {
+    /// Multiplies the texture's color, alpha included
+    pub tint: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for MaterialParams {}
+unsafe impl bytemuck::Zeroable for MaterialParams {}
+
+impl Default for MaterialParams {
+    fn default() -> Self {
+        Self { tint: [1.0; 4] }
+    }
+}
+
+pub struct Material {
+    pub blend_mode: BlendMode,
+    pub params: MaterialParams,
+    /// `params` as the gpu last saw them
+    uploaded_params: MaterialParams,
+    params_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    pub pipeline: PipelineId,
+    /// Accumulation pipeline used by weighted blended OIT, only set for transparent materials.
+    pub oit_pipeline: Option<PipelineId>,
+    /// Kept to look up variants of the pipelines, e.g. with a different depth test
+    pipeline_key: PipelineKey,
+}
+
+impl Material {
+    /// Layout of the texture and params, bind group 0 of every pipeline drawing materials
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Uint,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                },
+            ],
+            label: Some("texture_bind_group_layout"),
+        })
+    }
+
+    /// `key` describes the geometry side of the pipeline, the fragment shader, blending
+    /// and depth state are filled in from the blend mode.
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        blend_mode: BlendMode,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        texture: &texture::Texture,
+        key: PipelineKey,
+        depth_compare: wgpu::CompareFunction,
+    ) -> Self {
+        let params = MaterialParams::default();
+        let params_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[params]),
+            wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&texture.view),
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&texture.sampler),
+                },
+                wgpu::Binding {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &params_buffer,
+                        range: 0..std::mem::size_of::<MaterialParams>() as wgpu::BufferAddress,
+                    },
+                },
+            ],
+            label: Some("diffuse_bind_group"),
+        });
+
+        let format = key.color_targets[0].format;
+        let pipeline_key = PipelineKey {
+            frag_path: Some(blend_mode.frag_path().to_string()),
+            color_targets: vec![blend_mode.color_target(format)],
+            depth: Some(blend_mode.depth_state(depth_compare)),
+            ..key
+        };
+        let mut material = Self {
+            blend_mode,
+            params,
+            uploaded_params: params,
+            params_buffer,
+            bind_group,
+            pipeline: pipeline_cache.get_or_create(device, &pipeline_key),
+            oit_pipeline: None,
+            pipeline_key,
+        };
+        material.update_oit_pipeline(device, pipeline_cache);
+        material
+    }
+
+    /// Switches the pipelines to another depth test, e.g. when the camera starts
+    /// using a reverse-Z projection.
+    pub fn set_depth_compare(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        depth_compare: wgpu::CompareFunction,
+    ) {
+        self.pipeline_key.depth = Some(self.blend_mode.depth_state(depth_compare));
+        self.pipeline = pipeline_cache.get_or_create(device, &self.pipeline_key);
+        self.update_oit_pipeline(device, pipeline_cache);
+    }
+
+    /// Switches the pipelines to another index format, for meshes that need 32 bit indices.
+    pub fn set_index_format(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        index_format: wgpu::IndexFormat,
+    ) {
+        self.pipeline_key.index_format = index_format;
+        self.pipeline = pipeline_cache.get_or_create(device, &self.pipeline_key);
+        self.update_oit_pipeline(device, pipeline_cache);
+    }
+
+    /// Copies `params` to the gpu if they changed since the last upload.
+    pub fn upload_params(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+    ) {
+        if self.params == self.uploaded_params {
+            return;
+        }
+        let params = [self.params];
+        belt.write_buffer(device, encoder, &self.params_buffer, 0, bytemuck::cast_slice(&params));
+        self.uploaded_params = self.params;
+    }
+
+    fn update_oit_pipeline(&mut self, device: &wgpu::Device, pipeline_cache: &mut PipelineCache) {
+        if !self.blend_mode.is_transparent() {
+            return;
+        }
+        let oit_key = PipelineKey {
+            frag_path: Some("examples/diffuse_maps/shader/oit_accum_frag.spv".to_string()),
+            color_targets: WeightedBlendedOit::color_targets(),
+            ..self.pipeline_key.clone()
+        };
+        self.oit_pipeline = Some(pipeline_cache.get_or_create(device, &oit_key));
+    }
+}
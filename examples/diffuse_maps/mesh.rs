@@ -0,0 +1,453 @@
+use cgmath::{Deg, InnerSpace, Rad};
+use iced_wgpu::wgpu;
+use std::collections::HashMap;
+
+const VERTEX_CACHE_SIZE: usize = 32;
+
+/// An indexed triangle list on the cpu, the input and output of the mesh processing
+/// functions. Attributes are stored per vertex in separate arrays, `normals` and
+/// `tangents` stay empty until they are computed.
+#[derive(Clone, Debug, Default)]
+pub struct Mesh {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    /// xyz is the tangent, w the sign of the bitangent (`cross(normal, tangent) * w`)
+    pub tangents: Vec<[f32; 4]>,
+    pub morph_targets: Vec<MorphTarget>,
+    pub indices: Vec<u32>,
+}
+
+/// A blend shape: how far every vertex of a mesh moves when the target's weight is one.
+/// Weighted targets are added on top of the base mesh, for facial expressions and such.
+#[derive(Clone, Debug, Default)]
+pub struct MorphTarget {
+    pub name: String,
+    /// One per vertex of the mesh
+    pub position_deltas: Vec<[f32; 3]>,
+    /// One per vertex, or empty when the target doesn't change the normals
+    pub normal_deltas: Vec<[f32; 3]>,
+}
+
+impl Mesh {
+    pub fn new(positions: Vec<[f32; 3]>, tex_coords: Vec<[f32; 2]>, indices: Vec<u32>) -> Self {
+        Self {
+            positions,
+            normals: Vec::new(),
+            tex_coords,
+            tangents: Vec::new(),
+            morph_targets: Vec::new(),
+            indices,
+        }
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Replaces the vertices with copies of `sources`, every attribute that is present
+    /// comes along.
+    fn remap_vertices(&mut self, sources: &[u32]) {
+        fn remap<T: Copy>(attribute: &mut Vec<T>, sources: &[u32]) {
+            if !attribute.is_empty() {
+                *attribute = sources.iter().map(|&source| attribute[source as usize]).collect();
+            }
+        }
+        remap(&mut self.positions, sources);
+        remap(&mut self.normals, sources);
+        remap(&mut self.tex_coords, sources);
+        remap(&mut self.tangents, sources);
+        for target in &mut self.morph_targets {
+            remap(&mut target.position_deltas, sources);
+            remap(&mut target.normal_deltas, sources);
+        }
+    }
+
+    fn face_normal(&self, triangle: &[u32]) -> cgmath::Vector3<f32> {
+        let a: cgmath::Vector3<f32> = self.positions[triangle[0] as usize].into();
+        let b: cgmath::Vector3<f32> = self.positions[triangle[1] as usize].into();
+        let c: cgmath::Vector3<f32> = self.positions[triangle[2] as usize].into();
+        // Not normalized, the length is twice the triangle's area
+        (b - a).cross(c - a)
+    }
+
+    /// Smooth normals that keep edges sharper than `crease_angle`.
+    ///
+    /// A corner's normal averages the area weighted normals of the triangles around its
+    /// position whose normal is within `crease_angle` of its own triangle's, so the
+    /// triangles don't have to share vertices to be smoothed. Corners that end up with
+    /// different normals get separate vertices. 180 degrees smooths everything, 0 gives
+    /// flat normals.
+    pub fn compute_normals(&mut self, crease_angle: Deg<f32>) {
+        let cos_crease = Rad::from(crease_angle).0.cos();
+        let face_normals = self
+            .indices
+            .chunks(3)
+            .map(|triangle| self.face_normal(triangle))
+            .collect::<Vec<_>>();
+
+        let mut faces_at_position: HashMap<[u32; 3], Vec<usize>> = HashMap::new();
+        for (face, triangle) in self.indices.chunks(3).enumerate() {
+            for &index in triangle {
+                let key = position_key(self.positions[index as usize]);
+                faces_at_position.entry(key).or_default().push(face);
+            }
+        }
+
+        let mut sources = Vec::new();
+        let mut normals = Vec::new();
+        let mut vertices: HashMap<(u32, [u32; 3]), u32> = HashMap::new();
+        let mut indices = Vec::with_capacity(self.indices.len());
+        for (corner, &index) in self.indices.iter().enumerate() {
+            let face = corner / 3;
+            let face_direction = normalize_or_zero(face_normals[face]);
+            let key = position_key(self.positions[index as usize]);
+            let mut normal = cgmath::Vector3::new(0.0, 0.0, 0.0);
+            for &other in &faces_at_position[&key] {
+                let other_normal = face_normals[other];
+                if normalize_or_zero(other_normal).dot(face_direction) >= cos_crease - 1e-6 {
+                    normal += other_normal;
+                }
+            }
+            let normal: [f32; 3] = normalize_or_zero(normal).into();
+
+            let vertex = *vertices.entry((index, position_key(normal))).or_insert_with(|| {
+                sources.push(index);
+                normals.push(normal);
+                sources.len() as u32 - 1
+            });
+            indices.push(vertex);
+        }
+
+        self.remap_vertices(&sources);
+        self.normals = normals;
+        self.indices = indices;
+    }
+
+    /// MikkTSpace tangents, the tangent space normal maps are baked in by most tools.
+    /// Needs normals and texture coordinates. Vertices whose corners end up with different
+    /// tangents, like along uv seams and mirrored uvs, get split. Returns false when
+    /// MikkTSpace gives up on the mesh, the tangents are left as they were then.
+    pub fn compute_tangents(&mut self) -> bool {
+        assert!(!self.normals.is_empty(), "tangents need normals");
+        assert!(!self.tex_coords.is_empty(), "tangents need texture coordinates");
+
+        let mut geometry = TangentGeometry {
+            mesh: self,
+            tangents: vec![[0.0; 4]; self.indices.len()],
+        };
+        if !mikktspace::generate_tangents(&mut geometry) {
+            return false;
+        }
+        let corner_tangents = geometry.tangents;
+
+        let mut sources = Vec::new();
+        let mut tangents = Vec::new();
+        let mut vertices: HashMap<(u32, [u32; 4]), u32> = HashMap::new();
+        let mut indices = Vec::with_capacity(self.indices.len());
+        for (&index, tangent) in self.indices.iter().zip(corner_tangents) {
+            let vertex = *vertices.entry((index, tangent_key(tangent))).or_insert_with(|| {
+                sources.push(index);
+                tangents.push(tangent);
+                sources.len() as u32 - 1
+            });
+            indices.push(vertex);
+        }
+
+        self.remap_vertices(&sources);
+        self.tangents = tangents;
+        self.indices = indices;
+        true
+    }
+
+    /// Merges vertices whose attributes are all within `epsilon` of each other, 0 merges
+    /// exact duplicates only. Removes unused vertices too.
+    pub fn weld(&mut self, epsilon: f32) {
+        let quantize = |values: &[f32]| -> Vec<i64> {
+            values
+                .iter()
+                .map(|&value| {
+                    if epsilon > 0.0 {
+                        (value / epsilon).round() as i64
+                    } else {
+                        value.to_bits() as i64
+                    }
+                })
+                .collect()
+        };
+
+        let mut sources = Vec::new();
+        let mut vertices: HashMap<Vec<i64>, u32> = HashMap::new();
+        let mut remap: HashMap<u32, u32> = HashMap::new();
+        for &index in &self.indices {
+            if remap.contains_key(&index) {
+                continue;
+            }
+            let i = index as usize;
+            let mut key = quantize(&self.positions[i]);
+            if let Some(normal) = self.normals.get(i) {
+                key.extend(quantize(normal));
+            }
+            if let Some(tex_coords) = self.tex_coords.get(i) {
+                key.extend(quantize(tex_coords));
+            }
+            if let Some(tangent) = self.tangents.get(i) {
+                key.extend(quantize(tangent));
+            }
+            // Vertices that only look alike in the base mesh can still move apart
+            for target in &self.morph_targets {
+                key.extend(quantize(&target.position_deltas[i]));
+                if let Some(normal) = target.normal_deltas.get(i) {
+                    key.extend(quantize(normal));
+                }
+            }
+            let vertex = *vertices.entry(key).or_insert_with(|| {
+                sources.push(index);
+                sources.len() as u32 - 1
+            });
+            remap.insert(index, vertex);
+        }
+
+        self.remap_vertices(&sources);
+        for index in &mut self.indices {
+            *index = remap[index];
+        }
+    }
+
+    /// Reorders the triangles so vertices are reused while they are still in the gpu's
+    /// post-transform cache, then the vertices so they are fetched in order.
+    ///
+    /// Tom Forsyth's "Linear-Speed Vertex Cache Optimisation": triangles are emitted
+    /// greedily, scoring vertices by their position in a simulated LRU cache and by how
+    /// many triangles still use them.
+    pub fn optimize_vertex_cache(&mut self) {
+        let triangle_count = self.indices.len() / 3;
+        let vertex_count = self.positions.len();
+
+        let mut vertex_triangles: Vec<Vec<usize>> = vec![Vec::new(); vertex_count];
+        for (triangle, corners) in self.indices.chunks(3).enumerate() {
+            for &index in corners {
+                vertex_triangles[index as usize].push(triangle);
+            }
+        }
+        let mut remaining = vertex_triangles.iter().map(Vec::len).collect::<Vec<_>>();
+        let mut vertex_score = (0..vertex_count)
+            .map(|vertex| forsyth_score(None, remaining[vertex]))
+            .collect::<Vec<_>>();
+        let triangle_score = |triangle: usize, vertex_score: &[f32]| -> f32 {
+            self.indices[triangle * 3..triangle * 3 + 3]
+                .iter()
+                .map(|&index| vertex_score[index as usize])
+                .sum()
+        };
+        let mut emitted = vec![false; triangle_count];
+        let mut cache: Vec<u32> = Vec::with_capacity(VERTEX_CACHE_SIZE + 3);
+        let mut indices = Vec::with_capacity(self.indices.len());
+
+        let mut next = None;
+        let mut input_cursor = 0;
+        for _ in 0..triangle_count {
+            // Best triangle around the cache. When the cache has nothing left to offer (at
+            // the start, and after finishing a disconnected piece) the next one in input
+            // order, scoring all triangles there would make large meshes quadratic.
+            let triangle = match next {
+                Some(triangle) => triangle,
+                None => {
+                    while emitted[input_cursor] {
+                        input_cursor += 1;
+                    }
+                    input_cursor
+                }
+            };
+            emitted[triangle] = true;
+
+            let corners = [
+                self.indices[triangle * 3],
+                self.indices[triangle * 3 + 1],
+                self.indices[triangle * 3 + 2],
+            ];
+            indices.extend_from_slice(&corners);
+            for &index in &corners {
+                remaining[index as usize] -= 1;
+                cache.retain(|&cached| cached != index);
+            }
+            for &index in corners.iter().rev() {
+                cache.insert(0, index);
+            }
+
+            // Vertices pushed out of the cache score as uncached again
+            for &evicted in cache.iter().skip(VERTEX_CACHE_SIZE) {
+                vertex_score[evicted as usize] = forsyth_score(None, remaining[evicted as usize]);
+            }
+            cache.truncate(VERTEX_CACHE_SIZE);
+            for (position, &index) in cache.iter().enumerate() {
+                let remaining = remaining[index as usize];
+                vertex_score[index as usize] = forsyth_score(Some(position), remaining);
+            }
+
+            next = cache
+                .iter()
+                .flat_map(|&index| &vertex_triangles[index as usize])
+                .copied()
+                .filter(|&triangle| !emitted[triangle])
+                .max_by(|&a, &b| {
+                    let score_a = triangle_score(a, &vertex_score);
+                    let score_b = triangle_score(b, &vertex_score);
+                    score_a.partial_cmp(&score_b).unwrap()
+                });
+        }
+
+        // Vertices in the order the triangles first use them, so fetches are sequential
+        let mut remap = vec![None; vertex_count];
+        let mut sources = Vec::with_capacity(vertex_count);
+        for index in &mut indices {
+            let vertex = *remap[*index as usize].get_or_insert_with(|| {
+                sources.push(*index);
+                sources.len() as u32 - 1
+            });
+            *index = vertex;
+        }
+        self.remap_vertices(&sources);
+        self.indices = indices;
+    }
+
+    /// Average number of vertices transformed per triangle with a FIFO cache the size
+    /// `optimize_vertex_cache` plans for, 0.5 is the best possible and 3 the worst.
+    pub fn average_cache_miss_ratio(&self) -> f32 {
+        let mut cache = std::collections::VecDeque::with_capacity(VERTEX_CACHE_SIZE);
+        let mut misses = 0;
+        for &index in &self.indices {
+            if !cache.contains(&index) {
+                misses += 1;
+                if cache.len() == VERTEX_CACHE_SIZE {
+                    cache.pop_front();
+                }
+                cache.push_back(index);
+            }
+        }
+        misses as f32 / (self.indices.len() / 3).max(1) as f32
+    }
+}
+
+/// Indices in the smallest format that can address a mesh's vertices, 16 bits halve the
+/// size of the index buffer but only reach 65,536 vertices.
+#[derive(Clone, Debug)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    /// `indices` into a mesh with `vertex_count` vertices.
+    pub fn new(indices: &[u32], vertex_count: usize) -> Self {
+        if vertex_count <= u16::MAX as usize + 1 {
+            Indices::U16(indices.iter().map(|&index| index as u16).collect())
+        } else {
+            Indices::U32(indices.to_vec())
+        }
+    }
+
+    /// The format pipelines drawing the index buffer have to be created with.
+    pub fn format(&self) -> wgpu::IndexFormat {
+        match self {
+            Indices::U16(_) => wgpu::IndexFormat::Uint16,
+            Indices::U32(_) => wgpu::IndexFormat::Uint32,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::U16(indices) => indices.len(),
+            Indices::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Indices::U16(indices) => bytemuck::cast_slice(indices),
+            Indices::U32(indices) => bytemuck::cast_slice(indices),
+        }
+    }
+}
+
+/// Vertex score from Forsyth's article, with his constants.
+fn forsyth_score(cache_position: Option<usize>, remaining_triangles: usize) -> f32 {
+    if remaining_triangles == 0 {
+        return -1.0;
+    }
+    let cache_score = match cache_position {
+        None => 0.0,
+        // The last triangle's vertices get a fixed score, so the next triangle doesn't
+        // just reuse the same edge
+        Some(position) if position < 3 => 0.75,
+        Some(position) => {
+            let scale = 1.0 / (VERTEX_CACHE_SIZE - 3) as f32;
+            (1.0 - (position - 3) as f32 * scale).powf(1.5)
+        }
+    };
+    // Vertices with few triangles left get finished first, so they don't linger
+    let valence_boost = 2.0 * (remaining_triangles as f32).powf(-0.5);
+    cache_score + valence_boost
+}
+
+fn normalize_or_zero(vector: cgmath::Vector3<f32>) -> cgmath::Vector3<f32> {
+    let length = vector.magnitude();
+    if length > 0.0 {
+        vector / length
+    } else {
+        vector
+    }
+}
+
+/// Bit patterns of floats, to use them as hash map keys
+fn position_key(position: [f32; 3]) -> [u32; 3] {
+    [position[0].to_bits(), position[1].to_bits(), position[2].to_bits()]
+}
+
+fn tangent_key(tangent: [f32; 4]) -> [u32; 4] {
+    [
+        tangent[0].to_bits(),
+        tangent[1].to_bits(),
+        tangent[2].to_bits(),
+        tangent[3].to_bits(),
+    ]
+}
+
+/// Feeds a mesh to MikkTSpace, which wants per corner data.
+struct TangentGeometry<'a> {
+    mesh: &'a Mesh,
+    /// Tangent of every corner, in the order of `mesh.indices`
+    tangents: Vec<[f32; 4]>,
+}
+
+impl TangentGeometry<'_> {
+    fn vertex(&self, face: usize, vert: usize) -> usize {
+        self.mesh.indices[face * 3 + vert] as usize
+    }
+}
+
+impl mikktspace::Geometry for TangentGeometry<'_> {
+    fn num_faces(&self) -> usize {
+        self.mesh.indices.len() / 3
+    }
+
+    fn num_vertices_of_face(&self, _face: usize) -> usize {
+        3
+    }
+
+    fn position(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.mesh.positions[self.vertex(face, vert)]
+    }
+
+    fn normal(&self, face: usize, vert: usize) -> [f32; 3] {
+        self.mesh.normals[self.vertex(face, vert)]
+    }
+
+    fn tex_coord(&self, face: usize, vert: usize) -> [f32; 2] {
+        self.mesh.tex_coords[self.vertex(face, vert)]
+    }
+
+    fn set_tangent_encoded(&mut self, tangent: [f32; 4], face: usize, vert: usize) {
+        self.tangents[face * 3 + vert] = tangent;
+    }
+}
@@ -0,0 +1,152 @@
+use crate::mesh::MorphTarget;
+use crate::upload::StagingBelt;
+use iced_wgpu::wgpu;
+
+/// Size of the counts in front of the weights, see `MorphWeights` in the shader
+const HEADER_SIZE: wgpu::BufferAddress = 8;
+const WEIGHT_SIZE: wgpu::BufferAddress = std::mem::size_of::<f32>() as wgpu::BufferAddress;
+
+/// Delta of one vertex in one target. vec3s in storage buffers take the room of vec4s.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct MorphDelta {
+    position: [f32; 4],
+    normal: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for MorphDelta {}
+unsafe impl bytemuck::Zeroable for MorphDelta {}
+
+/// The morph targets of a mesh on the gpu, with a weight per target for every instance.
+/// The vertex shader adds the weighted deltas to the vertex before anything else, the
+/// vertex buffer itself never changes.
+pub struct MorphTargets {
+    target_count: usize,
+    instance_count: usize,
+    /// Only read through `bind_group`
+    _deltas: wgpu::Buffer,
+    weights: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+}
+
+impl MorphTargets {
+    /// Layout of the deltas and weights, bind group 2 of the mesh pipelines, see `morph.glsl`
+    pub fn bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        let storage = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::VERTEX,
+            ty: wgpu::BindingType::StorageBuffer {
+                dynamic: false,
+                readonly: true,
+            },
+        };
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[storage(0), storage(1)],
+            label: Some("morph_bind_group_layout"),
+        })
+    }
+
+    /// Every instance starts with `default_weights`. Without targets the buffers still
+    /// exist, the shader then skips morphing.
+    pub fn new(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        targets: &[MorphTarget],
+        default_weights: &[f32],
+        vertex_count: usize,
+        instance_count: usize,
+    ) -> Self {
+        // Target by target, vertex by vertex
+        let mut deltas = Vec::with_capacity(targets.len() * vertex_count);
+        for target in targets {
+            for vertex in 0..vertex_count {
+                let [x, y, z] = target.position_deltas[vertex];
+                let [nx, ny, nz] = target.normal_deltas.get(vertex).copied().unwrap_or_default();
+                deltas.push(MorphDelta {
+                    position: [x, y, z, 0.0],
+                    normal: [nx, ny, nz, 0.0],
+                });
+            }
+        }
+        if deltas.is_empty() {
+            deltas.push(MorphDelta {
+                position: [0.0; 4],
+                normal: [0.0; 4],
+            });
+        }
+        let deltas_size = (deltas.len() * std::mem::size_of::<MorphDelta>()) as wgpu::BufferAddress;
+        let deltas = device
+            .create_buffer_with_data(bytemuck::cast_slice(&deltas), wgpu::BufferUsage::STORAGE);
+
+        // The counts never change, they go in with the starting weights
+        let mut contents = Vec::with_capacity(2 + targets.len() * instance_count);
+        contents.extend_from_slice(&[targets.len() as u32, vertex_count as u32]);
+        for _ in 0..instance_count {
+            contents.extend((0..targets.len()).map(|target| {
+                default_weights.get(target).copied().unwrap_or(0.0).to_bits()
+            }));
+        }
+        if contents.len() == 2 {
+            contents.push(0);
+        }
+        let weights_size = (contents.len() * std::mem::size_of::<u32>()) as wgpu::BufferAddress;
+        let weights = device.create_buffer_with_data(
+            bytemuck::cast_slice(&contents),
+            wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+        );
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &deltas,
+                        range: 0..deltas_size,
+                    },
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &weights,
+                        range: 0..weights_size,
+                    },
+                },
+            ],
+            label: Some("morph_bind_group"),
+        });
+
+        Self {
+            target_count: targets.len(),
+            instance_count,
+            _deltas: deltas,
+            weights,
+            bind_group,
+        }
+    }
+
+    /// Sets the weights of `instance`, one per target.
+    pub fn set_weights(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+        instance: usize,
+        weights: &[f32],
+    ) {
+        assert!(instance < self.instance_count, "instance out of range");
+        let count = weights.len().min(self.target_count);
+        if count == 0 {
+            return;
+        }
+        let first = (instance * self.target_count) as wgpu::BufferAddress;
+        let offset = HEADER_SIZE + WEIGHT_SIZE * first;
+        belt.write_buffer(
+            device,
+            encoder,
+            &self.weights,
+            offset,
+            bytemuck::cast_slice(&weights[..count]),
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use crate::pipeline_cache::{Blend, ColorTarget, PipelineCache, PipelineId, PipelineKey};
+use crate::texture;
+use iced_wgpu::wgpu;
+
+const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum TransparencyMode {
+    /// Transparent instances are sorted back to front and blended one over another.
+    /// Exact for separate objects, wrong for intersecting ones.
+    Sorted,
+    /// Weighted blended order-independent transparency, an approximation that doesn't
+    /// need sorting and handles intersecting geometry.
+    WeightedBlended,
+}
+
+impl TransparencyMode {
+    pub fn toggle(&self) -> Self {
+        match self {
+            TransparencyMode::Sorted => TransparencyMode::WeightedBlended,
+            TransparencyMode::WeightedBlended => TransparencyMode::Sorted,
+        }
+    }
+}
+
+/// Render targets and the composite pass of weighted blended OIT.
+///
+/// Transparent geometry is drawn into `accum` (premultiplied color sum weighted by depth
+/// and alpha) and `revealage` (product of `1 - alpha`), then `composite` resolves the
+/// weighted average over whatever is already in the frame.
+pub struct WeightedBlendedOit {
+    accum: texture::Texture,
+    revealage: texture::Texture,
+    composite_bind_group_layout: wgpu::BindGroupLayout,
+    composite_bind_group: wgpu::BindGroup,
+    composite_pipeline: PipelineId,
+}
+
+impl WeightedBlendedOit {
+    pub fn new(
+        device: &wgpu::Device,
+        sc_desc: &wgpu::SwapChainDescriptor,
+        pipeline_cache: &mut PipelineCache,
+    ) -> Self {
+        let accum = create_target(device, sc_desc, ACCUM_FORMAT, "oit_accum");
+        let revealage = create_target(device, sc_desc, REVEALAGE_FORMAT, "oit_revealage");
+
+        let composite_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                bindings: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::SampledTexture {
+                            multisampled: false,
+                            dimension: wgpu::TextureViewDimension::D2,
+                            component_type: wgpu::TextureComponentType::Float,
+                        },
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::SampledTexture {
+                            multisampled: false,
+                            dimension: wgpu::TextureViewDimension::D2,
+                            component_type: wgpu::TextureComponentType::Float,
+                        },
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStage::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler { comparison: false },
+                    },
+                ],
+                label: Some("oit_composite_bind_group_layout"),
+            });
+        let composite_bind_group =
+            create_composite_bind_group(device, &composite_bind_group_layout, &accum, &revealage);
+
+        let layout = pipeline_cache.create_layout(device, &[&composite_bind_group_layout]);
+        let composite_pipeline = pipeline_cache.get_or_create(
+            device,
+            &PipelineKey {
+                color_targets: vec![ColorTarget {
+                    format: sc_desc.format,
+                    color_blend: Blend {
+                        src_factor: wgpu::BlendFactor::SrcAlpha,
+                        dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    alpha_blend: Blend {
+                        src_factor: wgpu::BlendFactor::Zero,
+                        dst_factor: wgpu::BlendFactor::One,
+                        operation: wgpu::BlendOperation::Add,
+                    },
+                    write_mask: wgpu::ColorWrite::ALL,
+                }],
+                ..PipelineKey::new(
+                    layout,
+                    "examples/diffuse_maps/shader/oit_composite_vert.spv",
+                    "examples/diffuse_maps/shader/oit_composite_frag.spv",
+                )
+            },
+        );
+
+        Self {
+            accum,
+            revealage,
+            composite_bind_group_layout,
+            composite_bind_group,
+            composite_pipeline,
+        }
+    }
+
+    /// Color targets of the accumulation pass, in the order the accumulation
+    /// shader writes them.
+    pub fn color_targets() -> Vec<ColorTarget> {
+        let one_one = Blend {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+        let revealage = Blend {
+            src_factor: wgpu::BlendFactor::Zero,
+            dst_factor: wgpu::BlendFactor::OneMinusSrcColor,
+            operation: wgpu::BlendOperation::Add,
+        };
+        vec![
+            ColorTarget {
+                format: ACCUM_FORMAT,
+                color_blend: one_one,
+                alpha_blend: one_one,
+                write_mask: wgpu::ColorWrite::ALL,
+            },
+            ColorTarget {
+                format: REVEALAGE_FORMAT,
+                color_blend: revealage,
+                alpha_blend: revealage,
+                write_mask: wgpu::ColorWrite::ALL,
+            },
+        ]
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, sc_desc: &wgpu::SwapChainDescriptor) {
+        self.accum = create_target(device, sc_desc, ACCUM_FORMAT, "oit_accum");
+        self.revealage = create_target(device, sc_desc, REVEALAGE_FORMAT, "oit_revealage");
+        self.composite_bind_group = create_composite_bind_group(
+            device,
+            &self.composite_bind_group_layout,
+            &self.accum,
+            &self.revealage,
+        );
+    }
+
+    /// Clears both targets; transparent geometry only tests against the opaque depth.
+    pub fn begin_accum_pass<'a>(
+        &'a self,
+        encoder: &'a mut wgpu::CommandEncoder,
+        depth_view: &'a wgpu::TextureView,
+    ) -> wgpu::RenderPass<'a> {
+        encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[
+                wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: &self.accum.view,
+                    resolve_target: None,
+                    load_op: wgpu::LoadOp::Clear,
+                    store_op: wgpu::StoreOp::Store,
+                    clear_color: wgpu::Color::TRANSPARENT,
+                },
+                wgpu::RenderPassColorAttachmentDescriptor {
+                    attachment: &self.revealage.view,
+                    resolve_target: None,
+                    load_op: wgpu::LoadOp::Clear,
+                    store_op: wgpu::StoreOp::Store,
+                    clear_color: wgpu::Color::WHITE,
+                },
+            ],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachmentDescriptor {
+                attachment: depth_view,
+                depth_load_op: wgpu::LoadOp::Load,
+                depth_store_op: wgpu::StoreOp::Store,
+                clear_depth: 1.0,
+                stencil_load_op: wgpu::LoadOp::Load,
+                stencil_store_op: wgpu::StoreOp::Store,
+                clear_stencil: 0,
+            }),
+        })
+    }
+
+    pub fn composite(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::TextureView,
+        pipeline_cache: &PipelineCache,
+    ) {
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            color_attachments: &[wgpu::RenderPassColorAttachmentDescriptor {
+                attachment: target,
+                resolve_target: None,
+                load_op: wgpu::LoadOp::Load,
+                store_op: wgpu::StoreOp::Store,
+                clear_color: wgpu::Color::BLACK,
+            }],
+            depth_stencil_attachment: None,
+        });
+        render_pass.set_pipeline(pipeline_cache.get(self.composite_pipeline));
+        render_pass.set_bind_group(0, &self.composite_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+fn create_target(
+    device: &wgpu::Device,
+    sc_desc: &wgpu::SwapChainDescriptor,
+    format: wgpu::TextureFormat,
+    label: &str,
+) -> texture::Texture {
+    texture::Texture::create_render_target(device, sc_desc.width, sc_desc.height, format, label)
+}
+
+fn create_composite_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    accum: &texture::Texture,
+    revealage: &texture::Texture,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        bindings: &[
+            wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&accum.view),
+            },
+            wgpu::Binding {
+                binding: 1,
+                resource: wgpu::BindingResource::TextureView(&revealage.view),
+            },
+            wgpu::Binding {
+                binding: 2,
+                resource: wgpu::BindingResource::Sampler(&accum.sampler),
+            },
+        ],
+        label: Some("oit_composite_bind_group"),
+    })
+}
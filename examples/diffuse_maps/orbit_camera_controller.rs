@@ -0,0 +1,179 @@
+use crate::camera::Camera;
+use crate::camera_controller::CameraController;
+use cgmath::{EuclideanSpace, InnerSpace, Rad};
+use iced_winit::winit::event::*;
+use std::f32::consts::FRAC_PI_2;
+
+const MAX_PITCH: f32 = FRAC_PI_2 - 0.01;
+const MIN_DISTANCE: f32 = 0.1;
+const MAX_DISTANCE: f32 = 500.0;
+
+/// Where the camera is relative to the orbit target, in spherical coordinates.
+#[derive(Copy, Clone, Debug)]
+struct Orbit {
+    target: cgmath::Point3<f32>,
+    distance: f32,
+    yaw: Rad<f32>,
+    pitch: Rad<f32>,
+}
+
+impl Orbit {
+    fn from_camera(camera: &Camera) -> Self {
+        let offset = camera.eye - camera.target;
+        let distance = offset.magnitude().max(MIN_DISTANCE);
+        Self {
+            target: camera.target,
+            distance,
+            yaw: Rad(offset.z.atan2(offset.x)),
+            pitch: Rad((offset.y / distance).asin()),
+        }
+    }
+
+    /// Unit vector from the target to the eye
+    fn direction(&self) -> cgmath::Vector3<f32> {
+        let (yaw_sin, yaw_cos) = self.yaw.0.sin_cos();
+        let (pitch_sin, pitch_cos) = self.pitch.0.sin_cos();
+        cgmath::Vector3::new(yaw_cos * pitch_cos, pitch_sin, yaw_sin * pitch_cos)
+    }
+
+    fn lerp(&self, other: &Orbit, t: f32) -> Orbit {
+        Orbit {
+            target: self.target + (other.target - self.target) * t,
+            distance: self.distance + (other.distance - self.distance) * t,
+            yaw: self.yaw + (other.yaw - self.yaw) * t,
+            pitch: self.pitch + (other.pitch - self.pitch) * t,
+        }
+    }
+}
+
+/// Model inspection camera: left drag orbits around the target, middle drag pans,
+/// the scroll wheel zooms. Input moves a goal orbit and the camera eases towards it,
+/// `damping` controls how fast (higher is snappier).
+pub struct OrbitCameraController {
+    current: Orbit,
+    goal: Orbit,
+    /// Radians per pixel of drag
+    rotate_sensitivity: f32,
+    /// Fraction of the distance to the target panned per pixel of drag
+    pan_sensitivity: f32,
+    /// Per second, the camera covers `1 - e^(-damping * dt)` of the way to the goal
+    damping: f32,
+    last_cursor_position: Option<(f32, f32)>,
+    is_rotating: bool,
+    is_panning: bool,
+}
+
+impl OrbitCameraController {
+    pub fn new(camera: &Camera) -> Self {
+        let orbit = Orbit::from_camera(camera);
+        Self {
+            current: orbit,
+            goal: orbit,
+            rotate_sensitivity: 0.005,
+            pan_sensitivity: 0.002,
+            damping: 12.0,
+            last_cursor_position: None,
+            is_rotating: false,
+            is_panning: false,
+        }
+    }
+
+    fn drag(&mut self, dx: f32, dy: f32) {
+        if self.is_rotating {
+            self.goal.yaw += Rad(dx * self.rotate_sensitivity);
+            let pitch = self.goal.pitch.0 + dy * self.rotate_sensitivity;
+            self.goal.pitch = Rad(pitch.max(-MAX_PITCH).min(MAX_PITCH));
+        }
+        if self.is_panning {
+            // Pan in the view plane, scaled by distance so the point under the cursor
+            // roughly follows it
+            let forward = -self.goal.direction();
+            let right = forward.cross(cgmath::Vector3::unit_y()).normalize();
+            let up = right.cross(forward);
+            let scale = self.goal.distance * self.pan_sensitivity;
+            self.goal.target += (up * dy - right * dx) * scale;
+        }
+    }
+}
+
+impl CameraController for OrbitCameraController {
+    fn process_events(&mut self, event: &WindowEvent) -> bool {
+        match event {
+            WindowEvent::MouseInput { state, button, .. } => {
+                let is_pressed = *state == ElementState::Pressed;
+                match button {
+                    MouseButton::Left => {
+                        self.is_rotating = is_pressed;
+                        true
+                    }
+                    MouseButton::Middle => {
+                        self.is_panning = is_pressed;
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = (position.x as f32, position.y as f32);
+                if let Some((last_x, last_y)) = self.last_cursor_position {
+                    self.drag(position.0 - last_x, position.1 - last_y);
+                }
+                self.last_cursor_position = Some(position);
+                self.is_rotating || self.is_panning
+            }
+            WindowEvent::CursorLeft { .. } => {
+                self.last_cursor_position = None;
+                false
+            }
+            WindowEvent::MouseWheel { delta, .. } => {
+                let lines = match delta {
+                    MouseScrollDelta::LineDelta(_, y) => *y,
+                    MouseScrollDelta::PixelDelta(position) => position.y as f32 / 20.0,
+                };
+                let distance = self.goal.distance * 0.9f32.powf(lines);
+                self.goal.distance = distance.max(MIN_DISTANCE).min(MAX_DISTANCE);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn update_camera(&mut self, camera: &mut Camera, dt: f32) {
+        let t = 1.0 - (-self.damping * dt).exp();
+        self.current = self.current.lerp(&self.goal, t);
+
+        camera.target = self.current.target;
+        camera.eye = self.current.target + self.current.direction() * self.current.distance;
+    }
+
+    fn attach(&mut self, camera: &Camera) {
+        self.current = Orbit::from_camera(camera);
+        self.goal = self.current;
+    }
+
+    fn detach(&mut self) {
+        self.is_rotating = false;
+        self.is_panning = false;
+        self.last_cursor_position = None;
+    }
+
+    /// Keeps the viewing direction and moves back until the bounding sphere of the
+    /// box fits into the field of view. Orthographic cameras only get recentered, their
+    /// distance doesn't change what's visible.
+    fn frame_bounds(&mut self, min: cgmath::Point3<f32>, max: cgmath::Point3<f32>, camera: &Camera) {
+        let center = cgmath::Point3::from_vec((min.to_vec() + max.to_vec()) * 0.5);
+        let radius = (max - min).magnitude() * 0.5;
+        self.goal.target = center;
+
+        if let Some(fovy) = camera.projection.fovy() {
+            let half_fovy = fovy * 0.5;
+            // The horizontal field of view is the narrower one on portrait windows
+            let half_fov = if camera.aspect < 1.0 {
+                Rad((half_fovy.0.tan() * camera.aspect).atan())
+            } else {
+                half_fovy
+            };
+            self.goal.distance = (radius / half_fov.0.sin()).max(MIN_DISTANCE).min(MAX_DISTANCE);
+        }
+    }
+}
@@ -0,0 +1,601 @@
+use crate::material::BlendMode;
+use crate::mesh::Mesh;
+use crate::pipeline_cache::{LayoutId, PipelineCache, PipelineId, PipelineKey};
+use crate::upload::StagingBelt;
+use cgmath::InnerSpace;
+use iced_wgpu::wgpu;
+
+const WORKGROUP_SIZE: u32 = 64;
+/// Bitonic sorting works on powers of two, float indices in the keys stay exact up to here
+const MAX_CAPACITY: u32 = 1 << 24;
+
+/// Simulation state of one particle, only ever touched by the compute shaders.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct Particle {
+    /// w is the age in seconds
+    position: [f32; 4],
+    /// w is the lifetime in seconds, a particle with an age past it is dead
+    velocity: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for Particle {}
+unsafe impl bytemuck::Zeroable for Particle {}
+
+/// What a particle is drawn with, written by the compute shaders in drawing order. Bound
+/// as instance buffer like `InstanceRaw`, every instance is a camera facing quad.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ParticleInstance {
+    /// w is the size, zero for dead particles so they cover no pixels
+    position: [f32; 4],
+    color: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for ParticleInstance {}
+unsafe impl bytemuck::Zeroable for ParticleInstance {}
+
+impl ParticleInstance {
+    pub fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        wgpu::VertexBufferDescriptor {
+            stride: std::mem::size_of::<ParticleInstance>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Instance,
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: 16,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float4,
+                },
+            ],
+        }
+    }
+}
+
+/// A triangle mesh surface emitters spawn on. `a`'s w is the area of all triangles up to
+/// and including this one, the shader picks triangles by area with a binary search.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+struct SurfaceTriangle {
+    a: [f32; 4],
+    b: [f32; 4],
+    c: [f32; 4],
+    normal: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for SurfaceTriangle {}
+unsafe impl bytemuck::Zeroable for SurfaceTriangle {}
+
+/// See `Simulation` in the particle compute shaders.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct SimulationUniforms {
+    emitter_position: [f32; 4],
+    /// xyz is the cone's axis, w the cosine of its half angle
+    emitter_direction: [f32; 4],
+    /// xyz is the acceleration, w the drag
+    gravity: [f32; 4],
+    start_color: [f32; 4],
+    end_color: [f32; 4],
+    eye: [f32; 4],
+    shape: u32,
+    spawn_count: u32,
+    seed: u32,
+    capacity: u32,
+    radius: f32,
+    speed: f32,
+    lifetime: f32,
+    size: f32,
+    curl_strength: f32,
+    curl_scale: f32,
+    dt: f32,
+    triangle_count: u32,
+}
+
+unsafe impl bytemuck::Pod for SimulationUniforms {}
+unsafe impl bytemuck::Zeroable for SimulationUniforms {}
+
+/// Where new particles appear and which way they fly off.
+#[derive(Clone, Debug)]
+pub enum EmitterShape {
+    /// At the emitter, in every direction
+    Point,
+    /// Anywhere inside the sphere, away from its center
+    Sphere { radius: f32 },
+    /// At the emitter, within `angle` of `direction`
+    Cone {
+        direction: cgmath::Vector3<f32>,
+        angle: cgmath::Rad<f32>,
+    },
+    /// Anywhere on the mesh placed at the emitter, along the normals of its triangles
+    MeshSurface(Mesh),
+}
+
+impl EmitterShape {
+    /// See `SHAPE_*` in particles_simulate.comp
+    fn id(&self) -> u32 {
+        match self {
+            EmitterShape::Point => 0,
+            EmitterShape::Sphere { .. } => 1,
+            EmitterShape::Cone { .. } => 2,
+            EmitterShape::MeshSurface(_) => 3,
+        }
+    }
+
+    fn triangles(&self) -> Vec<SurfaceTriangle> {
+        let mesh = match self {
+            EmitterShape::MeshSurface(mesh) => mesh,
+            _ => return Vec::new(),
+        };
+        let mut area = 0.0;
+        mesh.indices
+            .chunks(3)
+            .map(|triangle| {
+                let corner = |i: usize| cgmath::Vector3::from(mesh.positions[triangle[i] as usize]);
+                let (a, b, c) = (corner(0), corner(1), corner(2));
+                let cross = (b - a).cross(c - a);
+                area += cross.magnitude() * 0.5;
+                let normal = if cross.magnitude2() > 0.0 {
+                    cross.normalize()
+                } else {
+                    cross
+                };
+                SurfaceTriangle {
+                    a: a.extend(area).into(),
+                    b: b.extend(0.0).into(),
+                    c: c.extend(0.0).into(),
+                    normal: normal.extend(0.0).into(),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Spawns particles and says how they move, age and look.
+#[derive(Clone, Debug)]
+pub struct Emitter {
+    pub position: cgmath::Point3<f32>,
+    pub shape: EmitterShape,
+    /// Particles per second
+    pub rate: f32,
+    /// Starting speed, every particle gets within a quarter of it
+    pub speed: f32,
+    /// Seconds the longest living particles live, every particle gets at least half
+    pub lifetime: f32,
+    /// Half the width of a particle's quad
+    pub size: f32,
+    /// Color at birth, faded to `end_color` over the particle's life
+    pub start_color: [f32; 4],
+    pub end_color: [f32; 4],
+    pub gravity: cgmath::Vector3<f32>,
+    /// Fraction of the velocity lost per second
+    pub drag: f32,
+    /// Acceleration along a curl noise flow field, swirls without clumping
+    pub curl_strength: f32,
+    /// Frequency of the flow field, higher makes smaller swirls
+    pub curl_scale: f32,
+}
+
+/// How particles blend with what's behind them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParticleBlend {
+    /// Order doesn't matter, no sorting needed
+    Additive,
+    /// Alpha blended, sorted back to front on the gpu
+    Sorted,
+}
+
+impl ParticleBlend {
+    pub fn toggle(&self) -> Self {
+        match self {
+            ParticleBlend::Additive => ParticleBlend::Sorted,
+            ParticleBlend::Sorted => ParticleBlend::Additive,
+        }
+    }
+
+    fn blend_mode(&self) -> BlendMode {
+        match self {
+            ParticleBlend::Additive => BlendMode::Additive,
+            ParticleBlend::Sorted => BlendMode::AlphaBlend,
+        }
+    }
+}
+
+/// Particles simulated on the gpu.
+///
+/// Every frame a compute shader spawns particles from the emitter into dead slots and
+/// moves the living ones. Their distance to the camera goes into a list of keys, which
+/// a bitonic sort orders back to front when the particles are alpha blended. A last
+/// compute pass writes the particles in key order as instances, drawn as camera facing
+/// quads. Nothing comes back to the cpu, it never knows how many particles are alive.
+pub struct ParticleSystem {
+    emitter: Emitter,
+    blend: ParticleBlend,
+    capacity: u32,
+    /// Fraction of a particle carried over to the next frame's spawn count
+    spawn_debt: f32,
+    seed: u32,
+    uniform_buffer: wgpu::Buffer,
+    particles: wgpu::Buffer,
+    spawned: wgpu::Buffer,
+    keys: wgpu::Buffer,
+    instances: wgpu::Buffer,
+    simulate_pipeline: wgpu::ComputePipeline,
+    gather_pipeline: wgpu::ComputePipeline,
+    sort_pipeline: wgpu::ComputePipeline,
+    simulation_layout: wgpu::BindGroupLayout,
+    /// Rebuilt with the triangle buffer when the emitter changes
+    simulation_bind_group: wgpu::BindGroup,
+    triangle_count: u32,
+    /// Keeps the triangles alive, only read through `simulation_bind_group`
+    _triangles: wgpu::Buffer,
+    /// Reads the `k` and `j` of every bitonic step from its own slot of a uniform buffer
+    sort_bind_group: wgpu::BindGroup,
+    /// Uniforms of every bitonic step, they never change
+    _sort_steps: wgpu::Buffer,
+    sort_step_count: u32,
+    render_key: PipelineKey,
+    render_pipeline: PipelineId,
+}
+
+impl ParticleSystem {
+    /// Room for `capacity` particles, rounded up to a power of two. `layout` is the view
+    /// uniforms' alone, the particle shaders read them from bind group 0.
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        layout: LayoutId,
+        color_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        capacity: u32,
+        emitter: Emitter,
+        blend: ParticleBlend,
+    ) -> Self {
+        let capacity = capacity.max(WORKGROUP_SIZE).next_power_of_two();
+        assert!(capacity <= MAX_CAPACITY, "too many particles");
+        let count = capacity as usize;
+
+        let storage_usage = wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST;
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("particle_uniforms"),
+            size: std::mem::size_of::<SimulationUniforms>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        });
+        // Zeroed particles have lived their whole lifetime of zero, they start out dead
+        let particles = device.create_buffer_with_data(
+            bytemuck::cast_slice(&vec![Particle::default(); count]),
+            storage_usage,
+        );
+        let spawned = device.create_buffer_with_data(bytemuck::cast_slice(&[0u32]), storage_usage);
+        let keys = device.create_buffer_with_data(
+            bytemuck::cast_slice(&vec![[0.0f32; 2]; count]),
+            storage_usage,
+        );
+        let instances = device.create_buffer_with_data(
+            bytemuck::cast_slice(&vec![ParticleInstance::default(); count]),
+            wgpu::BufferUsage::VERTEX | wgpu::BufferUsage::STORAGE,
+        );
+
+        let entry = |binding, ty| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStage::COMPUTE,
+            ty,
+        };
+        let storage = |readonly| wgpu::BindingType::StorageBuffer {
+            dynamic: false,
+            readonly,
+        };
+        let simulation_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                entry(0, wgpu::BindingType::UniformBuffer { dynamic: false }),
+                entry(1, storage(false)),
+                entry(2, storage(false)),
+                entry(3, storage(false)),
+                entry(4, storage(true)),
+                entry(5, storage(false)),
+            ],
+            label: Some("particle_simulation_bind_group_layout"),
+        });
+        let sort_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                entry(0, wgpu::BindingType::UniformBuffer { dynamic: true }),
+                entry(1, storage(false)),
+            ],
+            label: Some("particle_sort_bind_group_layout"),
+        });
+        let mut compute_pipeline = |layout: &wgpu::BindGroupLayout, path: &str| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                bind_group_layouts: &[layout],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                layout: &pipeline_layout,
+                compute_stage: wgpu::ProgrammableStageDescriptor {
+                    module: pipeline_cache.shader_module(device, path),
+                    entry_point: "main",
+                },
+            })
+        };
+        let simulate_pipeline = compute_pipeline(
+            &simulation_layout,
+            "examples/diffuse_maps/shader/particles_simulate_comp.spv",
+        );
+        let gather_pipeline = compute_pipeline(
+            &simulation_layout,
+            "examples/diffuse_maps/shader/particles_gather_comp.spv",
+        );
+        let sort_pipeline =
+            compute_pipeline(&sort_layout, "examples/diffuse_maps/shader/particles_sort_comp.spv");
+
+        // Runs of k keys, merged by comparing keys j apart for every j below k
+        let mut steps = Vec::new();
+        let mut k = 2;
+        while k <= capacity {
+            let mut j = k / 2;
+            while j > 0 {
+                steps.push((k, j));
+                j /= 2;
+            }
+            k *= 2;
+        }
+        let slot = wgpu::BIND_BUFFER_ALIGNMENT as usize / std::mem::size_of::<u32>();
+        let mut step_data = vec![0u32; steps.len() * slot];
+        for (i, &(k, j)) in steps.iter().enumerate() {
+            step_data[i * slot] = k;
+            step_data[i * slot + 1] = j;
+        }
+        let sort_steps = device
+            .create_buffer_with_data(bytemuck::cast_slice(&step_data), wgpu::BufferUsage::UNIFORM);
+        let keys_size = capacity as wgpu::BufferAddress * 8;
+        let sort_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &sort_layout,
+            bindings: &[buffer_binding(0, &sort_steps, 8), buffer_binding(1, &keys, keys_size)],
+            label: Some("particle_sort_bind_group"),
+        });
+
+        let blend_mode = blend.blend_mode();
+        let key = PipelineKey::new(
+            layout,
+            "examples/diffuse_maps/shader/particle_vert.spv",
+            "examples/diffuse_maps/shader/particle_frag.spv",
+        )
+        .with_vertex_layouts(&[ParticleInstance::desc()]);
+        let render_key = PipelineKey {
+            color_targets: vec![blend_mode.color_target(color_format)],
+            depth: Some(blend_mode.depth_state(depth_compare)),
+            ..key
+        };
+        let render_pipeline = pipeline_cache.get_or_create(device, &render_key);
+
+        let triangles = emitter.shape.triangles();
+        let (triangle_buffer, simulation_bind_group) = create_simulation_bind_group(
+            device,
+            &simulation_layout,
+            &triangles,
+            [&uniform_buffer, &particles, &spawned, &keys, &instances],
+            capacity,
+        );
+
+        Self {
+            emitter,
+            blend,
+            capacity,
+            spawn_debt: 0.0,
+            seed: 0,
+            uniform_buffer,
+            particles,
+            spawned,
+            keys,
+            instances,
+            simulate_pipeline,
+            gather_pipeline,
+            sort_pipeline,
+            simulation_layout,
+            simulation_bind_group,
+            triangle_count: triangles.len() as u32,
+            _triangles: triangle_buffer,
+            sort_bind_group,
+            _sort_steps: sort_steps,
+            sort_step_count: steps.len() as u32,
+            render_key,
+            render_pipeline,
+        }
+    }
+
+    pub fn emitter(&self) -> &Emitter {
+        &self.emitter
+    }
+
+    /// Particles already flying keep going, only new ones come from `emitter`.
+    pub fn set_emitter(&mut self, device: &wgpu::Device, emitter: Emitter) {
+        let triangles = emitter.shape.triangles();
+        let (triangle_buffer, bind_group) = create_simulation_bind_group(
+            device,
+            &self.simulation_layout,
+            &triangles,
+            [&self.uniform_buffer, &self.particles, &self.spawned, &self.keys, &self.instances],
+            self.capacity,
+        );
+        self._triangles = triangle_buffer;
+        self.simulation_bind_group = bind_group;
+        self.triangle_count = triangles.len() as u32;
+        self.emitter = emitter;
+    }
+
+    pub fn blend(&self) -> ParticleBlend {
+        self.blend
+    }
+
+    pub fn set_blend(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        blend: ParticleBlend,
+    ) {
+        self.blend = blend;
+        let format = self.render_key.color_targets[0].format;
+        self.render_key.color_targets = vec![blend.blend_mode().color_target(format)];
+        self.render_pipeline = pipeline_cache.get_or_create(device, &self.render_key);
+    }
+
+    /// Switches the pipeline to another depth test, like `Material::set_depth_compare`.
+    pub fn set_depth_compare(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        depth_compare: wgpu::CompareFunction,
+    ) {
+        self.render_key.depth = Some(self.blend.blend_mode().depth_state(depth_compare));
+        self.render_pipeline = pipeline_cache.get_or_create(device, &self.render_key);
+    }
+
+    /// Records spawning and moving the particles by `dt` seconds, sorted for a camera at
+    /// `eye`. Draw with `draw` in a pass recorded after this.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+        dt: f32,
+        eye: cgmath::Point3<f32>,
+    ) {
+        let emitter = &self.emitter;
+        self.spawn_debt += emitter.rate * dt;
+        let spawn_count = self.spawn_debt.floor();
+        self.spawn_debt -= spawn_count;
+        self.seed = self.seed.wrapping_add(1);
+
+        let (radius, direction, cos_angle) = match emitter.shape {
+            EmitterShape::Sphere { radius } => (radius, cgmath::Vector3::unit_y(), 1.0),
+            EmitterShape::Cone { direction, angle } => (0.0, direction.normalize(), angle.0.cos()),
+            _ => (0.0, cgmath::Vector3::unit_y(), 1.0),
+        };
+        let uniforms = SimulationUniforms {
+            emitter_position: [emitter.position.x, emitter.position.y, emitter.position.z, 1.0],
+            emitter_direction: direction.extend(cos_angle).into(),
+            gravity: emitter.gravity.extend(emitter.drag).into(),
+            start_color: emitter.start_color,
+            end_color: emitter.end_color,
+            eye: [eye.x, eye.y, eye.z, 1.0],
+            shape: emitter.shape.id(),
+            spawn_count: spawn_count as u32,
+            seed: self.seed,
+            capacity: self.capacity,
+            radius,
+            speed: emitter.speed,
+            lifetime: emitter.lifetime,
+            size: emitter.size,
+            curl_strength: emitter.curl_strength,
+            curl_scale: emitter.curl_scale,
+            dt,
+            triangle_count: self.triangle_count,
+        };
+        belt.write_buffer(
+            device,
+            encoder,
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[uniforms]),
+        );
+        belt.write_buffer(device, encoder, &self.spawned, 0, bytemuck::cast_slice(&[0u32]));
+
+        let groups = self.capacity / WORKGROUP_SIZE;
+        {
+            let mut compute_pass = encoder.begin_compute_pass();
+            compute_pass.set_pipeline(&self.simulate_pipeline);
+            compute_pass.set_bind_group(0, &self.simulation_bind_group, &[]);
+            compute_pass.dispatch(groups, 1, 1);
+        }
+        if self.blend == ParticleBlend::Sorted {
+            // A pass per step, so every step sees the swaps of the one before
+            for step in 0..self.sort_step_count {
+                let offset = step * wgpu::BIND_BUFFER_ALIGNMENT as wgpu::DynamicOffset;
+                let mut compute_pass = encoder.begin_compute_pass();
+                compute_pass.set_pipeline(&self.sort_pipeline);
+                compute_pass.set_bind_group(0, &self.sort_bind_group, &[offset]);
+                compute_pass.dispatch(groups, 1, 1);
+            }
+        }
+        let mut compute_pass = encoder.begin_compute_pass();
+        compute_pass.set_pipeline(&self.gather_pipeline);
+        compute_pass.set_bind_group(0, &self.simulation_bind_group, &[]);
+        compute_pass.dispatch(groups, 1, 1);
+    }
+
+    /// Draws every particle slot, the dead ones come out as empty quads. `uniform_bind_group`
+    /// is a view's, bound at `uniform_offset`.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipeline_cache: &'a PipelineCache,
+        uniform_bind_group: &'a wgpu::BindGroup,
+        uniform_offset: wgpu::DynamicOffset,
+    ) {
+        render_pass.set_pipeline(pipeline_cache.get(self.render_pipeline));
+        render_pass.set_bind_group(0, uniform_bind_group, &[uniform_offset]);
+        render_pass.set_vertex_buffer(0, &self.instances, 0, 0);
+        render_pass.draw(0..6, 0..self.capacity);
+    }
+}
+
+/// The triangles of the emitter's shape in a buffer, and the bind group of the simulation
+/// with them and `buffers`: the uniforms, particles, spawn counter, keys and instances.
+fn create_simulation_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    triangles: &[SurfaceTriangle],
+    buffers: [&wgpu::Buffer; 5],
+    capacity: u32,
+) -> (wgpu::Buffer, wgpu::BindGroup) {
+    // Storage buffers can't be empty
+    let triangles = if triangles.is_empty() {
+        vec![SurfaceTriangle::default()]
+    } else {
+        triangles.to_vec()
+    };
+    let triangle_size = std::mem::size_of::<SurfaceTriangle>() * triangles.len();
+    let triangle_buffer = device
+        .create_buffer_with_data(bytemuck::cast_slice(&triangles), wgpu::BufferUsage::STORAGE);
+
+    let capacity = capacity as wgpu::BufferAddress;
+    let [uniforms, particles, spawned, keys, instances] = buffers;
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        bindings: &[
+            buffer_binding(
+                0,
+                uniforms,
+                std::mem::size_of::<SimulationUniforms>() as wgpu::BufferAddress,
+            ),
+            buffer_binding(
+                1,
+                particles,
+                capacity * std::mem::size_of::<Particle>() as wgpu::BufferAddress,
+            ),
+            buffer_binding(2, spawned, 4),
+            buffer_binding(3, keys, capacity * 8),
+            buffer_binding(4, &triangle_buffer, triangle_size as wgpu::BufferAddress),
+            buffer_binding(
+                5,
+                instances,
+                capacity * std::mem::size_of::<ParticleInstance>() as wgpu::BufferAddress,
+            ),
+        ],
+        label: Some("particle_simulation_bind_group"),
+    });
+    (triangle_buffer, bind_group)
+}
+
+fn buffer_binding(binding: u32, buffer: &wgpu::Buffer, size: wgpu::BufferAddress) -> wgpu::Binding {
+    wgpu::Binding {
+        binding,
+        resource: wgpu::BindingResource::Buffer {
+            buffer,
+            range: 0..size,
+        },
+    }
+}
@@ -0,0 +1,359 @@
+use iced_wgpu::wgpu;
+use std::collections::HashMap;
+use std::fs;
+use std::fs::File;
+use std::io::Read;
+
+/// Handle of a pipeline layout registered in a `PipelineCache`.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct LayoutId(usize);
+
+/// Handle of a render pipeline stored in a `PipelineCache`.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct PipelineId(usize);
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct VertexAttribute {
+    pub offset: wgpu::BufferAddress,
+    pub format: wgpu::VertexFormat,
+    pub shader_location: u32,
+}
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct VertexLayout {
+    pub stride: wgpu::BufferAddress,
+    pub step_mode: wgpu::InputStepMode,
+    pub attributes: Vec<VertexAttribute>,
+}
+
+impl VertexLayout {
+    pub fn from_desc(desc: &wgpu::VertexBufferDescriptor) -> Self {
+        Self {
+            stride: desc.stride,
+            step_mode: desc.step_mode,
+            attributes: desc
+                .attributes
+                .iter()
+                .map(|attr| VertexAttribute {
+                    offset: attr.offset,
+                    format: attr.format,
+                    shader_location: attr.shader_location,
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Blend {
+    pub src_factor: wgpu::BlendFactor,
+    pub dst_factor: wgpu::BlendFactor,
+    pub operation: wgpu::BlendOperation,
+}
+
+impl Blend {
+    pub const REPLACE: Blend = Blend {
+        src_factor: wgpu::BlendFactor::One,
+        dst_factor: wgpu::BlendFactor::Zero,
+        operation: wgpu::BlendOperation::Add,
+    };
+
+    fn to_desc(&self) -> wgpu::BlendDescriptor {
+        wgpu::BlendDescriptor {
+            src_factor: self.src_factor,
+            dst_factor: self.dst_factor,
+            operation: self.operation,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct ColorTarget {
+    pub format: wgpu::TextureFormat,
+    pub color_blend: Blend,
+    pub alpha_blend: Blend,
+    pub write_mask: wgpu::ColorWrite,
+}
+
+impl ColorTarget {
+    pub fn replace(format: wgpu::TextureFormat) -> Self {
+        Self {
+            format,
+            color_blend: Blend::REPLACE,
+            alpha_blend: Blend::REPLACE,
+            write_mask: wgpu::ColorWrite::ALL,
+        }
+    }
+
+    fn to_desc(&self) -> wgpu::ColorStateDescriptor {
+        wgpu::ColorStateDescriptor {
+            format: self.format,
+            color_blend: self.color_blend.to_desc(),
+            alpha_blend: self.alpha_blend.to_desc(),
+            write_mask: self.write_mask,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct DepthState {
+    pub format: wgpu::TextureFormat,
+    pub depth_write_enabled: bool,
+    pub depth_compare: wgpu::CompareFunction,
+}
+
+impl DepthState {
+    fn to_desc(&self) -> wgpu::DepthStencilStateDescriptor {
+        wgpu::DepthStencilStateDescriptor {
+            format: self.format,
+            depth_write_enabled: self.depth_write_enabled,
+            depth_compare: self.depth_compare,
+            stencil_front: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_back: wgpu::StencilStateFaceDescriptor::IGNORE,
+            stencil_read_mask: 0,
+            stencil_write_mask: 0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Rasterization {
+    pub front_face: wgpu::FrontFace,
+    pub cull_mode: wgpu::CullMode,
+}
+
+impl Rasterization {
+    pub fn new(front_face: wgpu::FrontFace, cull_mode: wgpu::CullMode) -> Self {
+        Self {
+            front_face,
+            cull_mode,
+        }
+    }
+
+    fn to_desc(&self) -> wgpu::RasterizationStateDescriptor {
+        wgpu::RasterizationStateDescriptor {
+            front_face: self.front_face,
+            cull_mode: self.cull_mode,
+            depth_bias: 0,
+            depth_bias_slope_scale: 0.0,
+            depth_bias_clamp: 0.0,
+        }
+    }
+}
+
+/// Everything that makes two render pipelines different.
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct PipelineKey {
+    pub layout: LayoutId,
+    pub vert_path: String,
+    pub frag_path: Option<String>,
+    pub vertex_layouts: Vec<VertexLayout>,
+    pub primitive_topology: wgpu::PrimitiveTopology,
+    pub rasterization: Rasterization,
+    pub color_targets: Vec<ColorTarget>,
+    pub depth: Option<DepthState>,
+    pub index_format: wgpu::IndexFormat,
+    pub sample_count: u32,
+}
+
+impl PipelineKey {
+    /// Key with the state all the examples used to hardcode: no vertex buffers, no culling,
+    /// no depth and a single opaque `Bgra8UnormSrgb` target.
+    pub fn new(layout: LayoutId, vert_path: &str, frag_path: &str) -> Self {
+        Self {
+            layout,
+            vert_path: vert_path.to_string(),
+            frag_path: Some(frag_path.to_string()),
+            vertex_layouts: Vec::new(),
+            primitive_topology: wgpu::PrimitiveTopology::TriangleList,
+            rasterization: Rasterization::new(wgpu::FrontFace::Ccw, wgpu::CullMode::None),
+            color_targets: vec![ColorTarget::replace(wgpu::TextureFormat::Bgra8UnormSrgb)],
+            depth: None,
+            index_format: wgpu::IndexFormat::Uint16,
+            sample_count: 1,
+        }
+    }
+
+    pub fn with_vertex_layouts(mut self, descs: &[wgpu::VertexBufferDescriptor]) -> Self {
+        self.vertex_layouts = descs.iter().map(VertexLayout::from_desc).collect();
+        self
+    }
+
+    /// Has to match the index buffers drawn with the pipeline, see `mesh::Indices`.
+    pub fn with_index_format(mut self, index_format: wgpu::IndexFormat) -> Self {
+        self.index_format = index_format;
+        self
+    }
+}
+
+#[derive(Copy, Clone, Debug, Default)]
+pub struct PipelineCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub shader_modules: usize,
+    pub pipelines: usize,
+}
+
+impl PipelineCacheStats {
+    pub fn hit_rate(&self) -> f32 {
+        let total = self.hits + self.misses;
+        if total == 0 {
+            0.0
+        } else {
+            self.hits as f32 / total as f32
+        }
+    }
+}
+
+/// Creates render pipelines on demand and hands out the existing one when the same
+/// state is requested again. Shader modules are shared between pipelines as well.
+pub struct PipelineCache {
+    layouts: Vec<wgpu::PipelineLayout>,
+    shader_modules: HashMap<String, wgpu::ShaderModule>,
+    pipelines: Vec<wgpu::RenderPipeline>,
+    ids: HashMap<PipelineKey, PipelineId>,
+    hits: u64,
+    misses: u64,
+}
+
+impl PipelineCache {
+    pub fn new() -> Self {
+        Self {
+            layouts: Vec::new(),
+            shader_modules: HashMap::new(),
+            pipelines: Vec::new(),
+            ids: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Bind group layouts can't be hashed, so a layout is registered once and referenced
+    /// from keys by its id.
+    pub fn create_layout(
+        &mut self,
+        device: &wgpu::Device,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+    ) -> LayoutId {
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            bind_group_layouts,
+        });
+        self.layouts.push(layout);
+        LayoutId(self.layouts.len() - 1)
+    }
+
+    pub fn get_or_create(&mut self, device: &wgpu::Device, key: &PipelineKey) -> PipelineId {
+        if let Some(id) = self.ids.get(key) {
+            self.hits += 1;
+            return *id;
+        }
+        self.misses += 1;
+
+        self.load_shader_module(device, &key.vert_path);
+        if let Some(frag_path) = &key.frag_path {
+            self.load_shader_module(device, frag_path);
+        }
+        let pipeline = self.create_pipeline(device, key);
+        self.pipelines.push(pipeline);
+
+        let id = PipelineId(self.pipelines.len() - 1);
+        self.ids.insert(key.clone(), id);
+        id
+    }
+
+    pub fn get(&self, id: PipelineId) -> &wgpu::RenderPipeline {
+        &self.pipelines[id.0]
+    }
+
+    pub fn stats(&self) -> PipelineCacheStats {
+        PipelineCacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            shader_modules: self.shader_modules.len(),
+            pipelines: self.pipelines.len(),
+        }
+    }
+
+    /// Shared shader module for pipelines the cache doesn't build, like compute pipelines.
+    pub fn shader_module(&mut self, device: &wgpu::Device, path: &str) -> &wgpu::ShaderModule {
+        self.load_shader_module(device, path);
+        &self.shader_modules[path]
+    }
+
+    fn load_shader_module(&mut self, device: &wgpu::Device, path: &str) {
+        if self.shader_modules.contains_key(path) {
+            return;
+        }
+        let spv = get_file_as_byte_vec(path);
+        let module =
+            device.create_shader_module(&wgpu::read_spirv(std::io::Cursor::new(&spv[..])).unwrap());
+        self.shader_modules.insert(path.to_string(), module);
+    }
+
+    fn create_pipeline(&self, device: &wgpu::Device, key: &PipelineKey) -> wgpu::RenderPipeline {
+        let attributes = key
+            .vertex_layouts
+            .iter()
+            .map(|layout| {
+                layout
+                    .attributes
+                    .iter()
+                    .map(|attr| wgpu::VertexAttributeDescriptor {
+                        offset: attr.offset,
+                        format: attr.format,
+                        shader_location: attr.shader_location,
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+        let vertex_buffers = key
+            .vertex_layouts
+            .iter()
+            .zip(attributes.iter())
+            .map(|(layout, attributes)| wgpu::VertexBufferDescriptor {
+                stride: layout.stride,
+                step_mode: layout.step_mode,
+                attributes,
+            })
+            .collect::<Vec<_>>();
+        let color_states = key
+            .color_targets
+            .iter()
+            .map(ColorTarget::to_desc)
+            .collect::<Vec<_>>();
+
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            layout: &self.layouts[key.layout.0],
+            vertex_stage: wgpu::ProgrammableStageDescriptor {
+                module: &self.shader_modules[&key.vert_path],
+                entry_point: "main",
+            },
+            fragment_stage: key.frag_path.as_ref().map(|frag_path| {
+                wgpu::ProgrammableStageDescriptor {
+                    module: &self.shader_modules[frag_path],
+                    entry_point: "main",
+                }
+            }),
+            rasterization_state: Some(key.rasterization.to_desc()),
+            primitive_topology: key.primitive_topology,
+            color_states: &color_states,
+            depth_stencil_state: key.depth.as_ref().map(DepthState::to_desc),
+            sample_count: key.sample_count,
+            sample_mask: !0,
+            alpha_to_coverage_enabled: false,
+            vertex_state: wgpu::VertexStateDescriptor {
+                index_format: key.index_format,
+                vertex_buffers: &vertex_buffers,
+            },
+        })
+    }
+}
+
+fn get_file_as_byte_vec(filename: &str) -> Vec<u8> {
+    let mut f = File::open(&filename).expect("no file found");
+    let metadata = fs::metadata(&filename).expect("unable to read metadata");
+    let mut buffer = vec![0; metadata.len() as usize];
+    f.read(&mut buffer).expect("buffer overflow");
+
+    buffer
+}
@@ -0,0 +1,398 @@
+//! Generators for simple meshes. They are centered on the origin with y up, faces are
+//! counter clockwise seen from outside and textures aren't mirrored seen from outside,
+//! with v pointing down. Every mesh comes with normals, texture coordinates and
+//! MikkTSpace tangents.
+use crate::mesh::Mesh;
+use cgmath::InnerSpace;
+use std::collections::HashMap;
+use std::f32::consts::PI;
+
+/// A point of the profile a `lathe` sweeps around the y axis.
+#[derive(Copy, Clone, Debug)]
+struct ProfilePoint {
+    /// Distance from the axis
+    radius: f32,
+    y: f32,
+    /// Normal in the profile's plane, (away from the axis, up)
+    normal: [f32; 2],
+    v: f32,
+}
+
+/// A `width` by `depth` plane facing up, split into `subdivisions` squares along each
+/// side.
+pub fn plane(width: f32, depth: f32, subdivisions: u32) -> Mesh {
+    let mut mesh = Mesh::default();
+    add_face(
+        &mut mesh,
+        cgmath::Vector3::new(0.0, 0.0, 0.0),
+        cgmath::Vector3::new(width, 0.0, 0.0),
+        cgmath::Vector3::new(0.0, 0.0, depth),
+        subdivisions.max(1),
+    );
+    finish(mesh)
+}
+
+/// A cube with sides of `size`, every face shows the whole texture.
+pub fn cube(size: f32) -> Mesh {
+    let x = cgmath::Vector3::new(size, 0.0, 0.0);
+    let y = cgmath::Vector3::new(0.0, size, 0.0);
+    let z = cgmath::Vector3::new(0.0, 0.0, size);
+    // Center, right and down of every face, seen from outside
+    let faces = [
+        (z * 0.5, x, -y),
+        (-z * 0.5, -x, -y),
+        (x * 0.5, -z, -y),
+        (-x * 0.5, z, -y),
+        (y * 0.5, x, z),
+        (-y * 0.5, x, -z),
+    ];
+    let mut mesh = Mesh::default();
+    for &(center, right, down) in &faces {
+        add_face(&mut mesh, center, right, down, 1);
+    }
+    finish(mesh)
+}
+
+/// A sphere made of `sectors` slices around the y axis and `stacks` rings from pole to
+/// pole, u follows the longitude and v the latitude.
+pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> Mesh {
+    let stacks = stacks.max(2);
+    let profile = (0..=stacks)
+        .map(|stack| {
+            let v = stack as f32 / stacks as f32;
+            let (sin, cos) = (v * PI).sin_cos();
+            // Exactly on the axis at the poles, so their collapsed triangles are dropped
+            let sin = if stack == 0 || stack == stacks { 0.0 } else { sin };
+            ProfilePoint {
+                radius: radius * sin,
+                y: radius * cos,
+                normal: [sin, cos],
+                v,
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut mesh = Mesh::default();
+    lathe(&mut mesh, &profile, sectors.max(3));
+    finish(mesh)
+}
+
+/// A sphere made of evenly sized triangles, an icosahedron whose triangles are split in
+/// four `subdivisions` times. Textured like `uv_sphere`.
+pub fn icosphere(radius: f32, subdivisions: u32) -> Mesh {
+    let t = (1.0 + 5f32.sqrt()) / 2.0;
+    let mut positions = [
+        [-1.0, t, 0.0],
+        [1.0, t, 0.0],
+        [-1.0, -t, 0.0],
+        [1.0, -t, 0.0],
+        [0.0, -1.0, t],
+        [0.0, 1.0, t],
+        [0.0, -1.0, -t],
+        [0.0, 1.0, -t],
+        [t, 0.0, -1.0],
+        [t, 0.0, 1.0],
+        [-t, 0.0, -1.0],
+        [-t, 0.0, 1.0],
+    ]
+    .iter()
+    .map(|&position| cgmath::Vector3::from(position).normalize())
+    .collect::<Vec<_>>();
+    let mut triangles: Vec<[usize; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        // Neighbouring triangles share the vertex in the middle of their edge
+        let mut midpoints: HashMap<(usize, usize), usize> = HashMap::new();
+        let mut midpoint = |a: usize, b: usize| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                positions.push((positions[a] + positions[b]).normalize());
+                positions.len() - 1
+            })
+        };
+        triangles = triangles
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let (ab, bc, ca) = (midpoint(a, b), midpoint(b, c), midpoint(c, a));
+                vec![[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    // Every corner gets its own vertex first, so triangles across the texture's seam and
+    // around the poles can have texture coordinates of their own. Welding shares the rest.
+    let mut mesh = Mesh::default();
+    for triangle in &triangles {
+        let corners = [
+            positions[triangle[0]],
+            positions[triangle[1]],
+            positions[triangle[2]],
+        ];
+        let mut tex_coords = [[0.0; 2]; 3];
+        for (tex_coords, corner) in tex_coords.iter_mut().zip(&corners) {
+            let u = (-corner.z).atan2(corner.x) / (2.0 * PI);
+            *tex_coords = [u.rem_euclid(1.0), corner.y.max(-1.0).min(1.0).acos() / PI];
+        }
+        // Corners on both sides of the seam, move the ones at the start past the end
+        let (min_u, max_u) = tex_coords
+            .iter()
+            .fold((1.0f32, 0.0f32), |(min, max), uv| (min.min(uv[0]), max.max(uv[0])));
+        if max_u - min_u > 0.5 {
+            for uv in &mut tex_coords {
+                if uv[0] < 0.5 {
+                    uv[0] += 1.0;
+                }
+            }
+        }
+        // The longitude of a pole is anything, take the one of the triangle's other corners
+        for pole in 0..3 {
+            if corners[pole].y.abs() > 0.9999 {
+                let others = [(pole + 1) % 3, (pole + 2) % 3];
+                tex_coords[pole][0] = (tex_coords[others[0]][0] + tex_coords[others[1]][0]) / 2.0;
+            }
+        }
+
+        let first = mesh.positions.len() as u32;
+        for (corner, uv) in corners.iter().zip(&tex_coords) {
+            mesh.positions.push((*corner * radius).into());
+            mesh.normals.push((*corner).into());
+            mesh.tex_coords.push(*uv);
+        }
+        mesh.indices.extend_from_slice(&[first, first + 1, first + 2]);
+    }
+    mesh.weld(0.0);
+    finish(mesh)
+}
+
+/// A closed cylinder around the y axis, with `segments` sides.
+pub fn cylinder(radius: f32, height: f32, segments: u32) -> Mesh {
+    let segments = segments.max(3);
+    let top = height / 2.0;
+    let profile = [
+        ProfilePoint {
+            radius,
+            y: top,
+            normal: [1.0, 0.0],
+            v: 0.0,
+        },
+        ProfilePoint {
+            radius,
+            y: -top,
+            normal: [1.0, 0.0],
+            v: 1.0,
+        },
+    ];
+    let mut mesh = Mesh::default();
+    lathe(&mut mesh, &profile, segments);
+    add_disc(&mut mesh, radius, top, segments, true);
+    add_disc(&mut mesh, radius, -top, segments, false);
+    finish(mesh)
+}
+
+/// A cone around the y axis with the tip at the top and a closed base.
+pub fn cone(radius: f32, height: f32, segments: u32) -> Mesh {
+    let segments = segments.max(3);
+    let top = height / 2.0;
+    let slope = cgmath::Vector2::new(height, radius).normalize();
+    let normal = [slope.x, slope.y];
+    let profile = [
+        ProfilePoint {
+            radius: 0.0,
+            y: top,
+            normal,
+            v: 0.0,
+        },
+        ProfilePoint {
+            radius,
+            y: -top,
+            normal,
+            v: 1.0,
+        },
+    ];
+    let mut mesh = Mesh::default();
+    lathe(&mut mesh, &profile, segments);
+    add_disc(&mut mesh, radius, -top, segments, false);
+    finish(mesh)
+}
+
+/// A ring around the y axis: a tube of `minor_radius` around a circle of `major_radius`.
+pub fn torus(
+    major_radius: f32,
+    minor_radius: f32,
+    major_segments: u32,
+    minor_segments: u32,
+) -> Mesh {
+    let minor_segments = minor_segments.max(3);
+    // From the top of the tube over its outside, so v points down seen from outside
+    let profile = (0..=minor_segments)
+        .map(|segment| {
+            let v = segment as f32 / minor_segments as f32;
+            let (sin, cos) = (PI / 2.0 - v * 2.0 * PI).sin_cos();
+            ProfilePoint {
+                radius: major_radius + minor_radius * cos,
+                y: minor_radius * sin,
+                normal: [cos, sin],
+                v,
+            }
+        })
+        .collect::<Vec<_>>();
+    let mut mesh = Mesh::default();
+    lathe(&mut mesh, &profile, major_segments.max(3));
+    finish(mesh)
+}
+
+/// A cylinder with `height` between two hemispheres, `rings` rings each. The whole
+/// capsule is `height + 2 * radius` tall, v is spread along the profile so the texture
+/// isn't stretched on the hemispheres.
+pub fn capsule(radius: f32, height: f32, segments: u32, rings: u32) -> Mesh {
+    let rings = rings.max(1);
+    let top = height / 2.0;
+    let length = PI * radius + height;
+    let mut profile = Vec::with_capacity(2 * rings as usize + 2);
+    for ring in 0..=rings {
+        let angle = ring as f32 / rings as f32 * PI / 2.0;
+        let (sin, cos) = angle.sin_cos();
+        profile.push(ProfilePoint {
+            radius: if ring == 0 { 0.0 } else { radius * sin },
+            y: top + radius * cos,
+            normal: [sin, cos],
+            v: angle * radius / length,
+        });
+    }
+    for ring in 0..=rings {
+        let angle = PI / 2.0 + ring as f32 / rings as f32 * PI / 2.0;
+        let (sin, cos) = angle.sin_cos();
+        profile.push(ProfilePoint {
+            radius: if ring == rings { 0.0 } else { radius * sin },
+            y: -top + radius * cos,
+            normal: [sin, cos],
+            v: (angle * radius + height) / length,
+        });
+    }
+    let mut mesh = Mesh::default();
+    lathe(&mut mesh, &profile, segments.max(3));
+    finish(mesh)
+}
+
+/// Adds a square grid of `subdivisions` by `subdivisions` quads. `right` and `down` span
+/// the face seen from its front, the normal is `down × right`.
+fn add_face(
+    mesh: &mut Mesh,
+    center: cgmath::Vector3<f32>,
+    right: cgmath::Vector3<f32>,
+    down: cgmath::Vector3<f32>,
+    subdivisions: u32,
+) {
+    let normal: [f32; 3] = down.cross(right).normalize().into();
+    let first = mesh.positions.len() as u32;
+    for row in 0..=subdivisions {
+        for column in 0..=subdivisions {
+            let u = column as f32 / subdivisions as f32;
+            let v = row as f32 / subdivisions as f32;
+            let position = center + right * (u - 0.5) + down * (v - 0.5);
+            mesh.positions.push(position.into());
+            mesh.normals.push(normal);
+            mesh.tex_coords.push([u, v]);
+        }
+    }
+    add_grid_indices(mesh, first, subdivisions, subdivisions);
+}
+
+/// Sweeps `profile` around the y axis in `segments` steps, u goes around the axis. The
+/// profile has to go down seen from outside for the faces to face outwards.
+fn lathe(mesh: &mut Mesh, profile: &[ProfilePoint], segments: u32) {
+    let first = mesh.positions.len() as u32;
+    for point in profile {
+        for segment in 0..=segments {
+            let u = segment as f32 / segments as f32;
+            let (sin, cos) = (u * 2.0 * PI).sin_cos();
+            // The angle goes from +x to -z, which is to the right seen from outside
+            mesh.positions.push([point.radius * cos, point.y, -point.radius * sin]);
+            mesh.normals.push([point.normal[0] * cos, point.normal[1], -point.normal[0] * sin]);
+            mesh.tex_coords.push([u, point.v]);
+        }
+    }
+    add_grid_indices(mesh, first, segments, profile.len() as u32 - 1);
+}
+
+/// Indices of a grid of `columns + 1` by `rows + 1` vertices starting at `first`, row by
+/// row, with u going right and v going down seen from the front.
+fn add_grid_indices(mesh: &mut Mesh, first: u32, columns: u32, rows: u32) {
+    let stride = columns + 1;
+    for row in 0..rows {
+        for column in 0..columns {
+            let a = first + row * stride + column;
+            let (b, c, d) = (a + 1, a + stride, a + stride + 1);
+            mesh.indices.extend_from_slice(&[a, c, d, a, d, b]);
+        }
+    }
+}
+
+/// A disc closing a `lathe` at height `y`, facing up or down. Textured as if the texture
+/// was projected onto it from the side it's facing.
+fn add_disc(mesh: &mut Mesh, radius: f32, y: f32, segments: u32, up: bool) {
+    let normal = if up { [0.0, 1.0, 0.0] } else { [0.0, -1.0, 0.0] };
+    let v_sign = if up { 1.0 } else { -1.0 };
+    let center = mesh.positions.len() as u32;
+    mesh.positions.push([0.0, y, 0.0]);
+    mesh.normals.push(normal);
+    mesh.tex_coords.push([0.5, 0.5]);
+    for segment in 0..segments {
+        let (sin, cos) = (segment as f32 / segments as f32 * 2.0 * PI).sin_cos();
+        mesh.positions.push([radius * cos, y, -radius * sin]);
+        mesh.normals.push(normal);
+        mesh.tex_coords.push([0.5 + cos * 0.5, 0.5 - v_sign * sin * 0.5]);
+    }
+    for segment in 0..segments {
+        let current = center + 1 + segment;
+        let next = center + 1 + (segment + 1) % segments;
+        if up {
+            mesh.indices.extend_from_slice(&[center, current, next]);
+        } else {
+            mesh.indices.extend_from_slice(&[center, next, current]);
+        }
+    }
+}
+
+/// Drops the triangles that collapsed on an axis, then adds tangents and puts the
+/// triangles in vertex cache order. Hand made meshes with normals go through it too.
+pub fn finish(mut mesh: Mesh) -> Mesh {
+    let positions = &mesh.positions;
+    let mut indices = Vec::with_capacity(mesh.indices.len());
+    for triangle in mesh.indices.chunks(3) {
+        let [a, b, c] = [
+            positions[triangle[0] as usize],
+            positions[triangle[1] as usize],
+            positions[triangle[2] as usize],
+        ];
+        if a != b && b != c && a != c {
+            indices.extend_from_slice(triangle);
+        }
+    }
+    mesh.indices = indices;
+
+    if !mesh.compute_tangents() {
+        panic!("MikkTSpace couldn't generate tangents for a mesh");
+    }
+    mesh.optimize_vertex_cache();
+    mesh
+}
@@ -0,0 +1,15 @@
+// Shared by the fragment shaders of instances drawn with lod cross-fading
+
+layout(location = 1) flat in float v_lod_fade;
+
+// Screen door cross-fade between two lod levels. A positive fade keeps the pixels whose
+// threshold in a 4x4 Bayer pattern is below it, a negative one the others, so the two
+// levels of a fading instance cover complementary pixels. 1 keeps every pixel.
+bool lod_faded_out() {
+    const float bayer[16] = float[16](
+        0.0, 8.0, 2.0, 10.0, 12.0, 4.0, 14.0, 6.0, 3.0, 11.0, 1.0, 9.0, 15.0, 7.0, 13.0, 5.0
+    );
+    ivec2 pixel = ivec2(gl_FragCoord.xy) & 3;
+    float threshold = (bayer[pixel.y * 4 + pixel.x] + 0.5) / 16.0;
+    return v_lod_fade >= 0.0 ? threshold >= v_lod_fade : threshold < -v_lod_fade;
+}
@@ -0,0 +1,15 @@
+// Shared by the fragment shaders of materials, bind group 0 is the material's
+
+layout(location = 0) in vec2 v_tex_coords;
+
+layout(set = 0, binding = 0) uniform texture2D t_diffuse;
+layout(set = 0, binding = 1) uniform sampler s_diffuse;
+// See MaterialParams
+layout(set = 0, binding = 2) uniform MaterialParams {
+    vec4 u_tint;
+};
+
+// The diffuse texture at the fragment, tinted
+vec4 material_color() {
+    return texture(sampler2D(t_diffuse, s_diffuse), v_tex_coords) * u_tint;
+}
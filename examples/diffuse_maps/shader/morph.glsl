@@ -0,0 +1,32 @@
+// Shared by the vertex shaders of meshes with morph targets, see MorphTargets. The deltas
+// go target by target, the weights instance by instance.
+
+// Row of the instance's weights, see Instance::morph_instance
+layout(location=12) in uint a_morph_instance;
+
+struct MorphDelta {
+    vec4 position;
+    vec4 normal;
+};
+
+layout(set=2, binding=0) readonly buffer MorphDeltas {
+    MorphDelta u_morph_deltas[];
+};
+
+layout(set=2, binding=1) readonly buffer MorphWeights {
+    uint u_morph_target_count;
+    uint u_morph_vertex_count;
+    float u_morph_weights[];
+};
+
+// Adds the instance's weighted deltas of the current vertex
+void morph(inout vec3 position, inout vec3 normal) {
+    for (uint target = 0; target < u_morph_target_count; target++) {
+        float weight = u_morph_weights[a_morph_instance * u_morph_target_count + target];
+        if (weight != 0.0) {
+            MorphDelta delta = u_morph_deltas[target * u_morph_vertex_count + gl_VertexIndex];
+            position += weight * delta.position.xyz;
+            normal += weight * delta.normal.xyz;
+        }
+    }
+}
@@ -4,15 +4,43 @@ layout(location=0) in vec3 a_position;
 layout(location=1) in vec2 a_tex_coords;
 
 layout(location=2) in mat4 a_model;
+// xyz is the spin axis, w the angular speed in radians per second
+layout(location=6) in vec4 a_spin;
+// See lod_fade.glsl
+layout(location=7) in float a_lod_fade;
 
 layout(location=0) out vec2 v_tex_coords;
+layout(location=1) flat out float v_lod_fade;
 
 layout(set=1, binding=0)
 uniform Uniforms {
     mat4 u_view_proj;
+    float u_time;
 };
 
+#include "morph.glsl"
+
+// Rodrigues' rotation formula as a matrix, axis has to be normalized
+mat4 axis_angle(vec3 axis, float angle) {
+    float s = sin(angle);
+    float c = cos(angle);
+    float t = 1.0 - c;
+    return mat4(
+        t * axis.x * axis.x + c,          t * axis.x * axis.y + s * axis.z, t * axis.x * axis.z - s * axis.y, 0.0,
+        t * axis.x * axis.y - s * axis.z, t * axis.y * axis.y + c,          t * axis.y * axis.z + s * axis.x, 0.0,
+        t * axis.x * axis.z + s * axis.y, t * axis.y * axis.z - s * axis.x, t * axis.z * axis.z + c,          0.0,
+        0.0,                              0.0,                              0.0,                              1.0
+    );
+}
+
 void main() {
-    gl_Position = u_view_proj * a_model * vec4(a_position, 1.0);
+    vec3 position = a_position;
+    // Unlit, the normal deltas go nowhere
+    vec3 normal = vec3(0.0);
+    morph(position, normal);
+
+    mat4 spin = axis_angle(a_spin.xyz, a_spin.w * u_time);
+    gl_Position = u_view_proj * a_model * spin * vec4(position, 1.0);
     v_tex_coords = a_tex_coords;
+    v_lod_fade = a_lod_fade;
 }
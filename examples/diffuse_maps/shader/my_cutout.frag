@@ -0,0 +1,19 @@
+#version 450
+
+layout(location = 0) out vec4 f_color;
+
+#include "material.glsl"
+#include "lod_fade.glsl"
+
+const float ALPHA_CUTOFF = 0.5;
+
+void main() {
+    if (lod_faded_out()) {
+        discard;
+    }
+    vec4 color = material_color();
+    if (color.a < ALPHA_CUTOFF) {
+        discard;
+    }
+    f_color = vec4(color.rgb, 1.0);
+}
@@ -0,0 +1,27 @@
+#version 450
+
+layout(location = 0) out vec4 f_accum;
+layout(location = 1) out float f_revealage;
+
+#include "material.glsl"
+
+layout(set = 1, binding = 0) uniform Uniforms {
+    mat4 u_view_proj;
+    float u_time;
+    float u_reverse_z;
+};
+
+#include "lod_fade.glsl"
+
+void main() {
+    if (lod_faded_out()) {
+        discard;
+    }
+    vec4 color = material_color();
+    // Weight function from McGuire and Bavoil, "Weighted Blended Order-Independent Transparency".
+    // Closer and more opaque fragments dominate the average, near is depth 1 with reverse-Z.
+    float closeness = u_reverse_z > 0.5 ? gl_FragCoord.z : 1.0 - gl_FragCoord.z;
+    float weight = color.a * clamp(3e3 * pow(closeness, 3.0), 1e-2, 3e3);
+    f_accum = vec4(color.rgb * color.a, color.a) * weight;
+    f_revealage = color.a;
+}
@@ -0,0 +1,18 @@
+#version 450
+
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform texture2D t_accum;
+layout(set = 0, binding = 1) uniform texture2D t_revealage;
+layout(set = 0, binding = 2) uniform sampler s_target;
+
+void main() {
+    ivec2 coords = ivec2(gl_FragCoord.xy);
+    vec4 accum = texelFetch(sampler2D(t_accum, s_target), coords, 0);
+    float revealage = texelFetch(sampler2D(t_revealage, s_target), coords, 0).r;
+    if (revealage >= 1.0) {
+        discard;
+    }
+    vec3 average_color = accum.rgb / clamp(accum.a, 1e-4, 5e4);
+    f_color = vec4(average_color, 1.0 - revealage);
+}
@@ -0,0 +1,32 @@
+#version 450
+
+// See ParticleInstance, w of the position is the size
+layout(location=0) in vec4 a_position;
+layout(location=1) in vec4 a_color;
+
+layout(location=0) out vec2 v_corner;
+layout(location=1) out vec4 v_color;
+
+layout(set=0, binding=0)
+uniform Uniforms {
+    mat4 u_view_proj;
+    float u_time;
+    vec4 u_camera_right;
+    vec4 u_camera_up;
+};
+
+// Two triangles of a quad facing the camera
+const vec2 CORNERS[6] = vec2[6](
+    vec2(-1.0, -1.0), vec2(1.0, -1.0), vec2(1.0, 1.0),
+    vec2(-1.0, -1.0), vec2(1.0, 1.0), vec2(-1.0, 1.0)
+);
+
+void main() {
+    vec2 corner = CORNERS[gl_VertexIndex];
+    float size = a_position.w;
+    vec3 position = a_position.xyz
+        + (u_camera_right.xyz * corner.x + u_camera_up.xyz * corner.y) * size;
+    gl_Position = u_view_proj * vec4(position, 1.0);
+    v_corner = corner;
+    v_color = a_color;
+}
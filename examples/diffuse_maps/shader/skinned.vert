@@ -0,0 +1,66 @@
+#version 450
+
+layout(location=0) in vec3 a_position;
+layout(location=1) in vec2 a_tex_coords;
+
+layout(location=2) in mat4 a_model;
+// xyz is the spin axis, w the angular speed in radians per second
+layout(location=6) in vec4 a_spin;
+// See lod_fade.glsl
+layout(location=7) in float a_lod_fade;
+
+layout(location=8) in vec3 a_normal;
+
+layout(location=10) in uvec4 a_joints;
+layout(location=11) in vec4 a_weights;
+
+layout(location=0) out vec2 v_tex_coords;
+layout(location=1) flat out float v_lod_fade;
+// World space, for fragment shaders that light the model
+layout(location=2) out vec3 v_normal;
+
+layout(set=1, binding=0)
+uniform Uniforms {
+    mat4 u_view_proj;
+    float u_time;
+};
+
+// Skinning matrices of the current pose, see Skeleton::joint_matrices
+layout(set=3, binding=0) readonly buffer Joints {
+    mat4 u_joints[];
+};
+
+#include "morph.glsl"
+
+// Rodrigues' rotation formula as a matrix, axis has to be normalized
+mat4 axis_angle(vec3 axis, float angle) {
+    float s = sin(angle);
+    float c = cos(angle);
+    float t = 1.0 - c;
+    return mat4(
+        t * axis.x * axis.x + c,          t * axis.x * axis.y + s * axis.z, t * axis.x * axis.z - s * axis.y, 0.0,
+        t * axis.x * axis.y - s * axis.z, t * axis.y * axis.y + c,          t * axis.y * axis.z + s * axis.x, 0.0,
+        t * axis.x * axis.z + s * axis.y, t * axis.y * axis.z - s * axis.x, t * axis.z * axis.z + c,          0.0,
+        0.0,                              0.0,                              0.0,                              1.0
+    );
+}
+
+void main() {
+    vec3 position = a_position;
+    vec3 normal = a_normal;
+    morph(position, normal);
+
+    mat4 skin =
+        a_weights.x * u_joints[a_joints.x] +
+        a_weights.y * u_joints[a_joints.y] +
+        a_weights.z * u_joints[a_joints.z] +
+        a_weights.w * u_joints[a_joints.w];
+    mat4 spin = axis_angle(a_spin.xyz, a_spin.w * u_time);
+    mat4 model = a_model * spin * skin;
+    gl_Position = u_view_proj * model * vec4(position, 1.0);
+    v_tex_coords = a_tex_coords;
+    v_lod_fade = a_lod_fade;
+    // The inverse transpose keeps normals perpendicular to the surface under non-uniform
+    // scale, which mat3(model) alone would skew
+    v_normal = normalize(transpose(inverse(mat3(model))) * normal);
+}
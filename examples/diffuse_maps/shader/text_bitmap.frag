@@ -0,0 +1,14 @@
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=1) in vec4 v_color;
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0) uniform texture2D t_atlas;
+layout(set=0, binding=1) uniform sampler s_atlas;
+
+// The atlas holds the glyph's coverage
+void main() {
+    float coverage = texture(sampler2D(t_atlas, s_atlas), v_uv).r;
+    f_color = vec4(v_color.rgb, v_color.a * coverage);
+}
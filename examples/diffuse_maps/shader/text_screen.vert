@@ -0,0 +1,25 @@
+#version 450
+
+// See TextVertex, the anchor is in pixels from the top left corner
+layout(location=0) in vec3 a_anchor;
+layout(location=1) in vec2 a_offset;
+layout(location=2) in vec2 a_uv;
+layout(location=3) in vec4 a_color;
+
+layout(location=0) out vec2 v_uv;
+layout(location=1) out vec4 v_color;
+
+layout(set=0, binding=2)
+uniform TextUniforms {
+    vec2 u_screen_size;
+};
+
+void main() {
+    vec2 pixel = a_anchor.xy + a_offset;
+    vec2 ndc = pixel / u_screen_size * 2.0 - 1.0;
+    // Pixels count down from the top, clip space up from the bottom
+    ndc.y = -ndc.y;
+    gl_Position = vec4(ndc, 0.0, 1.0);
+    v_uv = a_uv;
+    v_color = a_color;
+}
@@ -0,0 +1,17 @@
+#version 450
+
+layout(location=0) in vec2 v_uv;
+layout(location=1) in vec4 v_color;
+layout(location=0) out vec4 f_color;
+
+layout(set=0, binding=0) uniform texture2D t_atlas;
+layout(set=0, binding=1) uniform sampler s_atlas;
+
+// The atlas holds the distance to the outline, 0.5 right on it. Antialiased over about a
+// pixel on screen, however large the glyph is drawn.
+void main() {
+    float distance = texture(sampler2D(t_atlas, s_atlas), v_uv).r;
+    float width = max(fwidth(distance) * 0.5, 1e-4);
+    float alpha = smoothstep(0.5 - width, 0.5 + width, distance);
+    f_color = vec4(v_color.rgb, v_color.a * alpha);
+}
@@ -0,0 +1,26 @@
+#version 450
+
+// See TextVertex
+layout(location=0) in vec3 a_anchor;
+layout(location=1) in vec2 a_offset;
+layout(location=2) in vec2 a_uv;
+layout(location=3) in vec4 a_color;
+
+layout(location=0) out vec2 v_uv;
+layout(location=1) out vec4 v_color;
+
+layout(set=1, binding=0)
+uniform Uniforms {
+    mat4 u_view_proj;
+    float u_time;
+    vec4 u_camera_right;
+    vec4 u_camera_up;
+};
+
+// Labels face the camera, the offset is laid out with y pointing down
+void main() {
+    vec3 position = a_anchor + u_camera_right.xyz * a_offset.x - u_camera_up.xyz * a_offset.y;
+    gl_Position = u_view_proj * vec4(position, 1.0);
+    v_uv = a_uv;
+    v_color = a_color;
+}
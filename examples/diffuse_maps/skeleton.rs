@@ -0,0 +1,136 @@
+use cgmath::{InnerSpace, SquareMatrix};
+
+/// Translation, rotation and scale of a joint relative to its parent. Kept apart instead
+/// of as a matrix so poses can be interpolated and blended.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub translation: cgmath::Vector3<f32>,
+    pub rotation: cgmath::Quaternion<f32>,
+    pub scale: cgmath::Vector3<f32>,
+}
+
+impl Transform {
+    pub fn to_matrix(&self) -> cgmath::Matrix4<f32> {
+        cgmath::Matrix4::from_translation(self.translation)
+            * cgmath::Matrix4::from(self.rotation)
+            * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    /// `t` of the way to `other`, rotations take the shortest path.
+    pub fn lerp(&self, other: &Transform, t: f32) -> Transform {
+        Transform {
+            translation: self.translation + (other.translation - self.translation) * t,
+            rotation: nlerp(self.rotation, other.rotation, t),
+            scale: self.scale + (other.scale - self.scale) * t,
+        }
+    }
+}
+
+/// Normalized linear interpolation along the shorter arc. Not constant speed like slerp,
+/// but close enough between keyframes and poses, and it never divides by a tiny sine.
+pub fn nlerp(
+    a: cgmath::Quaternion<f32>,
+    b: cgmath::Quaternion<f32>,
+    t: f32,
+) -> cgmath::Quaternion<f32> {
+    let b = if a.dot(b) < 0.0 { -b } else { b };
+    (a * (1.0 - t) + b * t).normalize()
+}
+
+#[derive(Clone, Debug)]
+pub struct Joint {
+    pub name: String,
+    pub parent: Option<usize>,
+    /// Local transform when no animation moves the joint
+    pub rest: Transform,
+    /// Takes the mesh from model space into the joint's space at bind time
+    pub inverse_bind: cgmath::Matrix4<f32>,
+}
+
+/// The joints a skinned mesh is bound to. Joint indices are the ones the mesh's vertices
+/// use, parents don't have to come before their children.
+#[derive(Clone, Debug)]
+pub struct Skeleton {
+    pub joints: Vec<Joint>,
+    /// Transform of the skeleton's root in model space, from nodes above the joints
+    pub root: cgmath::Matrix4<f32>,
+    /// Joints with parents before children, the order global transforms are built in
+    order: Vec<usize>,
+}
+
+impl Skeleton {
+    pub fn new(joints: Vec<Joint>, root: cgmath::Matrix4<f32>) -> Self {
+        let mut order = Vec::with_capacity(joints.len());
+        let mut added = vec![false; joints.len()];
+        while order.len() < joints.len() {
+            let before = order.len();
+            for (index, joint) in joints.iter().enumerate() {
+                let parent_added = joint.parent.map_or(true, |parent| added[parent]);
+                if !added[index] && parent_added {
+                    added[index] = true;
+                    order.push(index);
+                }
+            }
+            assert!(order.len() > before, "joint hierarchy has a cycle");
+        }
+        Self {
+            joints,
+            root,
+            order,
+        }
+    }
+
+    /// Pose with every joint at rest and the morph targets at `morph_weights`.
+    pub fn rest_pose(&self, morph_weights: &[f32]) -> Pose {
+        Pose {
+            joints: self.joints.iter().map(|joint| joint.rest).collect(),
+            morph_weights: morph_weights.to_vec(),
+        }
+    }
+
+    /// Puts every joint of `pose` back into its rest transform and the morph targets to
+    /// `morph_weights`.
+    pub fn reset_pose(&self, pose: &mut Pose, morph_weights: &[f32]) {
+        for (transform, joint) in pose.joints.iter_mut().zip(&self.joints) {
+            *transform = joint.rest;
+        }
+        pose.morph_weights.copy_from_slice(morph_weights);
+    }
+
+    /// Skinning matrices for `pose`, what the vertex shader multiplies the vertices with.
+    /// Each takes a vertex from bind pose model space to where its joint puts it.
+    pub fn joint_matrices(&self, pose: &Pose, matrices: &mut Vec<cgmath::Matrix4<f32>>) {
+        let mut globals = vec![cgmath::Matrix4::identity(); self.joints.len()];
+        for &index in &self.order {
+            let parent = self.joints[index].parent.map_or(self.root, |parent| globals[parent]);
+            globals[index] = parent * pose.joints[index].to_matrix();
+        }
+        matrices.clear();
+        matrices.extend(
+            globals
+                .iter()
+                .zip(&self.joints)
+                .map(|(global, joint)| *global * joint.inverse_bind),
+        );
+    }
+}
+
+/// Local transforms of every joint of a skeleton, and the weights of the morph targets of
+/// the mesh bound to it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Pose {
+    pub joints: Vec<Transform>,
+    pub morph_weights: Vec<f32>,
+}
+
+impl Pose {
+    /// Moves this pose `weight` of the way to `other`, for cross-fading between clips.
+    pub fn blend(&mut self, other: &Pose, weight: f32) {
+        for (joint, other) in self.joints.iter_mut().zip(&other.joints) {
+            *joint = joint.lerp(other, weight);
+        }
+        for (morph_weight, other) in self.morph_weights.iter_mut().zip(&other.morph_weights) {
+            *morph_weight += (other - *morph_weight) * weight;
+        }
+    }
+}
@@ -0,0 +1,307 @@
+use crate::animation_clip::{AnimationClip, Playback, Playhead};
+use crate::culling::Aabb;
+use crate::gltf_model::GltfModel;
+use crate::material::Material;
+use crate::mesh::Indices;
+use crate::morph::MorphTargets;
+use crate::pipeline_cache::PipelineCache;
+use crate::skeleton::{Pose, Skeleton};
+use crate::upload::StagingBelt;
+use iced_wgpu::wgpu;
+
+const MATRIX_SIZE: wgpu::BufferAddress =
+    std::mem::size_of::<[[f32; 4]; 4]>() as wgpu::BufferAddress;
+
+/// Vertex of a skinned mesh. The first attributes match the static meshes' vertices, so
+/// both use the same fragment shaders.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct SkinnedVertex {
+    pub position: [f32; 3],
+    pub tex_coords: [f32; 2],
+    pub normal: [f32; 3],
+    pub tangent: [f32; 4],
+    /// Up to four joints moving the vertex
+    pub joints: [u16; 4],
+    /// How much each of `joints` moves the vertex, adds up to one
+    pub weights: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for SkinnedVertex {}
+unsafe impl bytemuck::Zeroable for SkinnedVertex {}
+
+impl SkinnedVertex {
+    pub fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        use std::mem;
+        let offset = |floats: usize| (mem::size_of::<f32>() * floats) as wgpu::BufferAddress;
+        wgpu::VertexBufferDescriptor {
+            stride: mem::size_of::<SkinnedVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: offset(3),
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float2,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: offset(5),
+                    shader_location: 8,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: offset(8),
+                    shader_location: 9,
+                    format: wgpu::VertexFormat::Float4,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: offset(12),
+                    shader_location: 10,
+                    format: wgpu::VertexFormat::Ushort4,
+                },
+                // The four u16 joints take as much room as two floats
+                wgpu::VertexAttributeDescriptor {
+                    offset: offset(14),
+                    shader_location: 11,
+                    format: wgpu::VertexFormat::Float4,
+                },
+            ],
+        }
+    }
+}
+
+/// Plays the clips of a skeleton, looping the current one. Switching clips cross-fades
+/// from the old one, which keeps playing until the fade is over.
+pub struct Animator {
+    clip: usize,
+    playhead: Playhead,
+    /// Clip fading out and where it is
+    fading: Option<(usize, Playhead)>,
+    /// How far the cross-fade is, in `0..1`
+    fade: f32,
+    pub fade_duration: f32,
+    /// Morph target weights where no clip animates them
+    default_weights: Vec<f32>,
+    pose: Pose,
+    fading_pose: Pose,
+}
+
+impl Animator {
+    pub fn new(skeleton: &Skeleton, default_weights: Vec<f32>) -> Self {
+        Self {
+            clip: 0,
+            playhead: Playhead::new(Playback::Loop),
+            fading: None,
+            fade: 0.0,
+            fade_duration: 0.3,
+            pose: skeleton.rest_pose(&default_weights),
+            fading_pose: skeleton.rest_pose(&default_weights),
+            default_weights,
+        }
+    }
+
+    pub fn clip(&self) -> usize {
+        self.clip
+    }
+
+    pub fn play(&mut self, clip: usize) {
+        if clip == self.clip {
+            return;
+        }
+        self.fading = Some((self.clip, self.playhead));
+        self.fade = 0.0;
+        self.clip = clip;
+        self.playhead.restart();
+    }
+
+    /// Advances the clips by `dt` seconds and returns the pose they result in.
+    pub fn update(&mut self, dt: f32, skeleton: &Skeleton, clips: &[AnimationClip]) -> &Pose {
+        // Clips only set the joints they animate, the rest stays in the rest pose
+        skeleton.reset_pose(&mut self.pose, &self.default_weights);
+        if let Some(clip) = clips.get(self.clip) {
+            self.playhead.advance(dt, clip.duration);
+            clip.sample(self.playhead.time(), &mut self.pose);
+        }
+
+        if let Some((fading, mut playhead)) = self.fading {
+            self.fade += dt / self.fade_duration;
+            if self.fade >= 1.0 {
+                self.fading = None;
+            } else if let Some(clip) = clips.get(fading) {
+                playhead.advance(dt, clip.duration);
+                self.fading = Some((fading, playhead));
+                skeleton.reset_pose(&mut self.fading_pose, &self.default_weights);
+                clip.sample(playhead.time(), &mut self.fading_pose);
+                // Start from the old clip's pose and move towards the new one
+                self.fading_pose.blend(&self.pose, self.fade);
+                std::mem::swap(&mut self.pose, &mut self.fading_pose);
+            }
+        }
+        &self.pose
+    }
+}
+
+/// An animated model skinned on the gpu. The joint matrices and morph target weights are
+/// uploaded every frame into storage buffers the vertex shader reads, the vertices never
+/// change.
+pub struct SkinnedModel {
+    pub skeleton: Skeleton,
+    pub clips: Vec<AnimationClip>,
+    pub animator: Animator,
+    /// Pipeline and textures, the pipeline's key has to use `SkinnedVertex`. `new` sets its
+    /// index format
+    pub material: Material,
+    /// World space box around the model, for culling
+    pub bounds: Aabb,
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    index_count: u32,
+    /// A single instance placing the model, laid out like the static meshes' instances
+    instance_buffer: wgpu::Buffer,
+    joint_buffer: wgpu::Buffer,
+    joint_bind_group: wgpu::BindGroup,
+    joint_matrices: Vec<cgmath::Matrix4<f32>>,
+    morph_targets: MorphTargets,
+}
+
+impl SkinnedModel {
+    /// Layout of the joint matrices, bind group 3 of the skinned pipeline
+    pub fn joint_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+        device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStage::VERTEX,
+                ty: wgpu::BindingType::StorageBuffer {
+                    dynamic: false,
+                    readonly: true,
+                },
+            }],
+            label: Some("joint_bind_group_layout"),
+        })
+    }
+
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        model: GltfModel,
+        joint_bind_group_layout: &wgpu::BindGroupLayout,
+        morph_bind_group_layout: &wgpu::BindGroupLayout,
+        mut material: Material,
+        instance: &[u8],
+        transform: &cgmath::Matrix4<f32>,
+    ) -> Self {
+        // Animations move the model away from its bind pose, half its size more around it
+        // covers most of them
+        let bind_pose =
+            Aabb::from_points(model.vertices.iter().map(|vertex| vertex.position.into()));
+        let margin = (bind_pose.max - bind_pose.min) * 0.5;
+        let bounds = Aabb {
+            min: bind_pose.min - margin,
+            max: bind_pose.max + margin,
+        }
+        .transform(transform);
+
+        let vertex_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&model.vertices),
+            wgpu::BufferUsage::VERTEX,
+        );
+        let indices = Indices::new(&model.indices, model.vertices.len());
+        let index_buffer =
+            device.create_buffer_with_data(indices.as_bytes(), wgpu::BufferUsage::INDEX);
+        material.set_index_format(device, pipeline_cache, indices.format());
+        let instance_buffer =
+            device.create_buffer_with_data(instance, wgpu::BufferUsage::VERTEX);
+
+        let joint_size = MATRIX_SIZE * model.skeleton.joints.len().max(1) as wgpu::BufferAddress;
+        let joint_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("joint_buffer"),
+            size: joint_size,
+            usage: wgpu::BufferUsage::STORAGE | wgpu::BufferUsage::COPY_DST,
+        });
+        let joint_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: joint_bind_group_layout,
+            bindings: &[wgpu::Binding {
+                binding: 0,
+                resource: wgpu::BindingResource::Buffer {
+                    buffer: &joint_buffer,
+                    range: 0..joint_size,
+                },
+            }],
+            label: Some("joint_bind_group"),
+        });
+        let morph_targets = MorphTargets::new(
+            device,
+            morph_bind_group_layout,
+            &model.morph_targets,
+            &model.default_weights,
+            model.vertices.len(),
+            1,
+        );
+
+        Self {
+            animator: Animator::new(&model.skeleton, model.default_weights),
+            skeleton: model.skeleton,
+            clips: model.clips,
+            material,
+            bounds,
+            vertex_buffer,
+            index_buffer,
+            index_count: indices.len() as u32,
+            instance_buffer,
+            joint_buffer,
+            joint_bind_group,
+            joint_matrices: Vec::new(),
+            morph_targets,
+        }
+    }
+
+    /// Cross-fades to the clip after the current one.
+    pub fn next_clip(&mut self) {
+        if !self.clips.is_empty() {
+            self.animator.play((self.animator.clip() + 1) % self.clips.len());
+        }
+    }
+
+    /// Animates the skeleton by `dt` seconds and uploads the joint matrices and morph
+    /// target weights.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+        dt: f32,
+    ) {
+        let pose = self.animator.update(dt, &self.skeleton, &self.clips);
+        self.morph_targets.set_weights(device, encoder, belt, 0, &pose.morph_weights);
+        self.skeleton.joint_matrices(pose, &mut self.joint_matrices);
+        if self.joint_matrices.is_empty() {
+            return;
+        }
+        let matrices = self
+            .joint_matrices
+            .iter()
+            .map(|&matrix| matrix.into())
+            .collect::<Vec<[[f32; 4]; 4]>>();
+        belt.write_buffer(device, encoder, &self.joint_buffer, 0, bytemuck::cast_slice(&matrices));
+    }
+
+    /// Draws the model, expects the view's uniforms to be bound already.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipeline_cache: &'a PipelineCache,
+    ) {
+        render_pass.set_pipeline(pipeline_cache.get(self.material.pipeline));
+        render_pass.set_bind_group(0, &self.material.bind_group, &[]);
+        render_pass.set_bind_group(2, &self.morph_targets.bind_group, &[]);
+        render_pass.set_bind_group(3, &self.joint_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, &self.vertex_buffer, 0, 0);
+        render_pass.set_vertex_buffer(1, &self.instance_buffer, 0, 0);
+        render_pass.set_index_buffer(&self.index_buffer, 0, 0);
+        render_pass.draw_indexed(0..self.index_count, 0, 0..1);
+    }
+}
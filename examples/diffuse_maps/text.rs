@@ -0,0 +1,775 @@
+use crate::material::BlendMode;
+use crate::pipeline_cache::{DepthState, PipelineCache, PipelineId, PipelineKey};
+use crate::texture;
+use crate::upload::{RingBuffer, StagingBelt};
+use ab_glyph::{Font, FontVec, GlyphId, PxScale, ScaleFont};
+use iced_wgpu::wgpu;
+use std::collections::HashMap;
+use std::ops::Range;
+
+/// Width and height of the glyph atlas. Rows are a multiple of 256 bytes, so any band of
+/// rows copies straight from a buffer.
+const ATLAS_SIZE: u32 = 1024;
+/// Pixel size SDF glyphs are rasterized at, they scale to any size from there
+const SDF_SIZE: f32 = 48.0;
+/// How far in pixels the distance field reaches out of and into a glyph
+const SDF_SPREAD: f32 = 6.0;
+/// Pixel size bitmap glyphs of world labels are rasterized at
+const WORLD_BITMAP_SIZE: f32 = 32.0;
+/// Room for the quads of a few frames, anything past it in one frame is dropped
+const RING_SIZE: wgpu::BufferAddress = 1 << 20;
+const VERTEX_SIZE: wgpu::BufferAddress =
+    std::mem::size_of::<TextVertex>() as wgpu::BufferAddress;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+struct TextVertex {
+    /// Where the text is anchored, in pixels for screen text and in world space for labels
+    anchor: [f32; 3],
+    /// Corner of the glyph quad relative to the anchor, y pointing down
+    offset: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+unsafe impl bytemuck::Pod for TextVertex {}
+unsafe impl bytemuck::Zeroable for TextVertex {}
+
+impl TextVertex {
+    fn desc<'a>() -> wgpu::VertexBufferDescriptor<'a> {
+        wgpu::VertexBufferDescriptor {
+            stride: VERTEX_SIZE,
+            step_mode: wgpu::InputStepMode::Vertex,
+            attributes: &[
+                wgpu::VertexAttributeDescriptor {
+                    offset: 0,
+                    shader_location: 0,
+                    format: wgpu::VertexFormat::Float3,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: 12,
+                    shader_location: 1,
+                    format: wgpu::VertexFormat::Float2,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: 20,
+                    shader_location: 2,
+                    format: wgpu::VertexFormat::Float2,
+                },
+                wgpu::VertexAttributeDescriptor {
+                    offset: 28,
+                    shader_location: 3,
+                    format: wgpu::VertexFormat::Float4,
+                },
+            ],
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct TextUniforms {
+    screen_size: [f32; 2],
+    _padding: [f32; 2],
+}
+
+unsafe impl bytemuck::Pod for TextUniforms {}
+unsafe impl bytemuck::Zeroable for TextUniforms {}
+
+/// How glyphs are stored in the atlas.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GlyphMode {
+    /// Coverage rasterized at the size the text is drawn at, sharp at that size only
+    Bitmap,
+    /// Signed distance to the outline, one rasterization stays sharp at every size
+    Sdf,
+}
+
+impl GlyphMode {
+    pub fn toggle(&self) -> Self {
+        match self {
+            GlyphMode::Bitmap => GlyphMode::Sdf,
+            GlyphMode::Sdf => GlyphMode::Bitmap,
+        }
+    }
+
+    fn frag_path(self) -> &'static str {
+        match self {
+            GlyphMode::Bitmap => "examples/diffuse_maps/shader/text_bitmap_frag.spv",
+            GlyphMode::Sdf => "examples/diffuse_maps/shader/text_sdf_frag.spv",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum HorizontalAlign {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum VerticalAlign {
+    Top,
+    Middle,
+    Bottom,
+}
+
+/// How a piece of text is laid out and colored. Sizes are in pixels for screen text and in
+/// world units for labels.
+#[derive(Copy, Clone, Debug)]
+pub struct TextStyle {
+    /// Height of a line
+    pub size: f32,
+    pub color: [f32; 4],
+    /// Which side of the text the anchor is on, every line is aligned on its own
+    pub horizontal_align: HorizontalAlign,
+    pub vertical_align: VerticalAlign,
+    /// Lines wrap at spaces to stay narrower than this, a word longer than it keeps a line
+    /// to itself
+    pub max_width: Option<f32>,
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            size: 16.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+            horizontal_align: HorizontalAlign::Left,
+            vertical_align: VerticalAlign::Top,
+            max_width: None,
+        }
+    }
+}
+
+/// A glyph placed by `layout`, at its pen position on the baseline relative to the anchor.
+struct PlacedGlyph {
+    id: GlyphId,
+    x: f32,
+    y: f32,
+}
+
+/// Breaks `text` into lines and places its glyphs at pixel size `size`, aligned around
+/// the anchor at the origin.
+fn layout(font: &FontVec, text: &str, size: f32, style: &TextStyle) -> Vec<PlacedGlyph> {
+    let font = font.as_scaled(PxScale::from(size));
+    let max_width = style.max_width.map(|width| width / style.size * size);
+    let space = font.h_advance(font.glyph_id(' '));
+    let word_width = |word: &str| {
+        let mut width = 0.0;
+        let mut previous: Option<GlyphId> = None;
+        for c in word.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                width += font.kern(previous, id);
+            }
+            width += font.h_advance(id);
+            previous = Some(id);
+        }
+        width
+    };
+
+    // Words of every line, with the line's width
+    let mut lines: Vec<(Vec<&str>, f32)> = Vec::new();
+    for paragraph in text.split('\n') {
+        let mut line: Vec<&str> = Vec::new();
+        let mut line_width = 0.0;
+        for word in paragraph.split(' ') {
+            let width = word_width(word);
+            let wrapped_width = line_width + space + width;
+            if !line.is_empty() && max_width.map_or(false, |max| wrapped_width > max) {
+                lines.push((std::mem::take(&mut line), line_width));
+                line_width = 0.0;
+            }
+            line_width += if line.is_empty() { width } else { space + width };
+            line.push(word);
+        }
+        lines.push((line, line_width));
+    }
+
+    let line_height = font.height() + font.line_gap();
+    let text_height = line_height * lines.len() as f32 - font.line_gap();
+    let top = match style.vertical_align {
+        VerticalAlign::Top => 0.0,
+        VerticalAlign::Middle => -text_height * 0.5,
+        VerticalAlign::Bottom => -text_height,
+    };
+    let mut glyphs = Vec::new();
+    for (i, (words, width)) in lines.iter().enumerate() {
+        let mut x = match style.horizontal_align {
+            HorizontalAlign::Left => 0.0,
+            HorizontalAlign::Center => -width * 0.5,
+            HorizontalAlign::Right => -width,
+        };
+        let y = top + font.ascent() + line_height * i as f32;
+        for (j, word) in words.iter().enumerate() {
+            if j > 0 {
+                x += space;
+            }
+            let mut previous: Option<GlyphId> = None;
+            for c in word.chars() {
+                let id = font.glyph_id(c);
+                if let Some(previous) = previous {
+                    x += font.kern(previous, id);
+                }
+                glyphs.push(PlacedGlyph { id, x, y });
+                x += font.h_advance(id);
+                previous = Some(id);
+            }
+        }
+    }
+    glyphs
+}
+
+/// Which rasterization of a glyph an atlas entry holds.
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+struct GlyphKey {
+    id: GlyphId,
+    /// Pixel size of a bitmap glyph, zero for SDF glyphs
+    size: u32,
+}
+
+/// A glyph in the atlas, with its quad in pixels of the size it was rasterized at
+/// relative to the pen position.
+#[derive(Copy, Clone, Debug)]
+struct AtlasGlyph {
+    uv_min: [f32; 2],
+    uv_max: [f32; 2],
+    offset: [f32; 2],
+    size: [f32; 2],
+}
+
+/// Single channel texture glyphs are packed into, row by row. Glyphs are rasterized into
+/// a copy on the cpu and the rows that changed are copied up on `upload`. Once it's full
+/// the atlas is emptied and starts over with the glyphs still in use.
+struct GlyphAtlas {
+    pixels: Vec<u8>,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    /// Glyphs rasterized so far, `None` for the ones without an outline like spaces
+    glyphs: HashMap<GlyphKey, Option<AtlasGlyph>>,
+    /// Where the next glyph goes, and the height of the row it goes into
+    cursor: (u32, u32),
+    row_height: u32,
+    /// Rows changed since the last upload
+    dirty_rows: Option<Range<u32>>,
+    /// A glyph didn't fit, the atlas is emptied before the next frame's text
+    full: bool,
+}
+
+impl GlyphAtlas {
+    fn new(device: &wgpu::Device) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("glyph_atlas"),
+            size: wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth: 1,
+            },
+            array_layer_count: 1,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsage::SAMPLED | wgpu::TextureUsage::COPY_DST,
+        });
+        let view = texture.create_default_view();
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            lod_min_clamp: -100.0,
+            lod_max_clamp: 100.0,
+            compare: wgpu::CompareFunction::Always,
+        });
+        Self {
+            pixels: vec![0; (ATLAS_SIZE * ATLAS_SIZE) as usize],
+            texture,
+            view,
+            sampler,
+            glyphs: HashMap::new(),
+            cursor: (0, 0),
+            row_height: 0,
+            // The texture starts out uninitialized
+            dirty_rows: Some(0..ATLAS_SIZE),
+            full: false,
+        }
+    }
+
+    fn clear(&mut self) {
+        for pixel in &mut self.pixels {
+            *pixel = 0;
+        }
+        self.glyphs.clear();
+        self.cursor = (0, 0);
+        self.row_height = 0;
+        self.dirty_rows = Some(0..ATLAS_SIZE);
+        self.full = false;
+    }
+
+    /// Looks up a glyph, rasterizing it on first use. `None` for glyphs without an outline
+    /// and for the ones that don't fit anymore.
+    fn glyph(&mut self, font: &FontVec, key: GlyphKey) -> Option<AtlasGlyph> {
+        if let Some(glyph) = self.glyphs.get(&key) {
+            return *glyph;
+        }
+        let (size, padding) = if key.size == 0 {
+            (SDF_SIZE, SDF_SPREAD.ceil() as u32)
+        } else {
+            // Keeps linear filtering from bleeding in the neighbours
+            (key.size as f32, 1)
+        };
+        let outlined = match font.outline_glyph(key.id.with_scale(size)) {
+            Some(outlined) => outlined,
+            None => {
+                self.glyphs.insert(key, None);
+                return None;
+            }
+        };
+        let bounds = outlined.px_bounds();
+        let (inner_width, inner_height) = (bounds.width() as u32, bounds.height() as u32);
+        let (width, height) = (inner_width + padding * 2, inner_height + padding * 2);
+        let (x, y) = self.allocate(width, height)?;
+
+        let mut coverage = vec![0.0; (width * height) as usize];
+        outlined.draw(|gx, gy, c| {
+            if gx < inner_width && gy < inner_height {
+                coverage[((gy + padding) * width + gx + padding) as usize] = c;
+            }
+        });
+        let values = if key.size == 0 {
+            signed_distance_field(&coverage, width, height)
+        } else {
+            coverage.iter().map(|c| (c * 255.0).round() as u8).collect()
+        };
+        for row in 0..height {
+            let start = ((y + row) * ATLAS_SIZE + x) as usize;
+            let source = (row * width) as usize;
+            self.pixels[start..start + width as usize]
+                .copy_from_slice(&values[source..source + width as usize]);
+        }
+        self.dirty_rows = Some(match self.dirty_rows.take() {
+            Some(rows) => rows.start.min(y)..rows.end.max(y + height),
+            None => y..y + height,
+        });
+
+        let atlas_size = ATLAS_SIZE as f32;
+        let glyph = AtlasGlyph {
+            uv_min: [x as f32 / atlas_size, y as f32 / atlas_size],
+            uv_max: [(x + width) as f32 / atlas_size, (y + height) as f32 / atlas_size],
+            offset: [bounds.min.x - padding as f32, bounds.min.y - padding as f32],
+            size: [width as f32, height as f32],
+        };
+        self.glyphs.insert(key, Some(glyph));
+        Some(glyph)
+    }
+
+    /// Finds room for a `width` by `height` glyph, moving on to a new row when this one
+    /// is out of room.
+    fn allocate(&mut self, width: u32, height: u32) -> Option<(u32, u32)> {
+        if self.cursor.0 + width > ATLAS_SIZE {
+            self.cursor = (0, self.cursor.1 + self.row_height);
+            self.row_height = 0;
+        }
+        if width > ATLAS_SIZE || self.cursor.1 + height > ATLAS_SIZE {
+            self.full = true;
+            return None;
+        }
+        let position = self.cursor;
+        self.cursor.0 += width;
+        self.row_height = self.row_height.max(height);
+        Some(position)
+    }
+
+    fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+    ) {
+        let rows = match self.dirty_rows.take() {
+            Some(rows) => rows,
+            None => return,
+        };
+        let start = (rows.start * ATLAS_SIZE) as usize;
+        let end = (rows.end * ATLAS_SIZE) as usize;
+        belt.write_texture(
+            device,
+            encoder,
+            wgpu::TextureCopyView {
+                texture: &self.texture,
+                mip_level: 0,
+                array_layer: 0,
+                origin: wgpu::Origin3d {
+                    x: 0,
+                    y: rows.start,
+                    z: 0,
+                },
+            },
+            wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: rows.end - rows.start,
+                depth: 1,
+            },
+            ATLAS_SIZE,
+            &self.pixels[start..end],
+        );
+    }
+}
+
+/// Distance field of a coverage bitmap, 0.5 on the outline and rising to 1 `SDF_SPREAD`
+/// pixels inside. Brute force over the spread around every pixel, glyphs are small and
+/// only rasterized once.
+fn signed_distance_field(coverage: &[f32], width: u32, height: u32) -> Vec<u8> {
+    let spread = SDF_SPREAD.ceil() as i32;
+    let inside = |x: i32, y: i32| {
+        x >= 0
+            && y >= 0
+            && x < width as i32
+            && y < height as i32
+            && coverage[(y * width as i32 + x) as usize] >= 0.5
+    };
+    let mut values = Vec::with_capacity((width * height) as usize);
+    for y in 0..height as i32 {
+        for x in 0..width as i32 {
+            let is_inside = inside(x, y);
+            let mut nearest = SDF_SPREAD;
+            for dy in -spread..=spread {
+                for dx in -spread..=spread {
+                    if inside(x + dx, y + dy) != is_inside {
+                        // The outline runs between the two pixel centers
+                        let distance = ((dx * dx + dy * dy) as f32).sqrt() - 0.5;
+                        nearest = nearest.min(distance);
+                    }
+                }
+            }
+            let signed = if is_inside { nearest } else { -nearest };
+            let value = 0.5 + signed / (2.0 * SDF_SPREAD);
+            values.push((value.max(0.0).min(1.0) * 255.0).round() as u8);
+        }
+    }
+    values
+}
+
+/// Text drawn by the scene renderer, labels placed in the world and screen space text
+/// like a HUD.
+///
+/// Works like `DebugDraw`: text queued during the frame is laid out into glyph quads,
+/// `upload` copies them and any new glyphs to the gpu and `clear` starts the next frame.
+pub struct TextRenderer {
+    font: FontVec,
+    mode: GlyphMode,
+    atlas: GlyphAtlas,
+    uniforms: TextUniforms,
+    uniform_buffer: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    world: Vec<TextVertex>,
+    screen: Vec<TextVertex>,
+    ring: RingBuffer,
+    /// Where this frame's quads start in the ring, the world ones first
+    offset: wgpu::BufferAddress,
+    world_count: u32,
+    screen_count: u32,
+    world_key: PipelineKey,
+    screen_key: PipelineKey,
+    world_pipeline: PipelineId,
+    screen_pipeline: PipelineId,
+}
+
+impl TextRenderer {
+    /// Both read the atlas from bind group 0, labels read the view uniforms from group 1
+    /// like the instances do.
+    pub fn new(
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        uniform_bind_group_layout: &wgpu::BindGroupLayout,
+        color_format: wgpu::TextureFormat,
+        depth_compare: wgpu::CompareFunction,
+        font: FontVec,
+        mode: GlyphMode,
+    ) -> Self {
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            bindings: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::SampledTexture {
+                        multisampled: false,
+                        dimension: wgpu::TextureViewDimension::D2,
+                        component_type: wgpu::TextureComponentType::Float,
+                    },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStage::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler { comparison: false },
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStage::VERTEX,
+                    ty: wgpu::BindingType::UniformBuffer { dynamic: false },
+                },
+            ],
+            label: Some("text_bind_group_layout"),
+        });
+        let atlas = GlyphAtlas::new(device);
+        let uniforms = TextUniforms {
+            screen_size: [1.0, 1.0],
+            _padding: [0.0; 2],
+        };
+        let uniform_buffer = device.create_buffer_with_data(
+            bytemuck::cast_slice(&[uniforms]),
+            wgpu::BufferUsage::UNIFORM | wgpu::BufferUsage::COPY_DST,
+        );
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            bindings: &[
+                wgpu::Binding {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&atlas.view),
+                },
+                wgpu::Binding {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&atlas.sampler),
+                },
+                wgpu::Binding {
+                    binding: 2,
+                    resource: wgpu::BindingResource::Buffer {
+                        buffer: &uniform_buffer,
+                        range: 0..std::mem::size_of::<TextUniforms>() as wgpu::BufferAddress,
+                    },
+                },
+            ],
+            label: Some("text_bind_group"),
+        });
+
+        let world_layout = pipeline_cache
+            .create_layout(device, &[&bind_group_layout, uniform_bind_group_layout]);
+        let screen_layout = pipeline_cache.create_layout(device, &[&bind_group_layout]);
+        let world_key = PipelineKey {
+            color_targets: vec![BlendMode::AlphaBlend.color_target(color_format)],
+            depth: Some(DepthState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare,
+            }),
+            ..PipelineKey::new(
+                world_layout,
+                "examples/diffuse_maps/shader/text_world_vert.spv",
+                mode.frag_path(),
+            )
+            .with_vertex_layouts(&[TextVertex::desc()])
+        };
+        // Over everything, the passes have a depth attachment so it still needs a state
+        let screen_key = PipelineKey {
+            layout: screen_layout,
+            vert_path: "examples/diffuse_maps/shader/text_screen_vert.spv".to_string(),
+            depth: Some(DepthState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::Always,
+            }),
+            ..world_key.clone()
+        };
+        Self {
+            font,
+            mode,
+            atlas,
+            uniforms,
+            uniform_buffer,
+            bind_group,
+            world: Vec::new(),
+            screen: Vec::new(),
+            ring: RingBuffer::new(device, RING_SIZE, 0, wgpu::BufferUsage::VERTEX, "text_ring"),
+            offset: 0,
+            world_count: 0,
+            screen_count: 0,
+            world_pipeline: pipeline_cache.get_or_create(device, &world_key),
+            screen_pipeline: pipeline_cache.get_or_create(device, &screen_key),
+            world_key,
+            screen_key,
+        }
+    }
+
+    /// Loads a TrueType or OpenType font file.
+    pub fn load_font(path: &str) -> Result<FontVec, failure::Error> {
+        let data = std::fs::read(path)?;
+        FontVec::try_from_vec(data).map_err(|_| failure::format_err!("invalid font {}", path))
+    }
+
+    pub fn mode(&self) -> GlyphMode {
+        self.mode
+    }
+
+    /// Switches between bitmap and SDF glyphs, the ones of the other mode stay in the
+    /// atlas until it fills up.
+    pub fn set_mode(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        mode: GlyphMode,
+    ) {
+        self.mode = mode;
+        self.world_key.frag_path = Some(mode.frag_path().to_string());
+        self.screen_key.frag_path = Some(mode.frag_path().to_string());
+        self.world_pipeline = pipeline_cache.get_or_create(device, &self.world_key);
+        self.screen_pipeline = pipeline_cache.get_or_create(device, &self.screen_key);
+    }
+
+    /// Switches labels to another depth test, like `Material::set_depth_compare`.
+    pub fn set_depth_compare(
+        &mut self,
+        device: &wgpu::Device,
+        pipeline_cache: &mut PipelineCache,
+        depth_compare: wgpu::CompareFunction,
+    ) {
+        if let Some(depth) = &mut self.world_key.depth {
+            depth.depth_compare = depth_compare;
+        }
+        self.world_pipeline = pipeline_cache.get_or_create(device, &self.world_key);
+    }
+
+    /// Size in pixels of the target screen text is drawn on.
+    pub fn set_screen_size(&mut self, width: u32, height: u32) {
+        self.uniforms.screen_size = [width as f32, height as f32];
+    }
+
+    /// Forgets the text queued so far, the text already uploaded is still drawn.
+    pub fn clear(&mut self) {
+        self.world.clear();
+        self.screen.clear();
+        if self.atlas.full {
+            self.atlas.clear();
+        }
+    }
+
+    /// Queues text at `position` in pixels from the top left corner of the screen.
+    pub fn queue_screen(&mut self, position: [f32; 2], text: &str, style: &TextStyle) {
+        let size = match self.mode {
+            GlyphMode::Bitmap => style.size.round().max(1.0),
+            GlyphMode::Sdf => SDF_SIZE,
+        };
+        let anchor = [position[0], position[1], 0.0];
+        self.queue(anchor, text, size, style, false);
+    }
+
+    /// Queues a label at `position` in the world, facing the camera.
+    pub fn queue_world(&mut self, position: cgmath::Point3<f32>, text: &str, style: &TextStyle) {
+        let size = match self.mode {
+            GlyphMode::Bitmap => WORLD_BITMAP_SIZE,
+            GlyphMode::Sdf => SDF_SIZE,
+        };
+        self.queue(position.into(), text, size, style, true);
+    }
+
+    /// Lays out text at pixel size `size` and adds a quad per glyph, scaled to the
+    /// style's size.
+    fn queue(&mut self, anchor: [f32; 3], text: &str, size: f32, style: &TextStyle, world: bool) {
+        let scale = style.size / size;
+        let glyph_size = match self.mode {
+            GlyphMode::Bitmap => size as u32,
+            GlyphMode::Sdf => 0,
+        };
+        for placed in layout(&self.font, text, size, style) {
+            let key = GlyphKey {
+                id: placed.id,
+                size: glyph_size,
+            };
+            let glyph = match self.atlas.glyph(&self.font, key) {
+                Some(glyph) => glyph,
+                None => continue,
+            };
+            let min = [
+                (placed.x + glyph.offset[0]) * scale,
+                (placed.y + glyph.offset[1]) * scale,
+            ];
+            let max = [min[0] + glyph.size[0] * scale, min[1] + glyph.size[1] * scale];
+            let corner = |x: usize, y: usize| TextVertex {
+                anchor,
+                offset: [[min[0], max[0]][x], [min[1], max[1]][y]],
+                uv: [
+                    [glyph.uv_min[0], glyph.uv_max[0]][x],
+                    [glyph.uv_min[1], glyph.uv_max[1]][y],
+                ],
+                color: style.color,
+            };
+            let vertices = if world { &mut self.world } else { &mut self.screen };
+            vertices.extend_from_slice(&[
+                corner(0, 0),
+                corner(0, 1),
+                corner(1, 1),
+                corner(0, 0),
+                corner(1, 1),
+                corner(1, 0),
+            ]);
+        }
+    }
+
+    /// Copies this frame's quads, new glyphs and the screen size to the gpu.
+    pub fn upload(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+    ) {
+        self.atlas.upload(device, encoder, belt);
+        let uniforms = [self.uniforms];
+        belt.write_buffer(
+            device,
+            encoder,
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&uniforms),
+        );
+
+        let capacity = (RING_SIZE / VERTEX_SIZE) as usize / 6 * 6;
+        self.world.truncate(capacity);
+        self.screen.truncate(capacity - self.world.len());
+        self.world_count = self.world.len() as u32;
+        self.screen_count = self.screen.len() as u32;
+        if self.world.is_empty() && self.screen.is_empty() {
+            return;
+        }
+        let vertices = self.world.iter().chain(&self.screen).copied().collect::<Vec<_>>();
+        self.offset = self.ring.push(device, encoder, belt, bytemuck::cast_slice(&vertices));
+    }
+
+    /// Draws the uploaded labels for a view, `uniform_bind_group` is the view's, bound at
+    /// `uniform_offset`.
+    pub fn draw_world<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipeline_cache: &'a PipelineCache,
+        uniform_bind_group: &'a wgpu::BindGroup,
+        uniform_offset: wgpu::DynamicOffset,
+    ) {
+        if self.world_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(pipeline_cache.get(self.world_pipeline));
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, uniform_bind_group, &[uniform_offset]);
+        render_pass.set_vertex_buffer(0, &self.ring.buffer, self.offset, 0);
+        render_pass.draw(0..self.world_count, 0..1);
+    }
+
+    /// Draws the uploaded screen text, the viewport should cover the whole screen.
+    pub fn draw_screen<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipeline_cache: &'a PipelineCache,
+    ) {
+        if self.screen_count == 0 {
+            return;
+        }
+        let end = self.world_count + self.screen_count;
+        render_pass.set_pipeline(pipeline_cache.get(self.screen_pipeline));
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_vertex_buffer(0, &self.ring.buffer, self.offset, 0);
+        render_pass.draw(self.world_count..end, 0..1);
+    }
+}
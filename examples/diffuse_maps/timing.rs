@@ -0,0 +1,142 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const FRAME_TIME_SAMPLES: usize = 120;
+
+/// What happened since the previous frame, returned by `AppLoop::tick`.
+#[derive(Copy, Clone, Debug)]
+pub struct FrameTime {
+    pub index: u64,
+    /// Time since the loop was created
+    pub elapsed: Duration,
+    /// Real time since the previous frame
+    pub delta: Duration,
+    /// How many fixed simulation steps have to run this frame
+    pub fixed_steps: u32,
+    pub fixed_delta: Duration,
+    /// How far between the previous and the current simulation step the frame is,
+    /// in `0..1`. Rendering interpolates the simulated state with it.
+    pub alpha: f32,
+}
+
+/// Drives the update loop: measures frame time, splits it into fixed simulation steps
+/// and optionally limits the frame rate.
+pub struct AppLoop {
+    fixed_delta: Duration,
+    /// Frames slower than this are clamped, otherwise a long stall would make the
+    /// simulation try to catch up forever
+    max_delta: Duration,
+    min_frame_time: Option<Duration>,
+    start: Instant,
+    last_frame: Instant,
+    accumulator: Duration,
+    index: u64,
+    stats: FrameStats,
+}
+
+impl AppLoop {
+    pub fn new(fixed_updates_per_second: u32) -> Self {
+        let now = Instant::now();
+        Self {
+            fixed_delta: Duration::from_secs(1) / fixed_updates_per_second,
+            max_delta: Duration::from_millis(250),
+            min_frame_time: None,
+            start: now,
+            last_frame: now,
+            accumulator: Duration::from_secs(0),
+            index: 0,
+            stats: FrameStats::new(),
+        }
+    }
+
+    /// `None` renders as fast as possible (or as fast as the present mode allows).
+    pub fn set_frame_rate_limit(&mut self, frames_per_second: Option<u32>) {
+        self.min_frame_time = frames_per_second.map(|fps| Duration::from_secs(1) / fps);
+    }
+
+    /// When the next frame may start, `None` if the frame rate isn't limited.
+    pub fn next_frame_at(&self) -> Option<Instant> {
+        self.min_frame_time.map(|min_frame_time| self.last_frame + min_frame_time)
+    }
+
+    pub fn is_frame_due(&self) -> bool {
+        match self.next_frame_at() {
+            Some(next_frame_at) => Instant::now() >= next_frame_at,
+            None => true,
+        }
+    }
+
+    /// Has to be called exactly once per rendered frame.
+    pub fn tick(&mut self) -> FrameTime {
+        let now = Instant::now();
+        let delta = now - self.last_frame;
+        self.last_frame = now;
+        self.stats.push(delta);
+
+        self.accumulator += delta.min(self.max_delta);
+        let mut fixed_steps = 0;
+        while self.accumulator >= self.fixed_delta {
+            self.accumulator -= self.fixed_delta;
+            fixed_steps += 1;
+        }
+
+        let frame = FrameTime {
+            index: self.index,
+            elapsed: now - self.start,
+            delta,
+            fixed_steps,
+            fixed_delta: self.fixed_delta,
+            alpha: self.accumulator.as_secs_f32() / self.fixed_delta.as_secs_f32(),
+        };
+        self.index += 1;
+        frame
+    }
+
+    pub fn stats(&self) -> &FrameStats {
+        &self.stats
+    }
+}
+
+/// Rolling statistics over the last `FRAME_TIME_SAMPLES` frames.
+pub struct FrameStats {
+    frame_times: VecDeque<Duration>,
+}
+
+impl FrameStats {
+    fn new() -> Self {
+        Self {
+            frame_times: VecDeque::with_capacity(FRAME_TIME_SAMPLES),
+        }
+    }
+
+    fn push(&mut self, frame_time: Duration) {
+        if self.frame_times.len() == FRAME_TIME_SAMPLES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+    }
+
+    pub fn average_frame_time(&self) -> Duration {
+        if self.frame_times.is_empty() {
+            return Duration::from_secs(0);
+        }
+        self.frame_times.iter().sum::<Duration>() / self.frame_times.len() as u32
+    }
+
+    pub fn min_frame_time(&self) -> Duration {
+        self.frame_times.iter().min().copied().unwrap_or_default()
+    }
+
+    pub fn max_frame_time(&self) -> Duration {
+        self.frame_times.iter().max().copied().unwrap_or_default()
+    }
+
+    pub fn fps(&self) -> f32 {
+        let average = self.average_frame_time().as_secs_f32();
+        if average > 0.0 {
+            1.0 / average
+        } else {
+            0.0
+        }
+    }
+}
@@ -0,0 +1,262 @@
+use iced_wgpu::wgpu;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+/// `copy_buffer_to_buffer` offsets and sizes have to be multiples of this.
+const COPY_ALIGNMENT: wgpu::BufferAddress = 4;
+
+type MapWriteFuture =
+    Pin<Box<dyn Future<Output = Result<wgpu::BufferWriteMapping, wgpu::BufferAsyncErr>>>>;
+
+struct MappedChunk {
+    buffer: wgpu::Buffer,
+    size: wgpu::BufferAddress,
+    offset: wgpu::BufferAddress,
+    mapping: wgpu::BufferWriteMapping,
+}
+
+struct ClosedChunk {
+    buffer: wgpu::Buffer,
+    size: wgpu::BufferAddress,
+}
+
+struct RecallingChunk {
+    buffer: wgpu::Buffer,
+    size: wgpu::BufferAddress,
+    future: MapWriteFuture,
+}
+
+/// Uploads data to the gpu through a set of persistent staging buffers.
+///
+/// Data is written into mapped chunks on the cpu and copied into the destination by the
+/// encoder. Before submitting the encoder call `finish` to unmap the chunks used this
+/// frame, after submitting call `recall` so they get mapped again once the gpu is done
+/// copying. New chunks are only created when every existing one is still in flight.
+pub struct StagingBelt {
+    chunk_size: wgpu::BufferAddress,
+    active_chunks: Vec<MappedChunk>,
+    closed_chunks: Vec<ClosedChunk>,
+    recalling_chunks: Vec<RecallingChunk>,
+    free_chunks: Vec<MappedChunk>,
+}
+
+impl StagingBelt {
+    pub fn new(chunk_size: wgpu::BufferAddress) -> Self {
+        Self {
+            chunk_size,
+            active_chunks: Vec::new(),
+            closed_chunks: Vec::new(),
+            recalling_chunks: Vec::new(),
+            free_chunks: Vec::new(),
+        }
+    }
+
+    pub fn write_buffer(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: &wgpu::Buffer,
+        offset: wgpu::BufferAddress,
+        data: &[u8],
+    ) {
+        let size = data.len() as wgpu::BufferAddress;
+        assert_eq!(size % COPY_ALIGNMENT, 0, "upload size must be a multiple of 4");
+        assert_eq!(offset % COPY_ALIGNMENT, 0, "upload offset must be a multiple of 4");
+
+        let (index, chunk_offset) = self.stage(device, data);
+        let chunk = &self.active_chunks[index];
+        encoder.copy_buffer_to_buffer(&chunk.buffer, chunk_offset, target, offset, size);
+    }
+
+    /// Copies `data`, rows of `bytes_per_row` bytes, into the `size` texels of a texture at
+    /// `target`. `bytes_per_row` has to be a multiple of 256.
+    pub fn write_texture(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        target: wgpu::TextureCopyView,
+        size: wgpu::Extent3d,
+        bytes_per_row: u32,
+        data: &[u8],
+    ) {
+        let (index, chunk_offset) = self.stage(device, data);
+        let chunk = &self.active_chunks[index];
+        encoder.copy_buffer_to_texture(
+            wgpu::BufferCopyView {
+                buffer: &chunk.buffer,
+                offset: chunk_offset,
+                bytes_per_row,
+                rows_per_image: size.height,
+            },
+            target,
+            size,
+        );
+    }
+
+    /// Unmaps the chunks written this frame. Has to be called before the encoder holding
+    /// the copies is submitted.
+    pub fn finish(&mut self) {
+        for chunk in self.active_chunks.drain(..) {
+            // dropping the mapping unmaps the buffer
+            let MappedChunk { buffer, size, .. } = chunk;
+            self.closed_chunks.push(ClosedChunk { buffer, size });
+        }
+    }
+
+    /// Starts mapping the chunks used by the last submit again. They can be reused as soon as
+    /// the gpu finished copying out of them.
+    pub fn recall(&mut self) {
+        for chunk in self.closed_chunks.drain(..) {
+            let future = Box::pin(chunk.buffer.map_write(0, chunk.size));
+            self.recalling_chunks.push(RecallingChunk {
+                buffer: chunk.buffer,
+                size: chunk.size,
+                future,
+            });
+        }
+    }
+
+    /// Writes `data` into an active chunk, returns the chunk's index and where in it the
+    /// data starts.
+    fn stage(&mut self, device: &wgpu::Device, data: &[u8]) -> (usize, wgpu::BufferAddress) {
+        let size = data.len() as wgpu::BufferAddress;
+        let index = match self
+            .active_chunks
+            .iter()
+            .position(|chunk| chunk.offset + size <= chunk.size)
+        {
+            Some(index) => index,
+            None => {
+                let chunk = self.take_free_chunk(device, size);
+                self.active_chunks.push(chunk);
+                self.active_chunks.len() - 1
+            }
+        };
+
+        let chunk = &mut self.active_chunks[index];
+        let offset = chunk.offset;
+        let start = offset as usize;
+        chunk.mapping.as_slice()[start..start + data.len()].copy_from_slice(data);
+        chunk.offset = align_to(offset + size, COPY_ALIGNMENT);
+        (index, offset)
+    }
+
+    fn take_free_chunk(&mut self, device: &wgpu::Device, size: wgpu::BufferAddress) -> MappedChunk {
+        self.poll_recalled(device);
+        if let Some(index) = self.free_chunks.iter().position(|chunk| chunk.size >= size) {
+            return self.free_chunks.swap_remove(index);
+        }
+
+        let size = size.max(self.chunk_size);
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("staging_belt_chunk"),
+            size,
+            usage: wgpu::BufferUsage::MAP_WRITE | wgpu::BufferUsage::COPY_SRC,
+        });
+        // A fresh buffer isn't used by the gpu, so this doesn't actually wait for anything
+        let mapping = buffer.map_write(0, size);
+        device.poll(wgpu::Maintain::Wait);
+        let mapping = futures::executor::block_on(mapping).expect("map staging chunk");
+
+        MappedChunk {
+            buffer,
+            size,
+            offset: 0,
+            mapping,
+        }
+    }
+
+    fn poll_recalled(&mut self, device: &wgpu::Device) {
+        device.poll(wgpu::Maintain::Poll);
+
+        let waker = futures::task::noop_waker_ref();
+        let mut context = Context::from_waker(waker);
+        let mut i = 0;
+        while i < self.recalling_chunks.len() {
+            match self.recalling_chunks[i].future.as_mut().poll(&mut context) {
+                Poll::Pending => i += 1,
+                Poll::Ready(result) => {
+                    let chunk = self.recalling_chunks.swap_remove(i);
+                    // a chunk that failed to map is simply dropped
+                    if let Ok(mapping) = result {
+                        self.free_chunks.push(MappedChunk {
+                            buffer: chunk.buffer,
+                            size: chunk.size,
+                            offset: 0,
+                            mapping,
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A persistent gpu buffer sub-allocated front to back and wrapped around when full.
+///
+/// Every `push` gets its own region, so several uploads recorded in the same encoder
+/// (e.g. uniforms of several cameras) don't overwrite each other. Copies are executed in
+/// submission order, so reusing a region after wrapping can't race with earlier draws.
+pub struct RingBuffer {
+    pub buffer: wgpu::Buffer,
+    size: wgpu::BufferAddress,
+    alignment: wgpu::BufferAddress,
+    head: wgpu::BufferAddress,
+}
+
+impl RingBuffer {
+    pub fn new(
+        device: &wgpu::Device,
+        size: wgpu::BufferAddress,
+        alignment: wgpu::BufferAddress,
+        usage: wgpu::BufferUsage,
+        label: &str,
+    ) -> Self {
+        let buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size,
+            usage: usage | wgpu::BufferUsage::COPY_DST,
+        });
+        Self {
+            buffer,
+            size,
+            alignment: alignment.max(COPY_ALIGNMENT),
+            head: 0,
+        }
+    }
+
+    /// Uniform buffer ring whose allocations can be used as dynamic offsets.
+    pub fn uniform(device: &wgpu::Device, size: wgpu::BufferAddress, label: &str) -> Self {
+        Self::new(
+            device,
+            size,
+            wgpu::BIND_BUFFER_ALIGNMENT,
+            wgpu::BufferUsage::UNIFORM,
+            label,
+        )
+    }
+
+    /// Returns the offset the data will be at once the encoder is executed.
+    pub fn push(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        belt: &mut StagingBelt,
+        data: &[u8],
+    ) -> wgpu::BufferAddress {
+        let size = data.len() as wgpu::BufferAddress;
+        assert!(size <= self.size, "upload doesn't fit into the ring buffer");
+        if self.head + size > self.size {
+            self.head = 0;
+        }
+        let offset = self.head;
+        belt.write_buffer(device, encoder, &self.buffer, offset, data);
+        self.head = align_to(offset + size, self.alignment);
+        offset
+    }
+}
+
+fn align_to(value: wgpu::BufferAddress, alignment: wgpu::BufferAddress) -> wgpu::BufferAddress {
+    (value + alignment - 1) / alignment * alignment
+}
@@ -0,0 +1,117 @@
+use crate::camera::Camera;
+use iced_winit::winit;
+use iced_wgpu::wgpu;
+
+/// Part of the surface a viewport covers, as fractions of the surface size with the
+/// origin in the top left corner. Stays valid when the window is resized.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ViewportRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl ViewportRect {
+    pub const FULL: ViewportRect = ViewportRect {
+        x: 0.0,
+        y: 0.0,
+        width: 1.0,
+        height: 1.0,
+    };
+}
+
+/// A viewport rectangle in physical pixels.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PhysicalRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Region of the surface a camera renders into. Keeps its pixel rectangle and the
+/// camera's aspect in sync with the surface size and scale factor.
+pub struct Viewport {
+    rect: ViewportRect,
+    physical: PhysicalRect,
+    scale_factor: f64,
+}
+
+impl Viewport {
+    pub fn new(
+        rect: ViewportRect,
+        surface_size: winit::dpi::PhysicalSize<u32>,
+        scale_factor: f64,
+    ) -> Self {
+        let mut viewport = Self {
+            rect,
+            physical: PhysicalRect {
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+            },
+            scale_factor,
+        };
+        viewport.resize(surface_size, scale_factor);
+        viewport
+    }
+
+    pub fn physical_rect(&self) -> PhysicalRect {
+        self.physical
+    }
+
+    pub fn scale_factor(&self) -> f64 {
+        self.scale_factor
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.physical.width as f32 / self.physical.height as f32
+    }
+
+    pub fn set_rect(&mut self, rect: ViewportRect, surface_size: winit::dpi::PhysicalSize<u32>) {
+        self.rect = rect;
+        self.resize(surface_size, self.scale_factor);
+    }
+
+    pub fn resize(&mut self, surface_size: winit::dpi::PhysicalSize<u32>, scale_factor: f64) {
+        let surface_width = surface_size.width as f32;
+        let surface_height = surface_size.height as f32;
+        let x = (self.rect.x * surface_width).round() as u32;
+        let y = (self.rect.y * surface_height).round() as u32;
+        let right = ((self.rect.x + self.rect.width) * surface_width).round() as u32;
+        let bottom = ((self.rect.y + self.rect.height) * surface_height).round() as u32;
+
+        self.scale_factor = scale_factor;
+        // A minimized window has a zero sized surface, keep at least a pixel so the aspect
+        // doesn't become NaN
+        self.physical = PhysicalRect {
+            x,
+            y,
+            width: right.saturating_sub(x).max(1),
+            height: bottom.saturating_sub(y).max(1),
+        };
+    }
+
+    /// Matches the camera's aspect to the viewport.
+    pub fn apply(&self, camera: &mut Camera) {
+        camera.aspect = self.aspect();
+    }
+
+    /// Restricts drawing to the viewport. The scissor rect keeps primitives that end up
+    /// outside of the viewport (wide lines, points, guard band) from bleeding into
+    /// neighbouring views.
+    pub fn set_on<'a>(&self, render_pass: &mut wgpu::RenderPass<'a>) {
+        let rect = self.physical;
+        render_pass.set_viewport(
+            rect.x as f32,
+            rect.y as f32,
+            rect.width as f32,
+            rect.height as f32,
+            0.0,
+            1.0,
+        );
+        render_pass.set_scissor_rect(rect.x, rect.y, rect.width, rect.height);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::pipeline_cache::PipelineCacheStats;
+use iced_wgpu::Renderer;
+use iced_winit::{
+    slider, Align, Checkbox, Color, Column, Command, Element, Length, Program, Row, Slider, Text,
+};
+
+/// Control panel drawn over the scene. The scene reads what it should look like from here
+/// after every update.
+pub struct Controls {
+    background_color: Color,
+    use_color: bool,
+    pipeline_cache_stats: PipelineCacheStats,
+    sliders: [slider::State; 3],
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    BackgroundColorChanged(Color),
+    UseColorToggled(bool),
+    /// The scene created or looked up a pipeline
+    PipelineCacheStatsChanged(PipelineCacheStats),
+}
+
+impl Controls {
+    pub fn new(pipeline_cache_stats: PipelineCacheStats) -> Controls {
+        Controls {
+            background_color: Color::WHITE,
+            use_color: false,
+            pipeline_cache_stats,
+            sliders: Default::default(),
+        }
+    }
+
+    pub fn background_color(&self) -> Color {
+        self.background_color
+    }
+
+    /// Whether the triangle is drawn with the color pipeline, see `Scene::set_use_color`
+    pub fn use_color(&self) -> bool {
+        self.use_color
+    }
+}
+
+impl Program for Controls {
+    type Renderer = Renderer;
+    type Message = Message;
+
+    fn update(&mut self, message: Message) -> Command<Message> {
+        match message {
+            Message::BackgroundColorChanged(color) => {
+                self.background_color = color;
+            }
+            Message::UseColorToggled(use_color) => {
+                self.use_color = use_color;
+            }
+            Message::PipelineCacheStatsChanged(stats) => {
+                self.pipeline_cache_stats = stats;
+            }
+        }
+
+        Command::none()
+    }
+
+    fn view(&mut self) -> Element<Message, Renderer> {
+        let [r, g, b] = &mut self.sliders;
+        let background_color = self.background_color;
+        let stats = self.pipeline_cache_stats;
+
+        let sliders = Row::new()
+            .width(Length::Units(500))
+            .spacing(20)
+            .push(Slider::new(r, 0.0..=1.0, background_color.r, move |r| {
+                Message::BackgroundColorChanged(Color {
+                    r,
+                    ..background_color
+                })
+            }))
+            .push(Slider::new(g, 0.0..=1.0, background_color.g, move |g| {
+                Message::BackgroundColorChanged(Color {
+                    g,
+                    ..background_color
+                })
+            }))
+            .push(Slider::new(b, 0.0..=1.0, background_color.b, move |b| {
+                Message::BackgroundColorChanged(Color {
+                    b,
+                    ..background_color
+                })
+            }));
+
+        // The panel sits in the bottom right corner, the rest of the window shows the scene
+        Row::new()
+            .width(Length::Fill)
+            .height(Length::Fill)
+            .align_items(Align::End)
+            .push(
+                Column::new()
+                    .width(Length::Fill)
+                    .align_items(Align::End)
+                    .push(
+                        Column::new()
+                            .padding(10)
+                            .spacing(10)
+                            .push(Text::new("Background color").color(Color::BLACK))
+                            .push(sliders)
+                            .push(
+                                Text::new(format!("{:?}", background_color))
+                                    .size(14)
+                                    .color(Color::BLACK),
+                            )
+                            .push(Checkbox::new(
+                                self.use_color,
+                                "Colored triangle",
+                                Message::UseColorToggled,
+                            ))
+                            .push(
+                                Text::new(format!(
+                                    "{} pipelines, {} shader modules, {:.0}% cache hits",
+                                    stats.pipelines,
+                                    stats.shader_modules,
+                                    stats.hit_rate() * 100.0,
+                                ))
+                                .size(14)
+                                .color(Color::BLACK),
+                            ),
+                    ),
+            )
+            .into()
+    }
+}
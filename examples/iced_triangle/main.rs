@@ -1,12 +1,16 @@
+mod controls;
+#[path = "../diffuse_maps/pipeline_cache.rs"]
+mod pipeline_cache;
 mod scene;
 
-use iced_wgpu::{wgpu, Backend, Primitive, Renderer, Settings, Viewport};
+use controls::{Controls, Message};
+use iced_wgpu::{wgpu, Backend, Renderer, Settings, Viewport};
 use iced_winit::winit::{
-    dpi::LogicalSize,
+    dpi::PhysicalPosition,
     event::*,
     event_loop::{ControlFlow, EventLoop},
 };
-use iced_winit::{winit, Size};
+use iced_winit::{conversion, program, winit, Debug, Size};
 use scene::Scene;
 
 pub fn main() {
@@ -19,6 +23,9 @@ pub fn main() {
         Size::new(physical_size.width, physical_size.height),
         window.scale_factor(),
     );
+    // Iced wants to know where the cursor is on every update, not only when it moves
+    let mut cursor_position = PhysicalPosition::new(-1.0, -1.0);
+    let mut modifiers = ModifiersState::default();
 
     // Initialize WGPU
     // contains properties of gpu like name, extensions etc. It's like a graphics driver
@@ -61,37 +68,89 @@ pub fn main() {
     };
     let mut resized = false;
 
+    let mut scene = Scene::new(&device);
+
     // Initialize iced
+    let mut debug = Debug::new();
     let mut renderer = Renderer::new(Backend::new(&mut device, Settings::default()));
-
-    let mut scene = Scene::new(&device);
+    let mut state = program::State::new(
+        Controls::new(scene.pipeline_cache_stats()),
+        viewport.logical_size(),
+        conversion::cursor_position(cursor_position, viewport.scale_factor()),
+        &mut renderer,
+        &mut debug,
+    );
 
     event_loop.run(move |event, _, control_flow| {
         // You should change this if you want to render continuosly
         *control_flow = ControlFlow::Wait;
 
         match event {
-            Event::WindowEvent { event, .. } => match event {
-                WindowEvent::CloseRequested => {
-                    *control_flow = ControlFlow::Exit;
-                }
-                WindowEvent::Resized(new_size) => {
-                    window.set_inner_size(new_size.to_logical::<f32>(window.scale_factor()));
-                    resized = true;
-                }
-                WindowEvent::KeyboardInput { input, .. } => match input {
-                    KeyboardInput {
-                        state: ElementState::Pressed,
-                        virtual_keycode: Some(VirtualKeyCode::Space),
-                        ..
+            Event::WindowEvent { event, .. } => {
+                match &event {
+                    WindowEvent::CloseRequested => {
+                        *control_flow = ControlFlow::Exit;
+                    }
+                    WindowEvent::Resized(new_size) => {
+                        viewport = Viewport::with_physical_size(
+                            Size::new(new_size.width, new_size.height),
+                            window.scale_factor(),
+                        );
+                        resized = true;
+                    }
+                    WindowEvent::ScaleFactorChanged {
+                        scale_factor,
+                        new_inner_size,
                     } => {
-                        scene.toggle_use_color();
-                        window.request_redraw();
+                        viewport = Viewport::with_physical_size(
+                            Size::new(new_inner_size.width, new_inner_size.height),
+                            *scale_factor,
+                        );
+                        resized = true;
+                    }
+                    WindowEvent::CursorMoved { position, .. } => {
+                        cursor_position = *position;
+                    }
+                    WindowEvent::ModifiersChanged(new_modifiers) => {
+                        modifiers = *new_modifiers;
                     }
                     _ => {}
-                },
-                _ => {}
-            },
+                }
+
+                // Iced sees every window event, the ones handled above included
+                if let Some(event) =
+                    conversion::window_event(&event, window.scale_factor(), modifiers)
+                {
+                    state.queue_event(event);
+                }
+            }
+            Event::MainEventsCleared => {
+                // Only when iced has something to react to
+                if !state.is_queue_empty() {
+                    // The scene answers some changes with messages of its own, like new
+                    // cache stats, those are shown right away too
+                    while !state.is_queue_empty() {
+                        let _ = state.update(
+                            None,
+                            viewport.logical_size(),
+                            conversion::cursor_position(cursor_position, viewport.scale_factor()),
+                            &mut renderer,
+                            &mut debug,
+                        );
+
+                        // The scene follows the controls
+                        let controls = state.program();
+                        scene.background_color = controls.background_color();
+                        if controls.use_color() != scene.use_color() {
+                            scene.set_use_color(&device, controls.use_color());
+                            let stats = scene.pipeline_cache_stats();
+                            state.queue_message(Message::PipelineCacheStatsChanged(stats));
+                        }
+                    }
+
+                    window.request_redraw();
+                }
+            }
             Event::RedrawRequested(_) => {
                 if resized {
                     let size = window.inner_size();
@@ -119,24 +178,20 @@ pub fn main() {
                 scene.draw(&mut encoder, &frame.view);
 
                 // And then iced on top
-                // let mouse_interaction = renderer.backend_mut().draw(
-                //     &mut device,
-                //     &mut encoder,
-                //     &frame.view,
-                //     &viewport,
-                //     state.primitive(),
-                //     &debug.overlay(),
-                // );
+                let mouse_interaction = renderer.backend_mut().draw(
+                    &mut device,
+                    &mut encoder,
+                    &frame.view,
+                    &viewport,
+                    state.primitive(),
+                    &debug.overlay(),
+                );
 
                 // Then we submit the work
                 queue.submit(&[encoder.finish()]);
 
                 // And update the mouse cursor
-                // window.set_cursor_icon(
-                //     iced_winit::conversion::mouse_interaction(
-                //         mouse_interaction,
-                //     ),
-                // );
+                window.set_cursor_icon(conversion::mouse_interaction(mouse_interaction));
             }
             _ => {}
         }
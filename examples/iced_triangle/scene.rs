@@ -1,16 +1,18 @@
+use crate::pipeline_cache::{LayoutId, PipelineCache, PipelineCacheStats, PipelineId, PipelineKey};
 use iced_wgpu::wgpu;
 use iced_winit::Color;
 use shaderc;
 use shaderc::CompilationArtifact;
 use std::fs;
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::Write;
 use wgpu::{BindGroup, BindGroupDescriptor, BindGroupLayout, BindGroupLayoutDescriptor};
 
 pub struct Scene {
     pub background_color: Color,
-    pipeline: wgpu::RenderPipeline,
-    color_pipeline: wgpu::RenderPipeline,
+    pipeline_cache: PipelineCache,
+    pipeline_layout: LayoutId,
+    pipeline: PipelineId,
     bind_group: wgpu::BindGroup,
     use_color: bool,
 }
@@ -34,33 +36,34 @@ impl Scene {
         );
         // compile_my_shader("examples/iced_triangle/shader/my.frag", "examples/iced_triangle/shader/my_frag.spv", shaderc::ShaderKind::Fragment);
         // compile_my_shader("examples/iced_triangle/shader/my.vert", "examples/iced_triangle/shader/my_vert.spv", shaderc::ShaderKind::Vertex);
-        let pipeline = build_pipeline(
-            device,
-            BuildPipelineDescriptor {
-                farg_path: "examples/iced_triangle/shader/my_frag.spv",
-                vert_path: "examples/iced_triangle/shader/my_vert.spv",
-                bind_group_layout: &bind_group_layout,
-            },
-        );
-        let color_pipeline = build_pipeline(
-            device,
-            BuildPipelineDescriptor {
-                farg_path: "examples/iced_triangle/shader/challenge_frag.spv",
-                vert_path: "examples/iced_triangle/shader/challenge_vert.spv",
-                bind_group_layout: &bind_group_layout,
-            },
-        );
+        let mut pipeline_cache = PipelineCache::new();
+        let pipeline_layout = pipeline_cache.create_layout(device, &[&bind_group_layout]);
+        let pipeline = pipeline_cache.get_or_create(device, &pipeline_key(pipeline_layout, false));
         Scene {
             background_color: Color::WHITE,
+            pipeline_cache,
+            pipeline_layout,
             pipeline,
-            color_pipeline,
             bind_group,
             use_color: false,
         }
     }
 
-    pub fn toggle_use_color(&mut self) {
-        self.use_color = !self.use_color;
+    pub fn use_color(&self) -> bool {
+        self.use_color
+    }
+
+    /// The color pipeline is only created the first time it's needed,
+    /// after that switching is a cache lookup.
+    pub fn set_use_color(&mut self, device: &wgpu::Device, use_color: bool) {
+        self.use_color = use_color;
+        self.pipeline = self
+            .pipeline_cache
+            .get_or_create(device, &pipeline_key(self.pipeline_layout, self.use_color));
+    }
+
+    pub fn pipeline_cache_stats(&self) -> PipelineCacheStats {
+        self.pipeline_cache.stats()
     }
 
     pub fn draw(&self, encoder: &mut wgpu::CommandEncoder, target: &wgpu::TextureView) {
@@ -82,86 +85,26 @@ impl Scene {
             }],
             depth_stencil_attachment: None,
         });
-        rpass.set_pipeline(if !self.use_color {
-            &self.pipeline
-        } else {
-            &self.color_pipeline
-        });
+        rpass.set_pipeline(self.pipeline_cache.get(self.pipeline));
         rpass.set_bind_group(0, &self.bind_group, &[]);
         rpass.draw(0..3, 0..1);
     }
 }
 
-struct BuildPipelineDescriptor<'a> {
-    farg_path: &'a str,
-    vert_path: &'a str,
-    bind_group_layout: &'a BindGroupLayout,
-}
-
-fn get_file_as_byte_vec(filename: &String) -> Vec<u8> {
-    let mut f = File::open(&filename).expect("no file found");
-    let metadata = fs::metadata(&filename).expect("unable to read metadata");
-    let mut buffer = vec![0; metadata.len() as usize];
-    f.read(&mut buffer).expect("buffer overflow");
-
-    buffer
-}
-
-fn build_pipeline(
-    device: &wgpu::Device,
-    build_pipeline_descriptor: BuildPipelineDescriptor,
-) -> wgpu::RenderPipeline {
-    // let vs = include_bytes!("shader/vert.spv");
-    // let fs = include_bytes!("shader/frag.spv");
-    // let fs = include_bytes!("shader/my_frag.spv");
-    // let vs = include_bytes!("shader/my_vert.spv");
-
-    let fs = get_file_as_byte_vec(&build_pipeline_descriptor.farg_path.to_string());
-    let vs = get_file_as_byte_vec(&build_pipeline_descriptor.vert_path.to_string());
-
-    let vs_module =
-        device.create_shader_module(&wgpu::read_spirv(std::io::Cursor::new(&vs[..])).unwrap());
-    let fs_module =
-        device.create_shader_module(&wgpu::read_spirv(std::io::Cursor::new(&fs[..])).unwrap());
-
-    let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
-        bind_group_layouts: &[&build_pipeline_descriptor.bind_group_layout],
-    });
-
-    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
-        layout: &pipeline_layout,
-        vertex_stage: wgpu::ProgrammableStageDescriptor {
-            module: &vs_module,
-            entry_point: "main",
-        },
-        fragment_stage: Some(wgpu::ProgrammableStageDescriptor {
-            module: &fs_module,
-            entry_point: "main",
-        }),
-        rasterization_state: Some(wgpu::RasterizationStateDescriptor {
-            front_face: wgpu::FrontFace::Ccw,
-            cull_mode: wgpu::CullMode::None,
-            depth_bias: 0,
-            depth_bias_slope_scale: 0.0,
-            depth_bias_clamp: 0.0,
-        }),
-        primitive_topology: wgpu::PrimitiveTopology::TriangleList,
-        color_states: &[wgpu::ColorStateDescriptor {
-            format: wgpu::TextureFormat::Bgra8UnormSrgb,
-            color_blend: wgpu::BlendDescriptor::REPLACE,
-            alpha_blend: wgpu::BlendDescriptor::REPLACE,
-            write_mask: wgpu::ColorWrite::ALL,
-        }],
-        depth_stencil_state: None,
-        sample_count: 1,
-        sample_mask: !0,
-        alpha_to_coverage_enabled: false,
-        vertex_state: wgpu::VertexStateDescriptor {
-            index_format: wgpu::IndexFormat::Uint16,
-            vertex_buffers: &[],
-        },
-    });
-    pipeline
+fn pipeline_key(layout: LayoutId, use_color: bool) -> PipelineKey {
+    if use_color {
+        PipelineKey::new(
+            layout,
+            "examples/iced_triangle/shader/challenge_vert.spv",
+            "examples/iced_triangle/shader/challenge_frag.spv",
+        )
+    } else {
+        PipelineKey::new(
+            layout,
+            "examples/iced_triangle/shader/my_vert.spv",
+            "examples/iced_triangle/shader/my_frag.spv",
+        )
+    }
 }
 
 fn build_bind_group(device: &wgpu::Device, bind_group_layout: &BindGroupLayout) -> BindGroup {